@@ -1,6 +1,8 @@
 // build.rs
 
-use std::path::PathBuf;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 
 fn main() {
     // set by cargo, build scripts should use this directory for output files
@@ -9,15 +11,130 @@ fn main() {
     // https://doc.rust-lang.org/nightly/cargo/reference/unstable.html#artifact-dependencies
     let kernel = PathBuf::from(std::env::var_os("CARGO_BIN_FILE_KERNEL_kernel").unwrap());
 
+    // pack the initrd, which the bootloader loads next to the kernel: everything in initrd/, with
+    // the sprites in /sprites
+    let initrd_path = out_dir.join("initrd.tar");
+    let mut initrd = Tar::default();
+    initrd.add_dir("initrd", "").unwrap();
+    initrd.add_dir("kernel/assets", "sprites").unwrap();
+    // where the kernel mounts a tmpfs
+    initrd.add_entry("tmp/", b'5', &[]);
+    fs::write(&initrd_path, initrd.finish()).unwrap();
+    println!("cargo:rerun-if-changed=initrd");
+
     // create an UEFI disk image (optional)
     let uefi_path = out_dir.join("uefi.img");
-    bootloader::UefiBoot::new(&kernel).create_disk_image(&uefi_path).unwrap();
+    bootloader::UefiBoot::new(&kernel)
+        .set_ramdisk(&initrd_path)
+        .create_disk_image(&uefi_path)
+        .unwrap();
 
     // create a BIOS disk image
     let bios_path = out_dir.join("bios.img");
-    bootloader::BiosBoot::new(&kernel).create_disk_image(&bios_path).unwrap();
+    bootloader::BiosBoot::new(&kernel)
+        .set_ramdisk(&initrd_path)
+        .create_disk_image(&bios_path)
+        .unwrap();
 
     // pass the disk image paths as env variables to the `main.rs`
     println!("cargo:rustc-env=UEFI_PATH={}", uefi_path.display());
     println!("cargo:rustc-env=BIOS_PATH={}", bios_path.display());
-}
\ No newline at end of file
+
+    // create a FAT32 disk for the game with its sprites on it, which the runner copies the first
+    // time it starts so the game has somewhere to save
+    let fat_path = out_dir.join("game-template.img");
+    create_game_disk(&fat_path, Path::new("kernel/assets")).unwrap();
+    println!("cargo:rustc-env=FAT_PATH={}", fat_path.display());
+    println!("cargo:rerun-if-changed=kernel/assets");
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+/// Size of the game disk; FAT32 needs at least 65525 clusters, so this can't be much smaller.
+const GAME_DISK_SIZE: u64 = 64 * 1024 * 1024;
+
+fn create_game_disk(path: &Path, assets: &Path) -> io::Result<()> {
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    file.set_len(GAME_DISK_SIZE)?;
+    let options = fatfs::FormatVolumeOptions::new()
+        .fat_type(fatfs::FatType::Fat32)
+        .volume_label(*b"SPACEINVADR");
+    fatfs::format_volume(&file, options)?;
+
+    let fs = fatfs::FileSystem::new(file, fatfs::FsOptions::new())?;
+    let sprites = fs.root_dir().create_dir("sprites")?;
+    for entry in fs::read_dir(assets)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let mut sprite = sprites.create_file(&name.to_string_lossy())?;
+        sprite.truncate()?;
+        io::Write::write_all(&mut sprite, &fs::read(entry.path())?)?;
+    }
+    Ok(())
+}
+
+/// Builds a tar archive in the ustar format, which is all the kernel's initrd reader knows.
+#[derive(Default)]
+struct Tar {
+    bytes: Vec<u8>,
+}
+
+impl Tar {
+    /// Adds the directory `source`, and everything in it, as `name` in the archive.
+    fn add_dir(&mut self, source: &str, name: &str) -> io::Result<()> {
+        if !name.is_empty() {
+            self.add_entry(&format!("{name}/"), b'5', &[]);
+        }
+        let mut entries: Vec<_> = fs::read_dir(source)?.collect::<Result<_, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            let path = format!("{source}/{file_name}");
+            let inner = if name.is_empty() {
+                file_name.into_owned()
+            } else {
+                format!("{name}/{file_name}")
+            };
+            if entry.file_type()?.is_dir() {
+                self.add_dir(&path, &inner)?;
+            } else {
+                self.add_entry(&inner, b'0', &fs::read(&path)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn add_entry(&mut self, name: &str, kind: u8, data: &[u8]) {
+        assert!(name.len() < 100, "name too long for the initrd: {name}");
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        let mode = if kind == b'5' { "0000755" } else { "0000644" };
+        header[100..108].copy_from_slice(format!("{mode}\0").as_bytes());
+        header[108..116].copy_from_slice(b"0000000\0");
+        header[116..124].copy_from_slice(b"0000000\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+        header[136..148].copy_from_slice(b"00000000000\0");
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        // the checksum is worked out with its own field as spaces
+        header[148..156].fill(b' ');
+        let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+        header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+
+        self.bytes.extend_from_slice(&header);
+        self.bytes.extend_from_slice(data);
+        self.bytes.resize(self.bytes.len().next_multiple_of(512), 0);
+    }
+
+    /// Ends the archive with two empty blocks.
+    fn finish(mut self) -> Vec<u8> {
+        self.bytes.resize(self.bytes.len() + 1024, 0);
+        self.bytes
+    }
+}
@@ -0,0 +1,369 @@
+//! ATA hard disks on the legacy IDE ports, as QEMU emulates them, using PIO: the CPU moves
+//! every word through the data port. Commands finish with IRQ14 or IRQ15; while interrupts are
+//! enabled the driver sleeps until then, otherwise it polls the status register.
+
+use crate::block::{BlockDevice, BlockError};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+pub const SECTOR_SIZE: usize = 512;
+
+/// One of the two IDE channels, each with a master and a slave drive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Primary,
+    Secondary,
+}
+
+impl Channel {
+    fn io_base(self) -> u16 {
+        match self {
+            Channel::Primary => 0x1F0,
+            Channel::Secondary => 0x170,
+        }
+    }
+
+    fn control_base(self) -> u16 {
+        match self {
+            Channel::Primary => 0x3F6,
+            Channel::Secondary => 0x376,
+        }
+    }
+
+    pub fn irq(self) -> u8 {
+        match self {
+            Channel::Primary => 14,
+            Channel::Secondary => 15,
+        }
+    }
+
+    fn irq_fired(self) -> &'static AtomicBool {
+        &IRQ_FIRED[self as usize]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Position {
+    Master,
+    Slave,
+}
+
+/// Set by the IRQ14/15 handlers, cleared before each command.
+static IRQ_FIRED: [AtomicBool; 2] = [AtomicBool::new(false), AtomicBool::new(false)];
+
+// Registers, as offsets from the channel's I/O base
+const DATA: u16 = 0;
+const ERROR: u16 = 1;
+const SECTOR_COUNT: u16 = 2;
+const LBA_LOW: u16 = 3;
+const LBA_MID: u16 = 4;
+const LBA_HIGH: u16 = 5;
+const DRIVE_SELECT: u16 = 6;
+/// Reading gives the status and acknowledges the interrupt; writing sends a command.
+const STATUS_COMMAND: u16 = 7;
+
+const STATUS_ERROR: u8 = 1 << 0;
+const STATUS_DATA_REQUEST: u8 = 1 << 3;
+const STATUS_DRIVE_FAULT: u8 = 1 << 5;
+const STATUS_BUSY: u8 = 1 << 7;
+/// What an empty channel reads as, with nothing pulling the bus down.
+const FLOATING_BUS: u8 = 0xFF;
+
+const SELECT_LBA: u8 = 0xE0;
+const SELECT_SLAVE: u8 = 1 << 4;
+
+const READ_SECTORS: u8 = 0x20;
+const READ_SECTORS_EXT: u8 = 0x24;
+const WRITE_SECTORS: u8 = 0x30;
+const WRITE_SECTORS_EXT: u8 = 0x34;
+const CACHE_FLUSH: u8 = 0xE7;
+const CACHE_FLUSH_EXT: u8 = 0xEA;
+const IDENTIFY: u8 = 0xEC;
+
+/// Highest sector count one LBA28 command can move; 0 in the register means this many.
+const MAX_SECTORS_PER_COMMAND: u64 = 256;
+const LBA28_LIMIT: u64 = 1 << 28;
+
+/// Status polls before giving up on a drive.
+const POLL_TIMEOUT: usize = 1_000_000;
+/// Wake-ups from `hlt` to wait for a completion interrupt, at least one per timer tick.
+const IRQ_TIMEOUT: usize = 200;
+
+/// An ATA hard disk found by [`AtaDrive::open`].
+pub struct AtaDrive {
+    channel: Channel,
+    position: Position,
+    sectors: u64,
+    lba48: bool,
+    model: String,
+}
+
+impl AtaDrive {
+    /// Looks for a hard disk at the given place with IDENTIFY DEVICE. Returns `None` for empty
+    /// slots and for CD drives (ATAPI), which answer differently.
+    pub fn open(channel: Channel, position: Position) -> Option<Self> {
+        let drive = AtaDrive {
+            channel,
+            position,
+            sectors: 0,
+            lba48: false,
+            model: String::new(),
+        };
+        if drive.read_register(STATUS_COMMAND) == FLOATING_BUS {
+            return None;
+        }
+        drive.select(0);
+        for register in [SECTOR_COUNT, LBA_LOW, LBA_MID, LBA_HIGH] {
+            drive.write_register(register, 0);
+        }
+        drive.write_register(STATUS_COMMAND, IDENTIFY);
+        drive.delay();
+        if drive.read_register(STATUS_COMMAND) == 0 {
+            return None;
+        }
+        drive.wait_not_busy().ok()?;
+        if drive.read_register(LBA_MID) != 0 || drive.read_register(LBA_HIGH) != 0 {
+            return None;
+        }
+        let status = drive.wait_not_busy().ok()?;
+        if status & (STATUS_ERROR | STATUS_DATA_REQUEST) != STATUS_DATA_REQUEST {
+            return None;
+        }
+
+        let mut identify = [0u16; 256];
+        drive.read_words(&mut identify);
+        let lba48 = identify[83] & (1 << 10) != 0;
+        let sectors = if lba48 {
+            identify[100..104]
+                .iter()
+                .rev()
+                .fold(0, |sectors, &word| sectors << 16 | word as u64)
+        } else {
+            (identify[61] as u64) << 16 | identify[60] as u64
+        };
+        // The model name is stored with the two bytes of each word swapped
+        let model = identify[27..47]
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .map(|byte| byte as char)
+            .collect::<String>();
+
+        IRQ_FIRED[channel as usize].store(false, Ordering::SeqCst);
+        crate::interrupts::unmask_irq(channel.irq());
+        Some(AtaDrive {
+            sectors,
+            lba48,
+            model: String::from(model.trim()),
+            ..drive
+        })
+    }
+
+    pub fn channel(&self) -> Channel {
+        self.channel
+    }
+
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// The model name the drive reports, e.g. "QEMU HARDDISK".
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn read_register(&self, register: u16) -> u8 {
+        unsafe { Port::new(self.channel.io_base() + register).read() }
+    }
+
+    fn write_register(&self, register: u16, value: u8) {
+        unsafe { Port::new(self.channel.io_base() + register).write(value) };
+    }
+
+    /// Reads the status without acknowledging an interrupt.
+    fn alternate_status(&self) -> u8 {
+        unsafe { Port::new(self.channel.control_base()).read() }
+    }
+
+    /// Gives the drive the 400 ns it needs to put a valid status up after a command.
+    fn delay(&self) {
+        for _ in 0..4 {
+            self.alternate_status();
+        }
+    }
+
+    /// Selects this drive on its channel, with the top four bits of an LBA28 address.
+    fn select(&self, lba_top: u8) {
+        let slave = match self.position {
+            Position::Master => 0,
+            Position::Slave => SELECT_SLAVE,
+        };
+        self.write_register(DRIVE_SELECT, SELECT_LBA | slave | (lba_top & 0x0F));
+        self.delay();
+    }
+
+    fn wait_not_busy(&self) -> Result<u8, BlockError> {
+        for _ in 0..POLL_TIMEOUT {
+            let status = self.alternate_status();
+            if status & STATUS_BUSY == 0 {
+                return Ok(status);
+            }
+        }
+        Err(BlockError::Timeout)
+    }
+
+    /// Waits for the drive to finish what it was doing: by sleeping until its interrupt when
+    /// `expect_irq` is set and interrupts are on, then by polling. Returns the status, having
+    /// acknowledged the interrupt, or the drive's error code.
+    fn wait(&self, expect_irq: bool) -> Result<u8, BlockError> {
+        if expect_irq && interrupts::are_enabled() {
+            let mut wakeups = 0;
+            loop {
+                // Interrupts stay off between checking the flag and halting, so the completion
+                // can't slip in between and leave us asleep until the next timer tick
+                interrupts::disable();
+                if self.channel.irq_fired().swap(false, Ordering::SeqCst) {
+                    interrupts::enable();
+                    break;
+                }
+                if wakeups == IRQ_TIMEOUT {
+                    interrupts::enable();
+                    return Err(BlockError::Timeout);
+                }
+                interrupts::enable_and_hlt();
+                wakeups += 1;
+            }
+        }
+        self.wait_not_busy()?;
+        let status = self.read_register(STATUS_COMMAND);
+        if status & (STATUS_ERROR | STATUS_DRIVE_FAULT) != 0 {
+            return Err(BlockError::Device(self.read_register(ERROR)));
+        }
+        Ok(status)
+    }
+
+    fn wait_for_data(&self, expect_irq: bool) -> Result<(), BlockError> {
+        if self.wait(expect_irq)? & STATUS_DATA_REQUEST == 0 {
+            return Err(BlockError::Device(self.read_register(ERROR)));
+        }
+        Ok(())
+    }
+
+    fn read_words(&self, words: &mut [u16]) {
+        let mut data = Port::<u16>::new(self.channel.io_base() + DATA);
+        for word in words {
+            *word = unsafe { data.read() };
+        }
+    }
+
+    /// Sends a read or write command for up to 256 sectors, picking LBA48 when the address
+    /// needs it.
+    fn command(&self, lba: u64, count: u64, lba28: u8, lba48: u8) {
+        self.channel.irq_fired().store(false, Ordering::SeqCst);
+        let count = (count % MAX_SECTORS_PER_COMMAND) as u8;
+        if lba + MAX_SECTORS_PER_COMMAND <= LBA28_LIMIT || !self.lba48 {
+            self.select((lba >> 24) as u8);
+            self.write_register(SECTOR_COUNT, count);
+            self.write_register(LBA_LOW, lba as u8);
+            self.write_register(LBA_MID, (lba >> 8) as u8);
+            self.write_register(LBA_HIGH, (lba >> 16) as u8);
+            self.write_register(STATUS_COMMAND, lba28);
+        } else {
+            self.select(0);
+            // High bytes first, then low bytes, through the same registers
+            self.write_register(SECTOR_COUNT, 0);
+            self.write_register(LBA_LOW, (lba >> 24) as u8);
+            self.write_register(LBA_MID, (lba >> 32) as u8);
+            self.write_register(LBA_HIGH, (lba >> 40) as u8);
+            self.write_register(SECTOR_COUNT, count);
+            self.write_register(LBA_LOW, lba as u8);
+            self.write_register(LBA_MID, (lba >> 8) as u8);
+            self.write_register(LBA_HIGH, (lba >> 16) as u8);
+            self.write_register(STATUS_COMMAND, lba48);
+        }
+        self.delay();
+    }
+}
+
+impl BlockDevice for AtaDrive {
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_sectors(&mut self, lba: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        self.sectors_for(lba, buffer.len())?;
+        let mut words = [0u16; SECTOR_SIZE / 2];
+        let chunk_size = MAX_SECTORS_PER_COMMAND as usize * SECTOR_SIZE;
+        for (index, chunk) in buffer.chunks_mut(chunk_size).enumerate() {
+            let start = lba + (index * chunk_size / SECTOR_SIZE) as u64;
+            let count = (chunk.len() / SECTOR_SIZE) as u64;
+            self.command(start, count, READ_SECTORS, READ_SECTORS_EXT);
+            for sector in chunk.chunks_exact_mut(SECTOR_SIZE) {
+                self.wait_for_data(true)?;
+                self.read_words(&mut words);
+                for (bytes, word) in sector.chunks_exact_mut(2).zip(words) {
+                    bytes.copy_from_slice(&word.to_le_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&mut self, lba: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        self.sectors_for(lba, buffer.len())?;
+        let mut data = Port::<u16>::new(self.channel.io_base() + DATA);
+        let chunk_size = MAX_SECTORS_PER_COMMAND as usize * SECTOR_SIZE;
+        for (index, chunk) in buffer.chunks(chunk_size).enumerate() {
+            let start = lba + (index * chunk_size / SECTOR_SIZE) as u64;
+            let count = (chunk.len() / SECTOR_SIZE) as u64;
+            self.command(start, count, WRITE_SECTORS, WRITE_SECTORS_EXT);
+            // The drive asks for the first sector straight away, and interrupts after each one
+            // it has taken
+            for (sector_index, sector) in chunk.chunks_exact(SECTOR_SIZE).enumerate() {
+                self.wait_for_data(sector_index > 0)?;
+                for bytes in sector.chunks_exact(2) {
+                    unsafe { data.write(u16::from_le_bytes([bytes[0], bytes[1]])) };
+                }
+            }
+            self.wait(true)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        self.channel.irq_fired().store(false, Ordering::SeqCst);
+        self.select(0);
+        let command = if self.lba48 {
+            CACHE_FLUSH_EXT
+        } else {
+            CACHE_FLUSH
+        };
+        self.write_register(STATUS_COMMAND, command);
+        self.delay();
+        self.wait(true).map(|_| ())
+    }
+}
+
+/// Every hard disk on the two IDE channels.
+pub fn drives() -> Vec<AtaDrive> {
+    [Channel::Primary, Channel::Secondary]
+        .into_iter()
+        .flat_map(|channel| {
+            [Position::Master, Position::Slave]
+                .into_iter()
+                .filter_map(move |position| AtaDrive::open(channel, position))
+        })
+        .collect()
+}
+
+/// Called by the IRQ14/15 handlers. Reading the status acknowledges the interrupt.
+pub(crate) fn handle_interrupt(channel: Channel) {
+    unsafe { Port::<u8>::new(channel.io_base() + STATUS_COMMAND).read() };
+    channel.irq_fired().store(true, Ordering::SeqCst);
+}
@@ -0,0 +1,138 @@
+use crate::sprite::Sprite;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+/// The shape of a barrier, `#` for solid: the arcade's bunker with an arch cut out of the bottom
+/// for the player to hide under.
+const SHAPE: [&str; 16] = [
+    "....##############....",
+    "...################...",
+    "..##################..",
+    ".####################.",
+    "######################",
+    "######################",
+    "######################",
+    "######################",
+    "######################",
+    "######################",
+    "######################",
+    "######################",
+    "#######........#######",
+    "######..........######",
+    "#####............#####",
+    "#####............#####",
+];
+
+/// The hole a shot blows in a barrier, centred on the pixel it hit. Ragged, so a barrier wears
+/// away unevenly.
+const BLAST: [&str; 8] = [
+    "#..#...#", "..#..#..", ".#####.#", "#######.", ".#######", "#.#####.", "..#..#..", "#...#..#",
+];
+
+pub const WIDTH: usize = 22;
+pub const HEIGHT: usize = 16;
+/// Each barrier pixel is a square of this many screen pixels.
+pub const SCALE: usize = 4;
+
+/// A barrier the player can hide behind. It is kept as one bit per pixel, so shots from either
+/// side eat it away a bit at a time instead of knocking it out whole.
+pub struct Barrier {
+    pub x: usize,
+    pub y: usize,
+    pub sprite: Sprite,
+}
+
+impl Barrier {
+    pub fn new(x: usize, y: usize, color: (u8, u8, u8)) -> Self {
+        let bits: Vec<bool> = SHAPE
+            .iter()
+            .flat_map(|row| row.bytes().map(|b| b == b'#'))
+            .collect();
+        Barrier {
+            x,
+            y,
+            sprite: Sprite::bitmap(WIDTH, HEIGHT, SCALE, color, bits),
+        }
+    }
+
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn z(mut self, z: i32) -> Self {
+        self.sprite = self.sprite.z(z);
+        self
+    }
+
+    /// Sends a shot `width` pixels wide at `x` through the barrier, from screen row `from` to
+    /// row `to`, which is above `from` for the player's shots and below it for the enemies'.
+    /// If it runs into a solid pixel on the way, a hole is blown around that pixel and true is
+    /// returned.
+    pub fn shoot(&mut self, x: usize, width: usize, from: usize, to: usize) -> bool {
+        let Some(columns) = span(self.x, WIDTH, x, width) else {
+            return false;
+        };
+        let Some(mut rows) = span(self.y, HEIGHT, from.min(to), from.abs_diff(to) + 1) else {
+            return false;
+        };
+        let bits = self.sprite.bits();
+        let hit = |row: usize| columns.clone().find(|&column| bits[row * WIDTH + column]);
+        let impact = if to < from {
+            // going up, the lowest row is met first
+            rows.rev()
+                .find_map(|row| hit(row).map(|column| (column, row)))
+        } else {
+            rows.find_map(|row| hit(row).map(|column| (column, row)))
+        };
+        let Some((column, row)) = impact else {
+            return false;
+        };
+        self.blast(column, row);
+        true
+    }
+
+    /// Clears every pixel under a rectangle on the screen, for enemies that march into it.
+    pub fn clear_rect(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        let (Some(columns), Some(rows)) = (
+            span(self.x, WIDTH, x, width),
+            span(self.y, HEIGHT, y, height),
+        ) else {
+            return;
+        };
+        let bits = self.sprite.bits();
+        let any_set = rows
+            .clone()
+            .any(|row| columns.clone().any(|column| bits[row * WIDTH + column]));
+        // only touch the bits if there is something to clear, so the barrier isn't redrawn
+        if any_set {
+            let bits = self.sprite.bits_mut();
+            for row in rows {
+                bits[row * WIDTH + columns.start..row * WIDTH + columns.end].fill(false);
+            }
+        }
+    }
+
+    /// Clears the pixels under [`BLAST`] centred on barrier pixel (`column`, `row`).
+    fn blast(&mut self, column: usize, row: usize) {
+        let bits = self.sprite.bits_mut();
+        for (dy, line) in BLAST.iter().enumerate() {
+            for (dx, b) in line.bytes().enumerate() {
+                let x = (column + dx).checked_sub(BLAST[0].len() / 2);
+                let y = (row + dy).checked_sub(BLAST.len() / 2);
+                if let (b'#', Some(x), Some(y)) = (b, x, y) {
+                    if x < WIDTH && y < HEIGHT {
+                        bits[y * WIDTH + x] = false;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The barrier pixels, along one axis, covered by screen pixels `start..start + len`, given that
+/// the barrier starts at screen pixel `origin` and is `count` pixels long.
+fn span(origin: usize, count: usize, start: usize, len: usize) -> Option<Range<usize>> {
+    let end = (start + len)
+        .checked_sub(origin)?
+        .div_ceil(SCALE)
+        .min(count);
+    let start = start.saturating_sub(origin) / SCALE;
+    (start < end).then_some(start..end)
+}
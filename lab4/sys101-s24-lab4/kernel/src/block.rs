@@ -0,0 +1,78 @@
+//! Disks and anything else that stores data in fixed-size sectors.
+
+use alloc::boxed::Box;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// The sectors asked for go past the end of the device.
+    OutOfRange,
+    /// The buffer isn't a whole number of sectors.
+    BadLength,
+    /// The device didn't answer in time, or stopped answering earlier and was given up on.
+    Timeout,
+    /// The device reported an error, with its own error code.
+    Device(u8),
+}
+
+/// A device read and written a whole number of sectors at a time, addressed by logical block
+/// address (LBA): sector 0 is the first one, and so on up to [`sector_count`](Self::sector_count).
+pub trait BlockDevice: Send {
+    /// Size of one sector in bytes, usually 512.
+    fn sector_size(&self) -> usize;
+
+    fn sector_count(&self) -> u64;
+
+    /// Reads sectors starting at `lba` into `buffer`, whose length must be a multiple of the
+    /// sector size.
+    fn read_sectors(&mut self, lba: u64, buffer: &mut [u8]) -> Result<(), BlockError>;
+
+    /// Writes `buffer`, whose length must be a multiple of the sector size, to the sectors
+    /// starting at `lba`.
+    fn write_sectors(&mut self, lba: u64, buffer: &[u8]) -> Result<(), BlockError>;
+
+    /// Makes sure everything written so far is stored, not just sitting in a cache.
+    fn flush(&mut self) -> Result<(), BlockError> {
+        Ok(())
+    }
+
+    /// Size of the whole device in bytes.
+    fn capacity(&self) -> u64 {
+        self.sector_count() * self.sector_size() as u64
+    }
+
+    /// How many sectors `len` bytes starting at `lba` cover, checking they are whole sectors
+    /// within the device.
+    fn sectors_for(&self, lba: u64, len: usize) -> Result<u64, BlockError> {
+        if !len.is_multiple_of(self.sector_size()) {
+            return Err(BlockError::BadLength);
+        }
+        let count = (len / self.sector_size()) as u64;
+        match lba.checked_add(count) {
+            Some(end) if end <= self.sector_count() => Ok(count),
+            _ => Err(BlockError::OutOfRange),
+        }
+    }
+}
+
+/// So a device can be picked at runtime, as a `Box<dyn BlockDevice>`.
+impl<D: BlockDevice + ?Sized> BlockDevice for Box<D> {
+    fn sector_size(&self) -> usize {
+        (**self).sector_size()
+    }
+
+    fn sector_count(&self) -> u64 {
+        (**self).sector_count()
+    }
+
+    fn read_sectors(&mut self, lba: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        (**self).read_sectors(lba, buffer)
+    }
+
+    fn write_sectors(&mut self, lba: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        (**self).write_sectors(lba, buffer)
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        (**self).flush()
+    }
+}
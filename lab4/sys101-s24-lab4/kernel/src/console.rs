@@ -0,0 +1,354 @@
+use crate::screen::{screenwriter, Rect, TextStyle, CHAR_WIDTH, LINE_HEIGHT};
+use core::fmt;
+use noto_sans_mono_bitmap::FontWeight;
+
+/// Number of columns between tab stops.
+const TAB_WIDTH: usize = 8;
+
+/// Timer ticks between two phases of the blinking cursor.
+const CURSOR_BLINK_TICKS: usize = 9;
+
+/// Most parameters a CSI sequence can carry; extra ones are ignored.
+const MAX_PARAMS: usize = 8;
+
+const DEFAULT_FOREGROUND: (u8, u8, u8) = (0x3f, 0xff, 0x7f);
+const DEFAULT_BACKGROUND: (u8, u8, u8) = (0, 0, 0);
+
+/// The 8 normal and 8 bright colors selected by SGR sequences.
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xaa, 0x00, 0x00),
+    (0x00, 0xaa, 0x00),
+    (0xaa, 0x55, 0x00),
+    (0x00, 0x00, 0xaa),
+    (0xaa, 0x00, 0xaa),
+    (0x00, 0xaa, 0xaa),
+    (0xaa, 0xaa, 0xaa),
+    (0x55, 0x55, 0x55),
+    (0xff, 0x55, 0x55),
+    (0x55, 0xff, 0x55),
+    (0xff, 0xff, 0x55),
+    (0x55, 0x55, 0xff),
+    (0xff, 0x55, 0xff),
+    (0x55, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+
+/// Where the console is inside an escape sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EscapeState {
+    Normal,
+    /// Just saw ESC.
+    Escape,
+    /// Inside `ESC [`, collecting parameters until the final byte.
+    Csi,
+}
+
+/// A scrolling text terminal drawn into a rectangle of the screen. Understands `\n`, `\r`,
+/// backspace, tab and the common ANSI CSI sequences: cursor movement (`A`, `B`, `C`, `D`, `H`,
+/// `f`), erasing (`J`, `K`), colors (`m`) and showing/hiding the cursor (`?25h`, `?25l`).
+pub struct Console {
+    area: Rect,
+    columns: usize,
+    rows: usize,
+    column: usize,
+    row: usize,
+    foreground: (u8, u8, u8),
+    background: (u8, u8, u8),
+    default_foreground: (u8, u8, u8),
+    default_background: (u8, u8, u8),
+    bold: bool,
+    state: EscapeState,
+    params: [usize; MAX_PARAMS],
+    param_count: usize,
+    private: bool,
+    cursor_enabled: bool,
+    cursor_drawn: bool,
+    blink_ticks: usize,
+}
+
+impl Console {
+    pub fn new(area: Rect) -> Self {
+        let mut console = Console {
+            area,
+            columns: (area.width / CHAR_WIDTH).max(1),
+            rows: (area.height / LINE_HEIGHT).max(1),
+            column: 0,
+            row: 0,
+            foreground: DEFAULT_FOREGROUND,
+            background: DEFAULT_BACKGROUND,
+            default_foreground: DEFAULT_FOREGROUND,
+            default_background: DEFAULT_BACKGROUND,
+            bold: false,
+            state: EscapeState::Normal,
+            params: [0; MAX_PARAMS],
+            param_count: 0,
+            private: false,
+            cursor_enabled: true,
+            cursor_drawn: false,
+            blink_ticks: 0,
+        };
+        console.clear();
+        console
+    }
+
+    /// Erases the whole console and moves the cursor to the top left corner.
+    pub fn clear(&mut self) {
+        self.hide_cursor();
+        let area = self.area;
+        screenwriter().fill_rect(area.x, area.y, area.width, area.height, self.background);
+        self.column = 0;
+        self.row = 0;
+    }
+
+    /// Sets the colors for the text written from now on. They also become the colors an SGR reset
+    /// (`ESC [0m`, `39` or `49`) goes back to.
+    pub fn set_colors(&mut self, foreground: (u8, u8, u8), background: (u8, u8, u8)) {
+        self.foreground = foreground;
+        self.background = background;
+        self.default_foreground = foreground;
+        self.default_background = background;
+    }
+
+    /// Shows or hides the blinking cursor, like `ESC [?25h` and `ESC [?25l`.
+    pub fn set_cursor_enabled(&mut self, enabled: bool) {
+        self.cursor_enabled = enabled;
+        if !enabled {
+            self.hide_cursor();
+        }
+    }
+
+    /// Advances the cursor blink. Call this from the timer handler.
+    pub fn tick(&mut self) {
+        self.blink_ticks += 1;
+        if self.blink_ticks >= CURSOR_BLINK_TICKS {
+            self.blink_ticks = 0;
+            if self.cursor_drawn {
+                self.hide_cursor();
+            } else if self.cursor_enabled {
+                self.toggle_cursor();
+            }
+        }
+    }
+
+    fn write_char(&mut self, c: char) {
+        match self.state {
+            EscapeState::Normal => self.write_plain(c),
+            EscapeState::Escape => {
+                if c == '[' {
+                    self.params = [0; MAX_PARAMS];
+                    self.param_count = 0;
+                    self.private = false;
+                    self.state = EscapeState::Csi;
+                } else {
+                    self.state = EscapeState::Normal;
+                }
+            }
+            EscapeState::Csi => self.collect_csi(c),
+        }
+    }
+
+    fn write_plain(&mut self, c: char) {
+        match c {
+            '\n' => self.newline(),
+            '\r' => self.column = 0,
+            '\x08' => {
+                if self.column > 0 {
+                    self.column -= 1;
+                    self.erase_cells(self.row, self.column, self.column + 1);
+                }
+            }
+            '\t' => {
+                let next_stop = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.column = next_stop.min(self.columns - 1);
+            }
+            '\x1b' => self.state = EscapeState::Escape,
+            c if c.is_control() => {}
+            c => {
+                if self.column >= self.columns {
+                    self.newline();
+                }
+                let (x, y) = self.cell_position(self.row, self.column);
+                let weight = if self.bold {
+                    FontWeight::Bold
+                } else {
+                    FontWeight::Regular
+                };
+                let style = TextStyle::new(self.foreground)
+                    .background(Some(self.background))
+                    .weight(weight);
+                screenwriter().draw_char(x, y, c, &style);
+                self.column += 1;
+            }
+        }
+    }
+
+    fn collect_csi(&mut self, c: char) {
+        match c {
+            '0'..='9' => {
+                if self.param_count == 0 {
+                    self.param_count = 1;
+                }
+                let param = &mut self.params[self.param_count - 1];
+                *param = param
+                    .saturating_mul(10)
+                    .saturating_add(c as usize - '0' as usize);
+            }
+            ';' => {
+                if self.param_count == 0 {
+                    self.param_count = 1;
+                }
+                if self.param_count < MAX_PARAMS {
+                    self.param_count += 1;
+                }
+            }
+            '?' => self.private = true,
+            '\x40'..='\x7e' => {
+                self.state = EscapeState::Normal;
+                self.execute_csi(c);
+            }
+            _ => self.state = EscapeState::Normal,
+        }
+    }
+
+    /// Parameter `index` of the current sequence, with `default` for missing or zero values.
+    fn param(&self, index: usize, default: usize) -> usize {
+        if index < self.param_count && self.params[index] != 0 {
+            self.params[index]
+        } else {
+            default
+        }
+    }
+
+    fn execute_csi(&mut self, command: char) {
+        match command {
+            'A' => self.row = self.row.saturating_sub(self.param(0, 1)),
+            'B' => self.row = (self.row + self.param(0, 1)).min(self.rows - 1),
+            'C' => self.column = (self.column + self.param(0, 1)).min(self.columns - 1),
+            'D' => self.column = self.column.saturating_sub(self.param(0, 1)),
+            'H' | 'f' => {
+                self.row = (self.param(0, 1) - 1).min(self.rows - 1);
+                self.column = (self.param(1, 1) - 1).min(self.columns - 1);
+            }
+            'J' => match self.params[0] {
+                0 => {
+                    self.erase_cells(self.row, self.column, self.columns);
+                    self.erase_rows(self.row + 1, self.rows);
+                }
+                1 => {
+                    self.erase_rows(0, self.row);
+                    self.erase_cells(self.row, 0, self.column + 1);
+                }
+                _ => self.erase_rows(0, self.rows),
+            },
+            'K' => match self.params[0] {
+                0 => self.erase_cells(self.row, self.column, self.columns),
+                1 => self.erase_cells(self.row, 0, self.column + 1),
+                _ => self.erase_cells(self.row, 0, self.columns),
+            },
+            'm' => self.select_graphic_rendition(),
+            'h' | 'l' if self.private && self.params[0] == 25 => {
+                self.cursor_enabled = command == 'h';
+            }
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self) {
+        for index in 0..self.param_count.max(1) {
+            match self.params[index] {
+                0 => {
+                    self.foreground = self.default_foreground;
+                    self.background = self.default_background;
+                    self.bold = false;
+                }
+                1 => self.bold = true,
+                22 => self.bold = false,
+                code @ 30..=37 => {
+                    let bright = if self.bold { 8 } else { 0 };
+                    self.foreground = PALETTE[code - 30 + bright];
+                }
+                39 => self.foreground = self.default_foreground,
+                code @ 40..=47 => self.background = PALETTE[code - 40],
+                49 => self.background = self.default_background,
+                code @ 90..=97 => self.foreground = PALETTE[code - 90 + 8],
+                code @ 100..=107 => self.background = PALETTE[code - 100 + 8],
+                _ => {}
+            }
+        }
+    }
+
+    fn newline(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            let area = Rect::new(
+                self.area.x,
+                self.area.y,
+                self.area.width,
+                self.rows * LINE_HEIGHT,
+            );
+            screenwriter().scroll_up(area, LINE_HEIGHT, self.background);
+        }
+    }
+
+    /// Top left pixel of a character cell.
+    fn cell_position(&self, row: usize, column: usize) -> (usize, usize) {
+        (
+            self.area.x + column * CHAR_WIDTH,
+            self.area.y + row * LINE_HEIGHT,
+        )
+    }
+
+    /// Erases the columns `from..to` of one row.
+    fn erase_cells(&mut self, row: usize, from: usize, to: usize) {
+        let to = to.min(self.columns);
+        if from < to {
+            let (x, y) = self.cell_position(row, from);
+            screenwriter().fill_rect(x, y, (to - from) * CHAR_WIDTH, LINE_HEIGHT, self.background);
+        }
+    }
+
+    /// Erases the whole rows `from..to`.
+    fn erase_rows(&mut self, from: usize, to: usize) {
+        let to = to.min(self.rows);
+        if from < to {
+            let (x, y) = self.cell_position(from, 0);
+            screenwriter().fill_rect(
+                x,
+                y,
+                self.columns * CHAR_WIDTH,
+                (to - from) * LINE_HEIGHT,
+                self.background,
+            );
+        }
+    }
+
+    fn hide_cursor(&mut self) {
+        if self.cursor_drawn {
+            self.toggle_cursor();
+        }
+    }
+
+    /// Inverts the cell under the cursor, drawing or removing the block cursor.
+    fn toggle_cursor(&mut self) {
+        let (x, y) = self.cell_position(self.row, self.column.min(self.columns - 1));
+        screenwriter().invert_rect(x, y, CHAR_WIDTH, LINE_HEIGHT);
+        self.cursor_drawn = !self.cursor_drawn;
+    }
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.hide_cursor();
+        for c in s.chars() {
+            self.write_char(c);
+        }
+        if self.cursor_enabled {
+            self.toggle_cursor();
+            self.blink_ticks = 0;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+use crate::image::Image;
+use crate::screen::screenwriter;
+use crate::sprite::Sprite;
+use kernel::{MouseEvent, RacyCell};
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref ARROW: [Image; 1] = [crate::disk::image(
+        "/sprites/cursor.qoi",
+        include_bytes!("../assets/cursor.qoi")
+    )];
+}
+
+/// The mouse pointer. It stays hidden until the mouse first moves, so there is no stray arrow
+/// on machines without a mouse.
+struct Cursor {
+    sprite: Sprite,
+    x: usize,
+    y: usize,
+    enabled: bool,
+}
+
+static CURSOR: RacyCell<Option<Cursor>> = RacyCell::new(None);
+
+/// Reads the arrow from the disk now, rather than in the first mouse or keyboard interrupt.
+pub fn init() {
+    lazy_static::initialize(&ARROW);
+}
+
+fn cursor() -> &'static mut Cursor {
+    let cursor = unsafe { CURSOR.get_mut() };
+    cursor.get_or_insert_with(|| {
+        let writer = screenwriter();
+        Cursor {
+            sprite: Sprite::animated(&*ARROW, 1, (0xff, 0xff, 0xff)),
+            x: writer.width() / 2,
+            y: writer.height() / 2,
+            enabled: false,
+        }
+    })
+}
+
+/// Moves the pointer by a mouse event, keeping its tip on screen, and redraws it.
+pub fn update(event: MouseEvent) {
+    let writer = screenwriter();
+    let cursor = cursor();
+    cursor.x = offset(cursor.x, event.dx, writer.width());
+    cursor.y = offset(cursor.y, event.dy, writer.height());
+    cursor.enabled = true;
+    show();
+}
+
+/// Takes the pointer off the screen, restoring what was under it. Call this before drawing
+/// anything that might end up under the pointer, and [`show`] afterwards.
+pub fn hide() {
+    cursor().sprite.erase(screenwriter());
+}
+
+/// Draws the pointer on top of everything else, erasing it from where it was.
+pub fn show() {
+    let cursor = cursor();
+    if cursor.enabled {
+        cursor.sprite.move_to(cursor.x as isize, cursor.y as isize);
+        cursor.sprite.draw(screenwriter());
+    }
+}
+
+fn offset(position: usize, delta: i16, limit: usize) -> usize {
+    (position as isize + delta as isize).clamp(0, limit as isize - 1) as usize
+}
@@ -0,0 +1,63 @@
+use crate::image::Image;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use kernel::ata::{self, Channel, Position};
+use kernel::block::BlockDevice;
+use kernel::fat32::{Fat32, FatError};
+use kernel::vfs;
+use kernel::virtio_blk::VirtioBlk;
+use spin::Mutex;
+
+/// The game's FAT32 disk, with sprites to use instead of the built-in ones, and room to save.
+static DISK: Mutex<Option<Fat32<Box<dyn BlockDevice>>>> = Mutex::new(None);
+/// Whether [`DISK`] holds a disk, for interrupt handlers, which mustn't wait for its lock while
+/// the main loop is saving.
+static MOUNTED: AtomicBool = AtomicBool::new(false);
+
+/// Looks for the game's disk: the virtio disk the runner attaches, or else an IDE data disk.
+/// The primary master is left alone, as it is the boot disk. Returns a description of the disk
+/// found.
+pub fn mount() -> Option<&'static str> {
+    let virtio = VirtioBlk::open().map(|disk| (Box::new(disk) as Box<dyn BlockDevice>, "virtio"));
+    let ide = ata::drives()
+        .into_iter()
+        .find(|drive| (drive.channel(), drive.position()) != (Channel::Primary, Position::Master))
+        .map(|drive| (Box::new(drive) as Box<dyn BlockDevice>, "IDE"));
+    for (device, kind) in virtio.into_iter().chain(ide) {
+        if let Ok(fs) = Fat32::mount(device) {
+            *DISK.lock() = Some(fs);
+            MOUNTED.store(true, Ordering::SeqCst);
+            return Some(kind);
+        }
+    }
+    None
+}
+
+/// Whether a game disk was found.
+pub fn mounted() -> bool {
+    MOUNTED.load(Ordering::SeqCst)
+}
+
+/// Reads a whole file from the game's disk, if there is a disk and the file is on it.
+pub fn read(path: &str) -> Option<Vec<u8>> {
+    DISK.lock().as_mut()?.read_file(path).ok()
+}
+
+/// Replaces a file on the game's disk with `data`, creating it if needed.
+pub fn write(path: &str, data: &[u8]) -> Result<(), FatError> {
+    let mut disk = DISK.lock();
+    let fs = disk.as_mut().ok_or(FatError::NotFound)?;
+    let mut file = fs.create(path)?;
+    file.write(data)?;
+    file.flush()
+}
+
+/// Loads an image from the game's disk, or else from the initrd, falling back to the one built
+/// into the kernel when neither has it or it can't be decoded.
+pub fn image(path: &str, built_in: &[u8]) -> Image {
+    read(path)
+        .and_then(|data| Image::decode(&data).ok())
+        .or_else(|| Image::decode(&vfs::read_file(path).ok()?).ok())
+        .unwrap_or_else(|| Image::decode(built_in).unwrap())
+}
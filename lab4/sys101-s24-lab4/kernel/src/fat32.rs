@@ -0,0 +1,986 @@
+//! FAT32 filesystems on a [`BlockDevice`], either filling the whole device or in the first FAT32
+//! partition of an MBR partition table. Files and directories are found by path, like
+//! `/sprites/enemy_a.qoi`, matching names without regard to ASCII case, and long file names are
+//! read and written alongside the 8.3 short names.
+
+use crate::block::{BlockDevice, BlockError};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const SECTOR_SIZE: usize = 512;
+const ENTRY_SIZE: usize = 32;
+
+// Boot sector fields
+const BYTES_PER_SECTOR: usize = 0x0B;
+const SECTORS_PER_CLUSTER: usize = 0x0D;
+const RESERVED_SECTORS: usize = 0x0E;
+const FAT_COUNT: usize = 0x10;
+const ROOT_ENTRY_COUNT: usize = 0x11;
+const TOTAL_SECTORS_16: usize = 0x13;
+const FAT_SIZE_16: usize = 0x16;
+const TOTAL_SECTORS_32: usize = 0x20;
+const FAT_SIZE_32: usize = 0x24;
+const ROOT_CLUSTER: usize = 0x2C;
+const FS_INFO_SECTOR: usize = 0x30;
+const SIGNATURE: usize = 0x1FE;
+
+const PARTITION_TABLE: usize = 0x1BE;
+const PARTITION_FAT32_CHS: u8 = 0x0B;
+const PARTITION_FAT32_LBA: u8 = 0x0C;
+/// Free cluster count in the FSInfo sector.
+const FS_INFO_FREE_COUNT: usize = 488;
+
+// Directory entry fields
+const ATTRIBUTES: usize = 11;
+const CASE_FLAGS: usize = 12;
+const CLUSTER_HIGH: usize = 20;
+const CLUSTER_LOW: usize = 26;
+const FILE_SIZE: usize = 28;
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0F;
+/// Set in the sequence number of the last long name entry, which comes first on disk.
+const LAST_LONG_ENTRY: u8 = 0x40;
+const LONG_NAME_CHARS: usize = 13;
+/// Where the 13 UCS-2 characters of a long name entry are.
+const LONG_NAME_OFFSETS: [usize; LONG_NAME_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const MAX_NAME_LENGTH: usize = 255;
+
+const CASE_LOWER_BASE: u8 = 0x08;
+const CASE_LOWER_EXTENSION: u8 = 0x10;
+
+const END_OF_DIRECTORY: u8 = 0x00;
+const DELETED: u8 = 0xE5;
+
+const CLUSTER_MASK: u32 = 0x0FFF_FFFF;
+const FREE_CLUSTER: u32 = 0;
+const END_OF_CHAIN: u32 = 0x0FFF_FFF8;
+const BAD_CLUSTER: u32 = 0x0FFF_FFF7;
+const FIRST_CLUSTER: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatError {
+    Io(BlockError),
+    /// Neither the device nor its first partition holds a FAT32 filesystem.
+    NotFat32,
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    /// Directories have to be empty to be removed.
+    DirectoryNotEmpty,
+    /// The name is empty, too long, or has characters FAT doesn't allow.
+    InvalidName,
+    DiskFull,
+    /// A cluster chain points somewhere it shouldn't.
+    Corrupt,
+}
+
+impl From<BlockError> for FatError {
+    fn from(error: BlockError) -> Self {
+        FatError::Io(error)
+    }
+}
+
+/// Where to move a [`File`]'s position to, as in `std::io::SeekFrom`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+/// A file or directory, as listed by [`Fat32::read_dir`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// The long name if there is one, the short name otherwise.
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u32,
+    short_name: [u8; 11],
+    first_cluster: u32,
+    /// The entries making it up in its directory: long name entries first, short entry last.
+    first_slot: usize,
+    slot: usize,
+}
+
+impl DirEntry {
+    /// The 8.3 name, like `ENEMY_~1.QOI`.
+    pub fn short_name(&self) -> String {
+        format_short_name(&self.short_name, 0)
+    }
+}
+
+/// What a path leads to: the root directory, or an entry in some directory.
+struct Node {
+    first_cluster: u32,
+    size: u32,
+    is_dir: bool,
+    /// The first cluster of the directory holding it, and its entry there. `None` for the root.
+    location: Option<(u32, DirEntry)>,
+}
+
+pub struct Fat32<D: BlockDevice> {
+    device: D,
+    sectors_per_cluster: u32,
+    fat_start: u64,
+    fat_sectors: u32,
+    fat_count: u32,
+    data_start: u64,
+    root_cluster: u32,
+    /// Highest cluster number, plus one.
+    cluster_end: u32,
+    fs_info: Option<u64>,
+    /// Where to start looking for a free cluster.
+    next_free: u32,
+    free_count_stale: bool,
+}
+
+impl<D: BlockDevice> Fat32<D> {
+    /// Reads the boot sector, of the device itself or of its first FAT32 partition.
+    pub fn mount(mut device: D) -> Result<Self, FatError> {
+        if device.sector_size() != SECTOR_SIZE {
+            return Err(FatError::NotFat32);
+        }
+        let mut sector = [0u8; SECTOR_SIZE];
+        device.read_sectors(0, &mut sector)?;
+        let mut partition_start = 0;
+        if !is_fat32_boot_sector(&sector) {
+            partition_start = (0..4)
+                .map(|index| &sector[PARTITION_TABLE + index * 16..][..16])
+                .find(|entry| matches!(entry[4], PARTITION_FAT32_CHS | PARTITION_FAT32_LBA))
+                .map(|entry| u32_at(entry, 8) as u64)
+                .ok_or(FatError::NotFat32)?;
+            device.read_sectors(partition_start, &mut sector)?;
+            if !is_fat32_boot_sector(&sector) {
+                return Err(FatError::NotFat32);
+            }
+        }
+
+        let sectors_per_cluster = sector[SECTORS_PER_CLUSTER] as u32;
+        let reserved_sectors = u16_at(&sector, RESERVED_SECTORS) as u64;
+        let fat_count = sector[FAT_COUNT] as u32;
+        let fat_sectors = u32_at(&sector, FAT_SIZE_32);
+        let total_sectors = match u16_at(&sector, TOTAL_SECTORS_16) {
+            0 => u32_at(&sector, TOTAL_SECTORS_32) as u64,
+            small => small as u64,
+        };
+        let fat_start = partition_start + reserved_sectors;
+        let data_start = fat_start + fat_count as u64 * fat_sectors as u64;
+        let data_sectors = (partition_start + total_sectors)
+            .checked_sub(data_start)
+            .ok_or(FatError::NotFat32)?;
+        // The FAT can't describe more clusters than it has entries for
+        let fat_entries = fat_sectors as u64 * SECTOR_SIZE as u64 / 4;
+        let cluster_end =
+            (data_sectors / sectors_per_cluster as u64 + FIRST_CLUSTER as u64).min(fat_entries);
+        // A volume needs at least one cluster, and the root directory has to be one of them
+        let root_cluster = u32_at(&sector, ROOT_CLUSTER);
+        if cluster_end <= FIRST_CLUSTER as u64
+            || !(FIRST_CLUSTER as u64..cluster_end).contains(&(root_cluster as u64))
+        {
+            return Err(FatError::NotFat32);
+        }
+        let fs_info = match u16_at(&sector, FS_INFO_SECTOR) {
+            0 | 0xFFFF => None,
+            offset => Some(partition_start + offset as u64),
+        };
+        Ok(Fat32 {
+            device,
+            sectors_per_cluster,
+            fat_start,
+            fat_sectors,
+            fat_count,
+            data_start,
+            root_cluster,
+            cluster_end: cluster_end as u32,
+            fs_info,
+            next_free: FIRST_CLUSTER,
+            free_count_stale: false,
+        })
+    }
+
+    /// Gives the device back.
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    /// Lists a directory, leaving out `.` and `..`.
+    pub fn read_dir(&mut self, path: &str) -> Result<Vec<DirEntry>, FatError> {
+        let node = self.find(path)?;
+        if !node.is_dir {
+            return Err(FatError::NotADirectory);
+        }
+        self.list(node.first_cluster)
+    }
+
+    /// Opens an existing file for reading and writing, at its start.
+    pub fn open(&mut self, path: &str) -> Result<File<'_, D>, FatError> {
+        let node = self.find(path)?;
+        if node.is_dir {
+            return Err(FatError::IsADirectory);
+        }
+        let (directory, entry) = node.location.ok_or(FatError::IsADirectory)?;
+        Ok(File {
+            directory,
+            slot: entry.slot,
+            first_cluster: node.first_cluster,
+            size: node.size,
+            position: 0,
+            cursor: None,
+            fs: self,
+        })
+    }
+
+    /// Opens a file, creating it if it doesn't exist and emptying it if it does.
+    pub fn create(&mut self, path: &str) -> Result<File<'_, D>, FatError> {
+        match self.find(path) {
+            Ok(_) => {
+                let mut file = self.open(path)?;
+                file.truncate()?;
+                Ok(file)
+            }
+            Err(FatError::NotFound) => {
+                let (parent, name) = split_path(path);
+                let parent = self.find(parent)?;
+                if !parent.is_dir {
+                    return Err(FatError::NotADirectory);
+                }
+                self.add_entry(parent.first_cluster, name, ATTR_ARCHIVE)?;
+                self.open(path)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Opens a file, creating it if it doesn't exist, with its position at the end.
+    pub fn append(&mut self, path: &str) -> Result<File<'_, D>, FatError> {
+        if let Err(FatError::NotFound) = self.find(path) {
+            self.create(path)?;
+        }
+        let mut file = self.open(path)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(file)
+    }
+
+    /// Reads a whole file.
+    pub fn read_file(&mut self, path: &str) -> Result<Vec<u8>, FatError> {
+        let mut file = self.open(path)?;
+        let mut data = vec![0; file.len() as usize];
+        let read = file.read(&mut data)?;
+        data.truncate(read);
+        Ok(data)
+    }
+
+    /// Deletes a file or an empty directory.
+    pub fn remove(&mut self, path: &str) -> Result<(), FatError> {
+        let node = self.find(path)?;
+        let (directory, entry) = node.location.ok_or(FatError::InvalidName)?;
+        if node.is_dir && !self.list(node.first_cluster)?.is_empty() {
+            return Err(FatError::DirectoryNotEmpty);
+        }
+        for slot in entry.first_slot..=entry.slot {
+            let mut raw = self.read_slot(directory, slot)?;
+            raw[0] = DELETED;
+            self.write_slot(directory, slot, &raw)?;
+        }
+        self.free_chain(node.first_cluster)
+    }
+
+    /// Makes sure everything written is on the device, not just in its cache.
+    pub fn flush(&mut self) -> Result<(), FatError> {
+        Ok(self.device.flush()?)
+    }
+
+    fn cluster_bytes(&self) -> usize {
+        self.sectors_per_cluster as usize * SECTOR_SIZE
+    }
+
+    /// The first sector of `cluster`, which a corrupt entry or FAT may have put outside the
+    /// volume.
+    fn cluster_sector(&self, cluster: u32) -> Result<u64, FatError> {
+        if !(FIRST_CLUSTER..self.cluster_end).contains(&cluster) {
+            return Err(FatError::Corrupt);
+        }
+        Ok(self.data_start + (cluster - FIRST_CLUSTER) as u64 * self.sectors_per_cluster as u64)
+    }
+
+    fn read_sector(&mut self, lba: u64) -> Result<[u8; SECTOR_SIZE], FatError> {
+        let mut sector = [0; SECTOR_SIZE];
+        self.device.read_sectors(lba, &mut sector)?;
+        Ok(sector)
+    }
+
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32, FatError> {
+        if !(FIRST_CLUSTER..self.cluster_end).contains(&cluster) {
+            return Err(FatError::Corrupt);
+        }
+        let offset = cluster as usize * 4;
+        let sector = self.read_sector(self.fat_start + (offset / SECTOR_SIZE) as u64)?;
+        Ok(u32_at(&sector, offset % SECTOR_SIZE) & CLUSTER_MASK)
+    }
+
+    /// Sets a FAT entry in every copy of the FAT, keeping the reserved top four bits.
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> Result<(), FatError> {
+        let offset = cluster as usize * 4;
+        for copy in 0..self.fat_count {
+            let lba = self.fat_start
+                + copy as u64 * self.fat_sectors as u64
+                + (offset / SECTOR_SIZE) as u64;
+            let mut sector = self.read_sector(lba)?;
+            let at = offset % SECTOR_SIZE;
+            let entry = u32_at(&sector, at) & !CLUSTER_MASK | value & CLUSTER_MASK;
+            sector[at..at + 4].copy_from_slice(&entry.to_le_bytes());
+            self.device.write_sectors(lba, &sector)?;
+        }
+        Ok(())
+    }
+
+    /// The cluster after this one in its chain, if there is one.
+    fn next_cluster(&mut self, cluster: u32) -> Result<Option<u32>, FatError> {
+        match self.fat_entry(cluster)? {
+            next if next >= END_OF_CHAIN => Ok(None),
+            FREE_CLUSTER | BAD_CLUSTER => Err(FatError::Corrupt),
+            next => Ok(Some(next)),
+        }
+    }
+
+    /// The `index`th cluster of the chain starting at `first`.
+    fn nth_cluster(&mut self, first: u32, index: usize) -> Result<Option<u32>, FatError> {
+        let mut cluster = first;
+        for _ in 0..index {
+            match self.next_cluster(cluster)? {
+                Some(next) => cluster = next,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(cluster))
+    }
+
+    /// Takes a free cluster, zeroes it and links it after `previous`.
+    fn allocate_cluster(&mut self, previous: Option<u32>) -> Result<u32, FatError> {
+        // Look a whole FAT sector at a time, wrapping around once
+        let total = self.cluster_end - FIRST_CLUSTER;
+        let mut cluster = self.next_free.clamp(FIRST_CLUSTER, self.cluster_end - 1);
+        let mut checked = 0;
+        let mut found = None;
+        while found.is_none() && checked < total {
+            let offset = cluster as usize * 4;
+            let sector = self.read_sector(self.fat_start + (offset / SECTOR_SIZE) as u64)?;
+            for at in (offset % SECTOR_SIZE..SECTOR_SIZE).step_by(4) {
+                if u32_at(&sector, at) & CLUSTER_MASK == FREE_CLUSTER {
+                    found = Some(cluster);
+                    break;
+                }
+                cluster += 1;
+                checked += 1;
+                if cluster == self.cluster_end {
+                    cluster = FIRST_CLUSTER;
+                    break;
+                }
+                if checked == total {
+                    break;
+                }
+            }
+        }
+        let cluster = found.ok_or(FatError::DiskFull)?;
+        self.next_free = cluster + 1;
+        self.mark_free_count_stale()?;
+
+        self.set_fat_entry(cluster, CLUSTER_MASK)?;
+        if let Some(previous) = previous {
+            self.set_fat_entry(previous, cluster)?;
+        }
+        let zeroes = vec![0; self.cluster_bytes()];
+        self.device
+            .write_sectors(self.cluster_sector(cluster)?, &zeroes)?;
+        Ok(cluster)
+    }
+
+    /// Frees every cluster of a chain. 0, the first cluster of an empty file, is no chain.
+    fn free_chain(&mut self, first: u32) -> Result<(), FatError> {
+        let mut cluster = Some(first).filter(|&cluster| cluster >= FIRST_CLUSTER);
+        if cluster.is_some() {
+            self.mark_free_count_stale()?;
+        }
+        let mut remaining = self.cluster_end;
+        while let Some(current) = cluster {
+            if remaining == 0 {
+                return Err(FatError::Corrupt);
+            }
+            remaining -= 1;
+            cluster = self.next_cluster(current)?;
+            self.set_fat_entry(current, FREE_CLUSTER)?;
+            self.next_free = self.next_free.min(current);
+        }
+        Ok(())
+    }
+
+    /// The free cluster count in the FSInfo sector is only a hint, but a wrong one is worse than
+    /// none, so it is marked unknown before the first change.
+    fn mark_free_count_stale(&mut self) -> Result<(), FatError> {
+        if let (Some(lba), false) = (self.fs_info, self.free_count_stale) {
+            let mut sector = self.read_sector(lba)?;
+            sector[FS_INFO_FREE_COUNT..FS_INFO_FREE_COUNT + 4].fill(0xFF);
+            self.device.write_sectors(lba, &sector)?;
+        }
+        self.free_count_stale = true;
+        Ok(())
+    }
+
+    /// Reads every cluster of a chain, for directories, which are small.
+    fn read_chain(&mut self, first: u32) -> Result<Vec<u8>, FatError> {
+        let mut data = Vec::new();
+        let mut cluster = Some(first);
+        while let Some(current) = cluster {
+            if data.len() >= self.cluster_end as usize * self.cluster_bytes() {
+                return Err(FatError::Corrupt);
+            }
+            let start = data.len();
+            data.resize(start + self.cluster_bytes(), 0);
+            self.device
+                .read_sectors(self.cluster_sector(current)?, &mut data[start..])?;
+            cluster = self.next_cluster(current)?;
+        }
+        Ok(data)
+    }
+
+    /// Where a directory's `slot`th entry is: its sector and the offset in it.
+    fn slot_position(&mut self, directory: u32, slot: usize) -> Result<(u64, usize), FatError> {
+        let byte = slot * ENTRY_SIZE;
+        let cluster = self
+            .nth_cluster(directory, byte / self.cluster_bytes())?
+            .ok_or(FatError::Corrupt)?;
+        let in_cluster = byte % self.cluster_bytes();
+        let lba = self.cluster_sector(cluster)? + (in_cluster / SECTOR_SIZE) as u64;
+        Ok((lba, in_cluster % SECTOR_SIZE))
+    }
+
+    fn read_slot(&mut self, directory: u32, slot: usize) -> Result<[u8; ENTRY_SIZE], FatError> {
+        let (lba, offset) = self.slot_position(directory, slot)?;
+        let sector = self.read_sector(lba)?;
+        let mut raw = [0; ENTRY_SIZE];
+        raw.copy_from_slice(&sector[offset..offset + ENTRY_SIZE]);
+        Ok(raw)
+    }
+
+    fn write_slot(
+        &mut self,
+        directory: u32,
+        slot: usize,
+        raw: &[u8; ENTRY_SIZE],
+    ) -> Result<(), FatError> {
+        let (lba, offset) = self.slot_position(directory, slot)?;
+        let mut sector = self.read_sector(lba)?;
+        sector[offset..offset + ENTRY_SIZE].copy_from_slice(raw);
+        self.device.write_sectors(lba, &sector)?;
+        Ok(())
+    }
+
+    fn list(&mut self, directory: u32) -> Result<Vec<DirEntry>, FatError> {
+        let data = self.read_chain(directory)?;
+        Ok(parse_directory(&data))
+    }
+
+    /// Follows a path from the root directory.
+    fn find(&mut self, path: &str) -> Result<Node, FatError> {
+        let mut node = Node {
+            first_cluster: self.root_cluster,
+            size: 0,
+            is_dir: true,
+            location: None,
+        };
+        for component in path.split('/').filter(|c| !c.is_empty() && *c != ".") {
+            if !node.is_dir {
+                return Err(FatError::NotADirectory);
+            }
+            let entry = self
+                .list(node.first_cluster)?
+                .into_iter()
+                .find(|entry| {
+                    entry.name.eq_ignore_ascii_case(component)
+                        || entry.short_name().eq_ignore_ascii_case(component)
+                })
+                .ok_or(FatError::NotFound)?;
+            node = Node {
+                first_cluster: entry.first_cluster,
+                size: entry.size,
+                is_dir: entry.is_dir,
+                location: Some((node.first_cluster, entry)),
+            };
+        }
+        Ok(node)
+    }
+
+    /// Adds an empty file or directory entry, with long name entries in front of it when the
+    /// name doesn't fit 8.3.
+    fn add_entry(&mut self, directory: u32, name: &str, attributes: u8) -> Result<(), FatError> {
+        if !is_valid_name(name) {
+            return Err(FatError::InvalidName);
+        }
+        let data = self.read_chain(directory)?;
+        let existing = parse_directory(&data);
+        let (short_name, case_flags, exact) = short_name_for(name, &existing)?;
+
+        let mut entries = Vec::new();
+        if !exact {
+            let units = name.encode_utf16().collect::<Vec<_>>();
+            let count = units.len().div_ceil(LONG_NAME_CHARS);
+            let checksum = short_name_checksum(&short_name);
+            for sequence in (1..=count).rev() {
+                let mut raw = [0u8; ENTRY_SIZE];
+                raw[0] = sequence as u8
+                    | if sequence == count {
+                        LAST_LONG_ENTRY
+                    } else {
+                        0
+                    };
+                raw[ATTRIBUTES] = ATTR_LONG_NAME;
+                raw[13] = checksum;
+                for (index, &offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+                    // Names end with a NUL, and the rest of the entry is padded with 0xFFFF
+                    let unit = match (sequence - 1) * LONG_NAME_CHARS + index {
+                        at if at < units.len() => units[at],
+                        at if at == units.len() => 0,
+                        _ => 0xFFFF,
+                    };
+                    raw[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+                }
+                entries.push(raw);
+            }
+        }
+        let mut raw = [0u8; ENTRY_SIZE];
+        raw[..11].copy_from_slice(&short_name);
+        raw[ATTRIBUTES] = attributes;
+        raw[CASE_FLAGS] = case_flags;
+        entries.push(raw);
+
+        let first_slot = self.free_slots(directory, &data, entries.len())?;
+        for (index, raw) in entries.iter().enumerate() {
+            self.write_slot(directory, first_slot + index, raw)?;
+        }
+        Ok(())
+    }
+
+    /// Finds `count` free entries in a row in a directory, growing it if there aren't any.
+    fn free_slots(&mut self, directory: u32, data: &[u8], count: usize) -> Result<usize, FatError> {
+        let slots = data.len() / ENTRY_SIZE;
+        let mut run_start = 0;
+        let mut run = 0;
+        for (slot, raw) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+            if raw[0] == END_OF_DIRECTORY {
+                // Everything from here on is free, and the run can go on into new clusters
+                if run == 0 {
+                    run_start = slot;
+                }
+                run += slots - slot;
+                break;
+            }
+            if raw[0] == DELETED {
+                if run == 0 {
+                    run_start = slot;
+                }
+                run += 1;
+                if run == count {
+                    return Ok(run_start);
+                }
+            } else {
+                run = 0;
+            }
+        }
+        if run >= count {
+            return Ok(run_start);
+        }
+        // A run cut short by the end of the directory carries on in new clusters, which only
+        // works if it reaches the end
+        let ends_directory = run > 0 && run_start + run == slots;
+        if !ends_directory {
+            run_start = slots;
+            run = 0;
+        }
+        let per_cluster = self.cluster_bytes() / ENTRY_SIZE;
+        let mut last = self
+            .nth_cluster(directory, slots / per_cluster - 1)?
+            .ok_or(FatError::Corrupt)?;
+        while run < count {
+            last = self.allocate_cluster(Some(last))?;
+            run += per_cluster;
+        }
+        Ok(run_start)
+    }
+
+    /// Writes a file's first cluster and size into its directory entry.
+    fn update_entry(
+        &mut self,
+        directory: u32,
+        slot: usize,
+        first_cluster: u32,
+        size: u32,
+    ) -> Result<(), FatError> {
+        let mut raw = self.read_slot(directory, slot)?;
+        raw[CLUSTER_HIGH..CLUSTER_HIGH + 2]
+            .copy_from_slice(&((first_cluster >> 16) as u16).to_le_bytes());
+        raw[CLUSTER_LOW..CLUSTER_LOW + 2].copy_from_slice(&(first_cluster as u16).to_le_bytes());
+        raw[FILE_SIZE..FILE_SIZE + 4].copy_from_slice(&size.to_le_bytes());
+        self.write_slot(directory, slot, &raw)
+    }
+}
+
+/// An open file, read and written from its current position, like `std::fs::File`.
+pub struct File<'a, D: BlockDevice> {
+    fs: &'a mut Fat32<D>,
+    directory: u32,
+    slot: usize,
+    /// 0 while the file is empty.
+    first_cluster: u32,
+    size: u32,
+    position: u32,
+    /// The last cluster visited and its index in the chain, so reading or writing along the
+    /// file doesn't walk the chain from the start each time.
+    cursor: Option<(usize, u32)>,
+}
+
+impl<D: BlockDevice> File<'_, D> {
+    pub fn len(&self) -> u32 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// Moves the position, which can't go past the end of the file. Returns the new position.
+    pub fn seek(&mut self, to: SeekFrom) -> Result<u32, FatError> {
+        let target = match to {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => self.size as i64 + offset,
+            SeekFrom::Current(offset) => self.position as i64 + offset,
+        };
+        self.position = target.clamp(0, self.size as i64) as u32;
+        Ok(self.position)
+    }
+
+    /// Reads from the position into `buffer`, returning how many bytes were read: fewer than
+    /// asked for at the end of the file.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, FatError> {
+        let wanted = buffer.len().min((self.size - self.position) as usize);
+        let mut done = 0;
+        while done < wanted {
+            let (lba, offset) = self.sector_at(self.position, false)?;
+            let sector = self.fs.read_sector(lba)?;
+            let count = (SECTOR_SIZE - offset).min(wanted - done);
+            buffer[done..done + count].copy_from_slice(&sector[offset..offset + count]);
+            done += count;
+            self.position += count as u32;
+        }
+        Ok(done)
+    }
+
+    /// Writes `data` at the position, growing the file as needed.
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, FatError> {
+        let mut done = 0;
+        while done < data.len() {
+            let (lba, offset) = self.sector_at(self.position, true)?;
+            let count = (SECTOR_SIZE - offset).min(data.len() - done);
+            let mut sector = if count == SECTOR_SIZE {
+                [0; SECTOR_SIZE]
+            } else {
+                self.fs.read_sector(lba)?
+            };
+            sector[offset..offset + count].copy_from_slice(&data[done..done + count]);
+            self.fs.device.write_sectors(lba, &sector)?;
+            done += count;
+            self.position += count as u32;
+            self.size = self.size.max(self.position);
+        }
+        self.fs
+            .update_entry(self.directory, self.slot, self.first_cluster, self.size)?;
+        Ok(done)
+    }
+
+    /// Cuts the file off at the position, freeing the clusters past it.
+    pub fn truncate(&mut self) -> Result<(), FatError> {
+        let cluster_bytes = self.fs.cluster_bytes() as u32;
+        if self.position == 0 {
+            self.fs.free_chain(self.first_cluster)?;
+            self.first_cluster = 0;
+        } else if self.first_cluster != 0 {
+            let last_index = ((self.position - 1) / cluster_bytes) as usize;
+            let last = self
+                .fs
+                .nth_cluster(self.first_cluster, last_index)?
+                .ok_or(FatError::Corrupt)?;
+            if let Some(rest) = self.fs.next_cluster(last)? {
+                self.fs.set_fat_entry(last, CLUSTER_MASK)?;
+                self.fs.free_chain(rest)?;
+            }
+        }
+        self.cursor = None;
+        self.size = self.position;
+        self.fs
+            .update_entry(self.directory, self.slot, self.first_cluster, self.size)
+    }
+
+    pub fn flush(&mut self) -> Result<(), FatError> {
+        self.fs.flush()
+    }
+
+    /// The sector holding byte `position` of the file, and the offset in it. With `grow` set,
+    /// clusters are added to the chain when it doesn't reach that far.
+    fn sector_at(&mut self, position: u32, grow: bool) -> Result<(u64, usize), FatError> {
+        let cluster_bytes = self.fs.cluster_bytes();
+        let index = position as usize / cluster_bytes;
+        if self.first_cluster == 0 {
+            if !grow {
+                return Err(FatError::Corrupt);
+            }
+            self.first_cluster = self.fs.allocate_cluster(None)?;
+        }
+        let (mut at, mut cluster) = match self.cursor {
+            Some((at, cluster)) if at <= index => (at, cluster),
+            _ => (0, self.first_cluster),
+        };
+        while at < index {
+            cluster = match self.fs.next_cluster(cluster)? {
+                Some(next) => next,
+                None if grow => self.fs.allocate_cluster(Some(cluster))?,
+                None => return Err(FatError::Corrupt),
+            };
+            at += 1;
+        }
+        self.cursor = Some((index, cluster));
+        let in_cluster = position as usize % cluster_bytes;
+        let lba = self.fs.cluster_sector(cluster)? + (in_cluster / SECTOR_SIZE) as u64;
+        Ok((lba, in_cluster % SECTOR_SIZE))
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// FAT32 boot sectors have no fixed root directory and no 16-bit FAT size.
+fn is_fat32_boot_sector(sector: &[u8]) -> bool {
+    sector[SIGNATURE..SIGNATURE + 2] == [0x55, 0xAA]
+        && u16_at(sector, BYTES_PER_SECTOR) as usize == SECTOR_SIZE
+        && sector[SECTORS_PER_CLUSTER].is_power_of_two()
+        && sector[FAT_COUNT] > 0
+        && u16_at(sector, ROOT_ENTRY_COUNT) == 0
+        && u16_at(sector, FAT_SIZE_16) == 0
+        && u32_at(sector, FAT_SIZE_32) != 0
+}
+
+/// Turns the directory's raw entries into a listing, putting long names back together.
+fn parse_directory(data: &[u8]) -> Vec<DirEntry> {
+    let mut entries = Vec::new();
+    // Long name pieces seen since the last short entry, the slot they started at, and their
+    // checksum
+    let mut long_name: Vec<u16> = Vec::new();
+    let mut long_start = None;
+    let mut long_checksum = 0;
+    for (slot, raw) in data.chunks_exact(ENTRY_SIZE).enumerate() {
+        match raw[0] {
+            END_OF_DIRECTORY => break,
+            DELETED => {
+                long_start = None;
+                continue;
+            }
+            _ => {}
+        }
+        let attributes = raw[ATTRIBUTES];
+        if attributes & ATTR_LONG_NAME == ATTR_LONG_NAME {
+            let sequence = (raw[0] & !LAST_LONG_ENTRY) as usize;
+            if raw[0] & LAST_LONG_ENTRY != 0 {
+                long_name = vec![0xFFFF; sequence * LONG_NAME_CHARS];
+                long_start = Some(slot);
+                long_checksum = raw[13];
+            }
+            if long_start.is_some()
+                && sequence >= 1
+                && sequence * LONG_NAME_CHARS <= long_name.len()
+            {
+                for (index, &offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+                    long_name[(sequence - 1) * LONG_NAME_CHARS + index] = u16_at(raw, offset);
+                }
+            }
+            continue;
+        }
+        if attributes & ATTR_VOLUME_ID != 0 {
+            long_start = None;
+            continue;
+        }
+
+        let mut short_name = [0; 11];
+        short_name.copy_from_slice(&raw[..11]);
+        let first_slot = long_start
+            .take()
+            .filter(|_| long_checksum == short_name_checksum(&short_name));
+        let name = match first_slot {
+            Some(_) => {
+                let end = long_name
+                    .iter()
+                    .position(|&unit| unit == 0 || unit == 0xFFFF)
+                    .unwrap_or(long_name.len());
+                char::decode_utf16(long_name[..end].iter().copied())
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect()
+            }
+            None => format_short_name(&short_name, raw[CASE_FLAGS]),
+        };
+        if name == "." || name == ".." {
+            continue;
+        }
+        entries.push(DirEntry {
+            name,
+            is_dir: attributes & ATTR_DIRECTORY != 0,
+            size: u32_at(raw, FILE_SIZE),
+            short_name,
+            first_cluster: (u16_at(raw, CLUSTER_HIGH) as u32) << 16
+                | u16_at(raw, CLUSTER_LOW) as u32,
+            first_slot: first_slot.unwrap_or(slot),
+            slot,
+        });
+    }
+    entries
+}
+
+/// `NAME    EXT` as `NAME.EXT`, lowered as the case flags say.
+fn format_short_name(short_name: &[u8; 11], case_flags: u8) -> String {
+    let part = |bytes: &[u8], lower: bool| {
+        bytes
+            .iter()
+            .take_while(|&&byte| byte != b' ')
+            .map(|&byte| match lower {
+                true => byte.to_ascii_lowercase() as char,
+                false => byte as char,
+            })
+            .collect::<String>()
+    };
+    let mut base = short_name[..8].to_vec();
+    // 0x05 stands for a name really starting with 0xE5
+    if base[0] == 0x05 {
+        base[0] = DELETED;
+    }
+    let mut name = part(&base, case_flags & CASE_LOWER_BASE != 0);
+    let extension = part(&short_name[8..], case_flags & CASE_LOWER_EXTENSION != 0);
+    if !extension.is_empty() {
+        name.push('.');
+        name.push_str(&extension);
+    }
+    name
+}
+
+fn short_name_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name
+        .iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.encode_utf16().count() <= MAX_NAME_LENGTH
+        && !name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c))
+}
+
+/// Picks the 8.3 name for a new entry, and the case flags that let an all-lowercase base or
+/// extension be shown as typed. Returns whether that is the whole name, or long name entries
+/// are needed.
+fn short_name_for(name: &str, existing: &[DirEntry]) -> Result<([u8; 11], u8, bool), FatError> {
+    let (base, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => (&name[..dot], &name[dot + 1..]),
+        _ => (name, ""),
+    };
+    let mut lossy = false;
+    let mut convert = |part: &str, length: usize| {
+        let mut converted = Vec::new();
+        for c in part.chars() {
+            if c == ' ' || c == '.' {
+                lossy = true;
+                continue;
+            }
+            let byte = match c {
+                c if c.is_ascii_alphanumeric() || "$%'-_@~`!(){}^#&".contains(c) => {
+                    c.to_ascii_uppercase() as u8
+                }
+                _ => {
+                    lossy = true;
+                    b'_'
+                }
+            };
+            if converted.len() == length {
+                lossy = true;
+                break;
+            }
+            converted.push(byte);
+        }
+        converted
+    };
+    let base_bytes = convert(base, 8);
+    let extension_bytes = convert(extension, 3);
+    if base_bytes.is_empty() {
+        return Err(FatError::InvalidName);
+    }
+
+    let all = |part: &str, test: fn(&char) -> bool| {
+        part.chars()
+            .filter(char::is_ascii_alphabetic)
+            .all(|c| test(&c))
+    };
+    let mut case_flags = 0;
+    let mut mixed = false;
+    for (part, flag) in [(base, CASE_LOWER_BASE), (extension, CASE_LOWER_EXTENSION)] {
+        if all(part, char::is_ascii_lowercase) && part.chars().any(|c| c.is_ascii_lowercase()) {
+            case_flags |= flag;
+        } else if !all(part, char::is_ascii_uppercase) {
+            mixed = true;
+        }
+    }
+
+    let mut short_name = [b' '; 11];
+    short_name[..base_bytes.len()].copy_from_slice(&base_bytes);
+    short_name[8..8 + extension_bytes.len()].copy_from_slice(&extension_bytes);
+    let taken = |candidate: &[u8; 11]| existing.iter().any(|entry| entry.short_name == *candidate);
+    if !lossy && !taken(&short_name) {
+        return Ok((short_name, case_flags, !mixed));
+    }
+
+    // Otherwise NAME~1.EXT, NAME~2.EXT and so on, with long name entries for the real name
+    for number in 1..1_000_000u32 {
+        let tail = alloc::format!("~{}", number);
+        let keep = base_bytes.len().min(8 - tail.len());
+        let mut candidate = [b' '; 11];
+        candidate[..keep].copy_from_slice(&base_bytes[..keep]);
+        candidate[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        candidate[8..].copy_from_slice(&short_name[8..]);
+        if !taken(&candidate) {
+            return Ok((candidate, 0, false));
+        }
+    }
+    Err(FatError::DiskFull)
+}
+
+/// Splits a path into its parent directory and last component.
+fn split_path(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(slash) => (&path[..slash], &path[slash + 1..]),
+        None => ("", path),
+    }
+}
@@ -0,0 +1,146 @@
+//! Physical memory frames, for devices that read and write memory on their own (DMA) and so
+//! need buffers they can be told the physical address of. Frames are handed out for good, like
+//! the heap, unless a driver that finds it can't use them gives them back with [`free`].
+
+use bootloader_api::info::{MemoryRegion, MemoryRegionKind};
+use core::ops::Range;
+use core::slice;
+use spin::Mutex;
+
+pub const FRAME_SIZE: u64 = 4096;
+/// Most pieces of free memory kept track of. Memory past them is never handed out.
+const MAX_RANGES: usize = 64;
+
+/// Zeroed, physically contiguous memory that a device can be pointed at.
+pub struct DmaBuffer {
+    physical_address: u64,
+    bytes: &'static mut [u8],
+}
+
+impl DmaBuffer {
+    pub fn physical_address(&self) -> u64 {
+        self.physical_address
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.bytes
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        self.bytes
+    }
+
+    /// For structures the device reads or writes behind the compiler's back, which have to be
+    /// accessed with volatile reads and writes.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.bytes.as_mut_ptr()
+    }
+}
+
+struct Frames {
+    /// Free physical memory, in whole frames, lowest first.
+    free: [(u64, u64); MAX_RANGES],
+    count: usize,
+    physical_offset: u64,
+}
+
+static FRAMES: Mutex<Frames> = Mutex::new(Frames {
+    free: [(0, 0); MAX_RANGES],
+    count: 0,
+    physical_offset: 0,
+});
+
+/// Takes the usable memory from the boot info's memory map, apart from `reserved` (the heap),
+/// and from frame 0, which real mode code and null pointers would both like to have.
+pub fn init(regions: &[MemoryRegion], physical_offset: u64, reserved: Range<u64>) {
+    let mut frames = FRAMES.lock();
+    frames.physical_offset = physical_offset;
+    frames.count = 0;
+    for region in regions
+        .iter()
+        .filter(|r| r.kind == MemoryRegionKind::Usable)
+    {
+        let start = region.start.max(FRAME_SIZE);
+        let pieces = [
+            (start, region.end.min(reserved.start)),
+            (start.max(reserved.end), region.end),
+        ];
+        for (start, end) in pieces {
+            let start = start.next_multiple_of(FRAME_SIZE);
+            let end = end / FRAME_SIZE * FRAME_SIZE;
+            if start < end && frames.count < MAX_RANGES {
+                let index = frames.count;
+                frames.free[index] = (start, end);
+                frames.count += 1;
+            }
+        }
+    }
+}
+
+/// Allocates at least `size` bytes of zeroed, physically contiguous memory, in whole frames.
+/// High memory goes first, leaving low memory to [`allocate_below`].
+pub fn allocate(size: usize) -> Option<DmaBuffer> {
+    allocate_below(size, u64::MAX)
+}
+
+/// Like [`allocate`], but ending at or below the physical address `limit`, for devices that
+/// can't reach all of memory, like ISA DMA.
+pub fn allocate_below(size: usize, limit: u64) -> Option<DmaBuffer> {
+    let frames_needed = (size as u64).div_ceil(FRAME_SIZE).max(1);
+    let length = frames_needed * FRAME_SIZE;
+    let mut frames = FRAMES.lock();
+    let physical_offset = frames.physical_offset;
+    let count = frames.count;
+    let (start, _) = frames.free[..count]
+        .iter_mut()
+        .rev()
+        .find(|(start, end)| *start + length <= (*end).min(limit))?;
+    let physical_address = *start;
+    *start += length;
+
+    let bytes = unsafe {
+        slice::from_raw_parts_mut(
+            (physical_offset + physical_address) as *mut u8,
+            length as usize,
+        )
+    };
+    bytes.fill(0);
+    Some(DmaBuffer {
+        physical_address,
+        bytes,
+    })
+}
+
+/// Gives back a buffer no device will use. If it doesn't border a free range and there is no
+/// room to keep track of another, its frames are lost, as they would have been anyway.
+pub fn free(buffer: DmaBuffer) {
+    let start = buffer.physical_address;
+    let end = start + buffer.bytes.len() as u64;
+    let mut frames = FRAMES.lock();
+    let count = frames.count;
+    for range in &mut frames.free[..count] {
+        if range.0 == end {
+            range.0 = start;
+            return;
+        }
+        if range.1 == start {
+            range.1 = end;
+            return;
+        }
+    }
+    if count < MAX_RANGES {
+        // keep the ranges in order
+        let index = frames.free[..count].partition_point(|&(free_start, _)| free_start < start);
+        frames.free.copy_within(index..count, index + 1);
+        frames.free[index] = (start, end);
+        frames.count += 1;
+    }
+}
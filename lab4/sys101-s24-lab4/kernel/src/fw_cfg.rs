@@ -0,0 +1,75 @@
+//! QEMU's firmware configuration device (fw_cfg), through its I/O ports 0x510/0x511: the way to
+//! hand the kernel a setting when QEMU starts, with `-fw_cfg name=opt/...,string=...`, without
+//! building it again.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use x86_64::instructions::port::Port;
+
+const SELECTOR: u16 = 0x510;
+const DATA: u16 = 0x511;
+
+/// Items with a fixed key; files have keys listed in the directory.
+const KEY_SIGNATURE: u16 = 0x0000;
+const KEY_FILE_DIR: u16 = 0x0019;
+const SIGNATURE: &[u8; 4] = b"QEMU";
+
+/// A directory entry: the size, big-endian like every number fw_cfg gives, the key, two bytes
+/// reserved, and the name, NUL-padded.
+const FILE_ENTRY_SIZE: usize = 64;
+const FILE_NAME_LEN: usize = 56;
+
+/// Starts reading the item `key` from its beginning.
+fn select(key: u16) {
+    unsafe { Port::new(SELECTOR).write(key) };
+}
+
+fn read(buffer: &mut [u8]) {
+    let mut data = Port::<u8>::new(DATA);
+    for byte in buffer {
+        *byte = unsafe { data.read() };
+    }
+}
+
+fn read_u32(key: u16) -> u32 {
+    select(key);
+    let mut bytes = [0; 4];
+    read(&mut bytes);
+    u32::from_be_bytes(bytes)
+}
+
+/// Whether the machine is QEMU with a fw_cfg device. Elsewhere the ports read as 0xFF.
+pub fn present() -> bool {
+    select(KEY_SIGNATURE);
+    let mut signature = [0; 4];
+    read(&mut signature);
+    &signature == SIGNATURE
+}
+
+/// The contents of the file `name` QEMU was given, or `None` if it wasn't given one by that
+/// name or this isn't QEMU.
+pub fn read_file(name: &str) -> Option<Vec<u8>> {
+    if !present() || name.len() >= FILE_NAME_LEN {
+        return None;
+    }
+    // the entries follow the count, read one after the other
+    let count = read_u32(KEY_FILE_DIR);
+    for _ in 0..count {
+        let mut entry = [0; FILE_ENTRY_SIZE];
+        read(&mut entry);
+        let entry_name = &entry[8..];
+        let len = entry_name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(FILE_NAME_LEN);
+        if &entry_name[..len] == name.as_bytes() {
+            let size = u32::from_be_bytes(entry[0..4].try_into().unwrap());
+            let key = u16::from_be_bytes(entry[4..6].try_into().unwrap());
+            select(key);
+            let mut contents = vec![0; size as usize];
+            read(&mut contents);
+            return Some(contents);
+        }
+    }
+    None
+}
@@ -0,0 +1,137 @@
+use crate::disk;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use kernel::fat32::FatError;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// Scores kept in the table.
+pub const MAX_ENTRIES: usize = 10;
+pub const INITIALS_LEN: usize = 3;
+
+/// Where the table is saved on the game's disk.
+const PATH: &str = "/hiscores.dat";
+const MAGIC: &[u8; 4] = b"HISC";
+/// Initials, one byte of padding and the score, little-endian.
+const ENTRY_SIZE: usize = 8;
+const FILE_SIZE: usize = MAGIC.len() + MAX_ENTRIES * ENTRY_SIZE + 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    /// Capital letters, padded with spaces.
+    pub initials: [u8; INITIALS_LEN],
+    pub score: u32,
+}
+
+impl Entry {
+    pub fn initials(&self) -> &str {
+        core::str::from_utf8(&self.initials).unwrap_or("???")
+    }
+}
+
+lazy_static! {
+    // best first; read from the disk the first time it's needed
+    static ref TABLE: Mutex<Vec<Entry>> = Mutex::new(load().unwrap_or_default());
+}
+
+/// The table as it is to be saved, once [`insert`] has changed it and until [`save`] writes it.
+static UNSAVED: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+/// Reads the table from the disk now, rather than in the middle of a game.
+pub fn init() {
+    lazy_static::initialize(&TABLE);
+}
+
+/// The table, best score first.
+pub fn entries() -> Vec<Entry> {
+    TABLE.lock().clone()
+}
+
+/// Whether `score` is good enough to go in the table.
+pub fn qualifies(score: u32) -> bool {
+    let table = TABLE.lock();
+    score > 0 && (table.len() < MAX_ENTRIES || table.last().is_some_and(|e| score > e.score))
+}
+
+/// Puts a score in the table, below any equal ones already there, for [`save`] to write to the
+/// disk later, as that is too slow for an interrupt handler. Returns its place, counting from 0,
+/// or `None` if it didn't make it.
+pub fn insert(entry: Entry) -> Option<usize> {
+    let mut table = TABLE.lock();
+    let rank = table.iter().take_while(|e| e.score >= entry.score).count();
+    if rank >= MAX_ENTRIES {
+        return None;
+    }
+    table.insert(rank, entry);
+    table.truncate(MAX_ENTRIES);
+    // without a disk the table only lasts until the machine stops
+    if disk::mounted() {
+        *UNSAVED.lock() = Some(encode(&table));
+    }
+    Some(rank)
+}
+
+/// Writes the table to the disk if it has changed since it was last saved. Returns `None` if
+/// there was nothing to save.
+pub fn save() -> Option<Result<(), FatError>> {
+    let data = without_interrupts(|| UNSAVED.lock().take())?;
+    Some(disk::write(PATH, &data))
+}
+
+/// Reads the saved table, checking its checksum so a damaged file is ignored rather than
+/// showing garbage.
+fn load() -> Option<Vec<Entry>> {
+    let data = disk::read(PATH)?;
+    if data.len() != FILE_SIZE || !data.starts_with(MAGIC) {
+        return None;
+    }
+    let (body, checksum) = data.split_at(FILE_SIZE - 4);
+    if crc32(body) != u32::from_le_bytes(checksum.try_into().unwrap()) {
+        return None;
+    }
+    let mut table: Vec<Entry> = body[MAGIC.len()..]
+        .chunks_exact(ENTRY_SIZE)
+        .filter(|bytes| bytes[0] != 0)
+        .map(|bytes| Entry {
+            initials: bytes[..INITIALS_LEN].try_into().unwrap(),
+            score: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+        })
+        .collect();
+    table.sort_by_key(|entry| Reverse(entry.score));
+    Some(table)
+}
+
+/// The table as saved: the magic number, all the entries with zeroes for empty ones, and a
+/// CRC-32 of everything before it.
+fn encode(table: &[Entry]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(FILE_SIZE);
+    data.extend_from_slice(MAGIC);
+    for slot in 0..MAX_ENTRIES {
+        let mut bytes = [0; ENTRY_SIZE];
+        if let Some(entry) = table.get(slot) {
+            bytes[..INITIALS_LEN].copy_from_slice(&entry.initials);
+            bytes[4..8].copy_from_slice(&entry.score.to_le_bytes());
+        }
+        data.extend_from_slice(&bytes);
+    }
+    let checksum = crc32(&data);
+    data.extend_from_slice(&checksum.to_le_bytes());
+    data
+}
+
+/// The CRC-32 used by zip and Ethernet, a bit at a time as the table is tiny.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
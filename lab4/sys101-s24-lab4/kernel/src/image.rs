@@ -0,0 +1,342 @@
+// Decoders for the image formats the kernel can embed with include_bytes!: uncompressed BMP,
+// binary PPM (P6) and QOI (https://qoiformat.org/qoi-specification.pdf).
+
+use alloc::vec::Vec;
+
+/// Largest width or height accepted from an image header, to keep a corrupt header from
+/// exhausting the heap.
+const MAX_DIMENSION: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageError {
+    /// The data doesn't start with the signature of any supported format.
+    UnknownFormat,
+    /// The data ends before the header says it should.
+    Truncated,
+    /// A header field holds a value the decoder doesn't handle, e.g. RLE-compressed BMP.
+    Unsupported(&'static str),
+    /// The heap is too small for the decoded pixels.
+    OutOfMemory,
+}
+
+/// A decoded image: `width` x `height` pixels stored row by row as RGBA bytes.
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Decodes a BMP, PPM or QOI image, telling them apart by their signature.
+    pub fn decode(data: &[u8]) -> Result<Image, ImageError> {
+        if data.starts_with(b"BM") {
+            Image::from_bmp(data)
+        } else if data.starts_with(b"P6") {
+            Image::from_ppm(data)
+        } else if data.starts_with(b"qoif") {
+            Image::from_qoi(data)
+        } else {
+            Err(ImageError::UnknownFormat)
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// RGBA bytes, 4 per pixel, row by row from the top.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Makes every pixel of the given color fully transparent. Useful for formats without an
+    /// alpha channel, like PPM.
+    pub fn with_color_key(mut self, key: (u8, u8, u8)) -> Self {
+        for pixel in self.pixels.chunks_exact_mut(4) {
+            if (pixel[0], pixel[1], pixel[2]) == key {
+                pixel[3] = 0;
+            }
+        }
+        self
+    }
+
+    /// Allocates an image with every pixel transparent black.
+    fn blank(width: usize, height: usize) -> Result<Image, ImageError> {
+        if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+            return Err(ImageError::Unsupported("image dimensions"));
+        }
+        let mut pixels = Vec::new();
+        pixels
+            .try_reserve_exact(width * height * 4)
+            .map_err(|_| ImageError::OutOfMemory)?;
+        pixels.resize(width * height * 4, 0);
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, rgba: [u8; 4]) {
+        let offset = (y * self.width + x) * 4;
+        self.pixels[offset..offset + 4].copy_from_slice(&rgba);
+    }
+
+    /// Decodes an uncompressed Windows bitmap with 8 (paletted), 24 or 32 bits per pixel. 32-bit
+    /// images use their alpha channel unless it is zero everywhere, as many encoders leave it.
+    pub fn from_bmp(data: &[u8]) -> Result<Image, ImageError> {
+        const BI_RGB: u32 = 0;
+        const BI_BITFIELDS: u32 = 3;
+
+        let pixel_offset = read_u32_le(data, 10)? as usize;
+        let header_size = read_u32_le(data, 14)? as usize;
+        let width = read_u32_le(data, 18)? as i32;
+        let height = read_u32_le(data, 22)? as i32;
+        let bits_per_pixel = read_u16_le(data, 28)?;
+        let compression = read_u32_le(data, 30)?;
+        if width <= 0 || height == 0 {
+            return Err(ImageError::Unsupported("image dimensions"));
+        }
+        // Positive heights are stored bottom-up, negative ones top-down.
+        let top_down = height < 0;
+        let (width, height) = (width as usize, height.unsigned_abs() as usize);
+        // checked before the sizes below are worked out from them, so those can't overflow
+        if width > MAX_DIMENSION || height > MAX_DIMENSION {
+            return Err(ImageError::Unsupported("image dimensions"));
+        }
+
+        // Bit positions of each channel; the defaults are the BGRA byte order of BI_RGB.
+        let mut masks = [0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0xff00_0000];
+        match (compression, bits_per_pixel) {
+            (BI_RGB, 8 | 24 | 32) => {}
+            (BI_BITFIELDS, 32) => {
+                for (channel, mask) in masks.iter_mut().take(3).enumerate() {
+                    *mask = read_u32_le(data, 14 + 40 + channel * 4)?;
+                }
+                masks[3] = if header_size >= 56 {
+                    read_u32_le(data, 14 + 40 + 12)?
+                } else {
+                    0
+                };
+            }
+            (BI_RGB | BI_BITFIELDS, _) => return Err(ImageError::Unsupported("BMP bit depth")),
+            _ => return Err(ImageError::Unsupported("BMP compression")),
+        }
+
+        let palette_offset = 14 + header_size;
+        let palette_len = match read_u32_le(data, 46)? {
+            0 => 256,
+            colors => colors as usize,
+        };
+        let bytes_per_pixel = usize::from(bits_per_pixel / 8);
+        // Rows are padded to a multiple of 4 bytes.
+        let row_size = (width * bytes_per_pixel + 3) & !3;
+        let pixel_end = pixel_offset
+            .checked_add(row_size * height)
+            .ok_or(ImageError::Truncated)?;
+        let pixel_data = data
+            .get(pixel_offset..pixel_end)
+            .ok_or(ImageError::Truncated)?;
+
+        let mut image = Image::blank(width, height)?;
+        let mut any_alpha = false;
+        for (row_index, row) in pixel_data.chunks_exact(row_size).enumerate() {
+            let y = if top_down {
+                row_index
+            } else {
+                height - 1 - row_index
+            };
+            for x in 0..width {
+                let pixel = &row[x * bytes_per_pixel..(x + 1) * bytes_per_pixel];
+                let rgba = match bits_per_pixel {
+                    8 => {
+                        let index = usize::from(pixel[0]);
+                        if index >= palette_len {
+                            return Err(ImageError::Unsupported("BMP palette index"));
+                        }
+                        let entry = data
+                            .get(palette_offset + index * 4..palette_offset + index * 4 + 3)
+                            .ok_or(ImageError::Truncated)?;
+                        [entry[2], entry[1], entry[0], 0xff]
+                    }
+                    24 => [pixel[2], pixel[1], pixel[0], 0xff],
+                    _ => {
+                        let value = u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                        let [r, g, b, a] = masks.map(|mask| extract_channel(value, mask));
+                        any_alpha |= a != 0;
+                        [r, g, b, a]
+                    }
+                };
+                image.set_pixel(x, y, rgba);
+            }
+        }
+        if bits_per_pixel == 32 && !any_alpha {
+            for pixel in image.pixels.chunks_exact_mut(4) {
+                pixel[3] = 0xff;
+            }
+        }
+        Ok(image)
+    }
+
+    /// Decodes a binary portable pixmap (`P6`). Samples wider than 8 bits are scaled down. The
+    /// format has no alpha channel, so every pixel is opaque.
+    pub fn from_ppm(data: &[u8]) -> Result<Image, ImageError> {
+        let mut position = 2;
+        let width = read_ppm_number(data, &mut position)?;
+        let height = read_ppm_number(data, &mut position)?;
+        let max_value = read_ppm_number(data, &mut position)?;
+        if max_value == 0 || max_value > 0xffff {
+            return Err(ImageError::Unsupported("PPM maximum value"));
+        }
+        // Exactly one whitespace character separates the header from the samples.
+        position += 1;
+
+        let bytes_per_sample = if max_value < 256 { 1 } else { 2 };
+        let mut image = Image::blank(width, height)?;
+        let samples = data
+            .get(position..position + width * height * 3 * bytes_per_sample)
+            .ok_or(ImageError::Truncated)?;
+        let scale = |sample: &[u8]| -> u8 {
+            let value = match sample {
+                [high, low] => usize::from(*high) << 8 | usize::from(*low),
+                _ => usize::from(sample[0]),
+            };
+            (value * 255 / max_value) as u8
+        };
+        for (index, pixel) in samples.chunks_exact(3 * bytes_per_sample).enumerate() {
+            let (r, g, b) = (
+                scale(&pixel[..bytes_per_sample]),
+                scale(&pixel[bytes_per_sample..2 * bytes_per_sample]),
+                scale(&pixel[2 * bytes_per_sample..]),
+            );
+            image.set_pixel(index % width, index / width, [r, g, b, 0xff]);
+        }
+        Ok(image)
+    }
+
+    /// Decodes a "Quite OK Image".
+    pub fn from_qoi(data: &[u8]) -> Result<Image, ImageError> {
+        const OP_RGB: u8 = 0xfe;
+        const OP_RGBA: u8 = 0xff;
+        const HEADER_SIZE: usize = 14;
+
+        let width = read_u32_be(data, 4)? as usize;
+        let height = read_u32_be(data, 8)? as usize;
+        let mut image = Image::blank(width, height)?;
+
+        let mut seen = [[0u8; 4]; 64];
+        let mut pixel = [0, 0, 0, 0xff];
+        let mut position = HEADER_SIZE;
+        let mut run = 0;
+        let mut next_byte = || -> Result<u8, ImageError> {
+            let byte = *data.get(position).ok_or(ImageError::Truncated)?;
+            position += 1;
+            Ok(byte)
+        };
+        for index in 0..width * height {
+            if run > 0 {
+                run -= 1;
+            } else {
+                let tag = next_byte()?;
+                match tag {
+                    OP_RGB => {
+                        pixel[0] = next_byte()?;
+                        pixel[1] = next_byte()?;
+                        pixel[2] = next_byte()?;
+                    }
+                    OP_RGBA => {
+                        pixel = [next_byte()?, next_byte()?, next_byte()?, next_byte()?];
+                    }
+                    _ => match tag >> 6 {
+                        // QOI_OP_INDEX
+                        0b00 => pixel = seen[usize::from(tag & 0x3f)],
+                        // QOI_OP_DIFF: each channel differs by -2..=1
+                        0b01 => {
+                            pixel[0] = pixel[0].wrapping_add((tag >> 4) & 0x03).wrapping_sub(2);
+                            pixel[1] = pixel[1].wrapping_add((tag >> 2) & 0x03).wrapping_sub(2);
+                            pixel[2] = pixel[2].wrapping_add(tag & 0x03).wrapping_sub(2);
+                        }
+                        // QOI_OP_LUMA: green difference plus red and blue relative to it
+                        0b10 => {
+                            let green = (tag & 0x3f).wrapping_sub(32);
+                            let red_blue = next_byte()?;
+                            pixel[0] = pixel[0]
+                                .wrapping_add(green)
+                                .wrapping_add(red_blue >> 4)
+                                .wrapping_sub(8);
+                            pixel[1] = pixel[1].wrapping_add(green);
+                            pixel[2] = pixel[2]
+                                .wrapping_add(green)
+                                .wrapping_add(red_blue & 0x0f)
+                                .wrapping_sub(8);
+                        }
+                        // QOI_OP_RUN: repeat the previous pixel
+                        _ => run = tag & 0x3f,
+                    },
+                }
+                let [r, g, b, a] = pixel.map(usize::from);
+                seen[(r * 3 + g * 5 + b * 7 + a * 11) % 64] = pixel;
+            }
+            image.set_pixel(index % width, index / width, pixel);
+        }
+        Ok(image)
+    }
+}
+
+/// Scales the bits of `value` selected by `mask` to a full 8-bit channel.
+fn extract_channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let bits = (value & mask) >> mask.trailing_zeros();
+    let max = mask >> mask.trailing_zeros();
+    (u64::from(bits) * 255 / u64::from(max)) as u8
+}
+
+fn read_u16_le(data: &[u8], offset: usize) -> Result<u16, ImageError> {
+    let bytes = data.get(offset..offset + 2).ok_or(ImageError::Truncated)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> Result<u32, ImageError> {
+    let bytes = data.get(offset..offset + 4).ok_or(ImageError::Truncated)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> Result<u32, ImageError> {
+    let bytes = data.get(offset..offset + 4).ok_or(ImageError::Truncated)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Reads the next decimal number of a PPM header, skipping whitespace and `#` comments.
+fn read_ppm_number(data: &[u8], position: &mut usize) -> Result<usize, ImageError> {
+    loop {
+        match data.get(*position) {
+            Some(b'#') => {
+                while !matches!(data.get(*position), Some(b'\n') | None) {
+                    *position += 1;
+                }
+            }
+            Some(byte) if byte.is_ascii_whitespace() => *position += 1,
+            Some(_) => break,
+            None => return Err(ImageError::Truncated),
+        }
+    }
+    let mut number: usize = 0;
+    let mut digits = 0;
+    while let Some(byte) = data.get(*position).filter(|byte| byte.is_ascii_digit()) {
+        number = number
+            .saturating_mul(10)
+            .saturating_add(usize::from(byte - b'0'));
+        digits += 1;
+        *position += 1;
+    }
+    if digits == 0 {
+        return Err(ImageError::Unsupported("PPM header"));
+    }
+    Ok(number)
+}
@@ -0,0 +1,123 @@
+//! The initial ramdisk: a tar archive the bootloader loads into memory next to the kernel, which
+//! the build script makes. It's unpacked into a [`TmpFs`], borrowing the file contents from the
+//! archive, and mounted read-only at `/`.
+
+use crate::tmpfs::TmpFs;
+use crate::vfs::{FileKind, FileSystem, VfsError};
+use core::ops::Range;
+use core::str;
+
+const BLOCK_SIZE: usize = 512;
+
+// Header fields of the ustar format
+const NAME: Range<usize> = 0..100;
+const SIZE: Range<usize> = 124..136;
+const CHECKSUM: Range<usize> = 148..156;
+const TYPE: usize = 156;
+const MAGIC: Range<usize> = 257..262;
+const PREFIX: Range<usize> = 345..500;
+
+const TYPE_FILE: u8 = b'0';
+/// Very old archives mark files with a NUL instead of `0`.
+const TYPE_OLD_FILE: u8 = 0;
+const TYPE_DIRECTORY: u8 = b'5';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitrdError {
+    /// A header, at this offset, isn't a ustar header or fails its checksum.
+    BadHeader(usize),
+    /// The archive ends in the middle of a file.
+    Truncated,
+    /// A path in the archive couldn't be made, e.g. a file inside something that is a file too.
+    BadPath(VfsError),
+}
+
+impl From<VfsError> for InitrdError {
+    fn from(error: VfsError) -> Self {
+        InitrdError::BadPath(error)
+    }
+}
+
+/// The text in a NUL-padded header field.
+fn text(field: &[u8]) -> Result<&str, ()> {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    str::from_utf8(&field[..end]).map_err(|_| ())
+}
+
+/// The number in an octal header field, padded with spaces or NULs.
+fn octal(field: &[u8]) -> Result<usize, ()> {
+    let digits = text(field)?.trim_matches(|c| c == ' ' || c == '\0');
+    usize::from_str_radix(digits, 8).map_err(|_| ())
+}
+
+/// Checks a header against its checksum: the sum of all its bytes, counting the checksum field
+/// itself as spaces.
+fn checksum_matches(header: &[u8]) -> bool {
+    let sum: usize = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| if CHECKSUM.contains(&i) { b' ' } else { b } as usize)
+        .sum();
+    octal(&header[CHECKSUM]) == Ok(sum)
+}
+
+/// Unpacks a tar archive into a new filesystem. Directories are made as paths need them, so
+/// archives don't have to list them; links and other special files are skipped.
+pub fn unpack(archive: &'static [u8]) -> Result<TmpFs, InitrdError> {
+    let mut fs = TmpFs::new();
+    let mut offset = 0;
+    while offset + BLOCK_SIZE <= archive.len() {
+        let header = &archive[offset..offset + BLOCK_SIZE];
+        // the archive ends with blocks of zeroes
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let bad_header = move |_| InitrdError::BadHeader(offset);
+        if &header[MAGIC] != b"ustar" || !checksum_matches(header) {
+            return Err(InitrdError::BadHeader(offset));
+        }
+        let size = octal(&header[SIZE]).map_err(bad_header)?;
+        let prefix = text(&header[PREFIX]).map_err(bad_header)?;
+        let name = text(&header[NAME]).map_err(bad_header)?;
+
+        let start = offset + BLOCK_SIZE;
+        let data = archive
+            .get(start..start + size)
+            .ok_or(InitrdError::Truncated)?;
+        offset = start + size.next_multiple_of(BLOCK_SIZE);
+
+        let kind = match header[TYPE] {
+            TYPE_FILE | TYPE_OLD_FILE => FileKind::File,
+            TYPE_DIRECTORY => FileKind::Directory,
+            _ => continue,
+        };
+        let mut names = prefix
+            .split('/')
+            .chain(name.split('/'))
+            .filter(|name| !matches!(*name, "" | "." | ".."));
+        let Some(mut last) = names.next() else {
+            continue;
+        };
+        let mut dir = fs.root();
+        for name in names {
+            dir = match fs.lookup(dir, last) {
+                Ok(inode) => inode,
+                Err(VfsError::NotFound) => fs.create(dir, last, FileKind::Directory)?,
+                Err(error) => return Err(error.into()),
+            };
+            last = name;
+        }
+        match (kind, fs.lookup(dir, last)) {
+            (FileKind::Directory, Ok(_)) => {}
+            (FileKind::Directory, Err(_)) => {
+                fs.create(dir, last, FileKind::Directory)?;
+            }
+            (FileKind::File, _) => {
+                let _ = fs.remove(dir, last);
+                let inode = fs.create(dir, last, FileKind::File)?;
+                fs.set_contents(inode, data)?;
+            }
+        }
+    }
+    Ok(fs)
+}
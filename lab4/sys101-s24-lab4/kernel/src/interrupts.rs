@@ -5,6 +5,12 @@ use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin::Mutex;
 use crate::HandlerTable;
+use crate::ata::{self, Channel};
+use crate::keyboard;
+use crate::mouse;
+use crate::sb16;
+use crate::speaker;
+use crate::virtio_blk;
 
 // This code is largely Copyright (c) 2019 Philipp Oppermann.
 // Gabriel Ferrer added:
@@ -22,6 +28,13 @@ lazy_static! {
         idt.double_fault.set_handler_fn(double_fault_handler);
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Mouse.as_usize()].set_handler_fn(mouse_interrupt_handler);
+        idt[InterruptIndex::Sb16.as_usize()].set_handler_fn(sb16_interrupt_handler);
+        idt[InterruptIndex::PrimaryAta.as_usize()].set_handler_fn(primary_ata_interrupt_handler);
+        idt[InterruptIndex::SecondaryAta.as_usize()].set_handler_fn(secondary_ata_interrupt_handler);
+        idt[InterruptIndex::Pci9.as_usize()].set_handler_fn(pci9_interrupt_handler);
+        idt[InterruptIndex::Pci10.as_usize()].set_handler_fn(pci10_interrupt_handler);
+        idt[InterruptIndex::Pci11.as_usize()].set_handler_fn(pci11_interrupt_handler);
         idt
     };
 }
@@ -50,11 +63,38 @@ const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 pub static PICS: Mutex<ChainedPics> =
     Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
+/// Line of the secondary PIC on the primary one.
+const CASCADE_IRQ: u8 = 2;
+pub const MOUSE_IRQ: u8 = 12;
+/// The lines with a handler for PCI devices, which the firmware routes them to.
+pub(crate) const PCI_IRQS: [u8; 3] = [9, 10, 11];
+
+/// Lets an IRQ line through the PICs, whatever the firmware left in the masks. Lines on the
+/// secondary PIC also need the cascade line unmasked.
+pub fn unmask_irq(irq: u8) {
+    let mut pics = PICS.lock();
+    let [mut primary, mut secondary] = unsafe { pics.read_masks() };
+    if irq < 8 {
+        primary &= !(1 << irq);
+    } else {
+        primary &= !(1 << CASCADE_IRQ);
+        secondary &= !(1 << (irq - 8));
+    }
+    unsafe { pics.write_masks(primary, secondary) };
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Sb16 = PIC_1_OFFSET + sb16::IRQ,
+    Pci9 = PIC_1_OFFSET + 9,
+    Pci10 = PIC_1_OFFSET + 10,
+    Pci11 = PIC_1_OFFSET + 11,
+    Mouse = PIC_1_OFFSET + MOUSE_IRQ,
+    PrimaryAta = PIC_1_OFFSET + 14,
+    SecondaryAta = PIC_1_OFFSET + 15,
 }
 
 impl InterruptIndex {
@@ -68,6 +108,7 @@ impl InterruptIndex {
 }
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    speaker::tick();
     let h = &*HANDLERS.lock();
     if let Some(handler) = h {
         handler.handle_timer();
@@ -79,24 +120,17 @@ extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFr
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    use pc_keyboard::{layouts, HandleControl, Keyboard, ScancodeSet1};
     use x86_64::instructions::port::Port;
 
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> =
-            Mutex::new(Keyboard::new(layouts::Us104Key, ScancodeSet1,
-                HandleControl::Ignore)
-            );
-    }
-
-    let mut keyboard = KEYBOARD.lock();
     let mut port = Port::new(0x60);
 
     let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            let h = &*HANDLERS.lock();
-            if let Some(handler) = h {
+    if let Some(key_event) = keyboard::add_byte(scancode) {
+        let key = keyboard::process_keyevent(key_event.clone());
+        let h = &*HANDLERS.lock();
+        if let Some(handler) = h {
+            handler.handle_key_event(key_event);
+            if let Some(key) = key {
                 handler.handle_keyboard(key);
             }
         }
@@ -106,4 +140,72 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
     }
-}
\ No newline at end of file
+}
+
+extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    if let Some(byte) = mouse::read_byte() {
+        let event = mouse::DECODER.lock().add_byte(byte);
+        if let Some(event) = event {
+            let h = &*HANDLERS.lock();
+            if let Some(handler) = h {
+                handler.handle_mouse(event);
+            }
+        }
+    }
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Mouse.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn sb16_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    sb16::handle_interrupt();
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Sb16.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn primary_ata_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    ata::handle_interrupt(Channel::Primary);
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::PrimaryAta.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn secondary_ata_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    ata::handle_interrupt(Channel::Secondary);
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::SecondaryAta.as_u8());
+    }
+}
+
+// The firmware routes PCI interrupts to IRQ 9, 10 or 11. Devices can share a line, so every
+// PCI driver gets a look at each interrupt.
+
+fn pci_interrupt(irq: u8) {
+    virtio_blk::handle_interrupt(irq);
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(PIC_1_OFFSET + irq);
+    }
+}
+
+extern "x86-interrupt" fn pci9_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    pci_interrupt(9);
+}
+
+extern "x86-interrupt" fn pci10_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    pci_interrupt(10);
+}
+
+extern "x86-interrupt" fn pci11_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    pci_interrupt(11);
+}
@@ -0,0 +1,99 @@
+use alloc::boxed::Box;
+use pc_keyboard::{
+    layouts, DecodedKey, Error, HandleControl, KeyCode, KeyEvent, KeyState, Keyboard,
+    KeyboardLayout, ScancodeSet, ScancodeSet1, ScancodeSet2,
+};
+use spin::Mutex;
+
+/// The keyboard layouts keys can be decoded with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// US 104-key QWERTY.
+    #[default]
+    Us104,
+    /// UK 105-key QWERTY.
+    Uk105,
+    /// US 104-key Dvorak.
+    Dvorak104,
+    /// Japanese 109-key.
+    Jis109,
+    /// French AZERTY.
+    Azerty,
+}
+
+/// The scancode set the keyboard controller sends. Set 1 is what you get with translation
+/// turned on, which is the usual setup, including QEMU's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CodeSet {
+    #[default]
+    Set1,
+    Set2,
+}
+
+/// A `Keyboard` with its layout and scancode set erased, so they can be picked at runtime.
+trait Decoder: Send {
+    fn add_byte(&mut self, byte: u8) -> Result<Option<KeyEvent>, Error>;
+    fn process_keyevent(&mut self, event: KeyEvent) -> Option<DecodedKey>;
+}
+
+impl<T, S> Decoder for Keyboard<T, S>
+where
+    T: KeyboardLayout + Send,
+    S: ScancodeSet + Send,
+{
+    fn add_byte(&mut self, byte: u8) -> Result<Option<KeyEvent>, Error> {
+        Keyboard::add_byte(self, byte)
+    }
+
+    fn process_keyevent(&mut self, event: KeyEvent) -> Option<DecodedKey> {
+        Keyboard::process_keyevent(self, event)
+    }
+}
+
+/// More than the number of `KeyCode`s, so any of them can index the key state table.
+const KEY_CODES: usize = 256;
+
+static DECODER: Mutex<Option<Box<dyn Decoder>>> = Mutex::new(None);
+static KEYS_DOWN: Mutex<[bool; KEY_CODES]> = Mutex::new([false; KEY_CODES]);
+
+/// Sets up the decoder the keyboard interrupt handler feeds scancodes to.
+pub(crate) fn init(layout: Layout, code_set: CodeSet, handle_control: HandleControl) {
+    let decoder: Box<dyn Decoder> = match layout {
+        Layout::Us104 => with_layout(layouts::Us104Key, code_set, handle_control),
+        Layout::Uk105 => with_layout(layouts::Uk105Key, code_set, handle_control),
+        Layout::Dvorak104 => with_layout(layouts::Dvorak104Key, code_set, handle_control),
+        Layout::Jis109 => with_layout(layouts::Jis109Key, code_set, handle_control),
+        Layout::Azerty => with_layout(layouts::Azerty, code_set, handle_control),
+    };
+    *DECODER.lock() = Some(decoder);
+}
+
+fn with_layout<T>(layout: T, code_set: CodeSet, handle_control: HandleControl) -> Box<dyn Decoder>
+where
+    T: KeyboardLayout + Send + 'static,
+{
+    match code_set {
+        CodeSet::Set1 => Box::new(Keyboard::new(layout, ScancodeSet1, handle_control)),
+        CodeSet::Set2 => Box::new(Keyboard::new(layout, ScancodeSet2, handle_control)),
+    }
+}
+
+/// Feeds one byte from the keyboard to the decoder and returns the key press or release it
+/// completes, keeping the key state table up to date.
+pub(crate) fn add_byte(byte: u8) -> Option<KeyEvent> {
+    let event = DECODER.lock().as_mut()?.add_byte(byte).ok()??;
+    KEYS_DOWN.lock()[event.code as usize] = event.state == KeyState::Down;
+    Some(event)
+}
+
+/// Turns a key event into a character or raw key, taking the modifiers into account. Releases
+/// and modifier keys give `None`.
+pub(crate) fn process_keyevent(event: KeyEvent) -> Option<DecodedKey> {
+    DECODER.lock().as_mut()?.process_keyevent(event)
+}
+
+/// True while the key is held down. Unlike the decoded key handler, this sees every key at
+/// once, so holding one key doesn't hide the others.
+pub fn is_key_down(code: KeyCode) -> bool {
+    KEYS_DOWN.lock()[code as usize]
+}
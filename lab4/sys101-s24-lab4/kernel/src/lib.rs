@@ -3,13 +3,30 @@
 #![no_std]
 #![feature(abi_x86_interrupt)]
 
+pub mod ata;
+pub mod block;
+pub mod fat32;
+pub mod frames;
+pub mod fw_cfg;
+pub mod initrd;
 mod interrupts;
+mod keyboard;
+mod mouse;
+pub mod pci;
+pub mod rand;
+pub mod sb16;
+pub mod speaker;
+pub mod tmpfs;
+pub mod vfs;
+pub mod virtio_blk;
 
 use core::cell::UnsafeCell;
 use core::panic::PanicInfo;
 use core::fmt::Write;
 use uart_16550::SerialPort;
-use pc_keyboard::DecodedKey;
+use pc_keyboard::{DecodedKey, HandleControl, KeyEvent};
+pub use keyboard::{is_key_down, CodeSet, Layout};
+pub use mouse::{MouseButtons, MouseEvent};
 extern crate alloc;
 
 pub fn serial() -> SerialPort {
@@ -24,12 +41,17 @@ pub fn serial() -> SerialPort {
 /// up the handlers. When ready, call the **.start()** method to start up your pluggable
 /// interrupt operating system.
 ///
-/// For now, it only includes timer and keyboard handlers.
+/// For now, it only includes timer, keyboard and mouse handlers.
 /// I will add more if it seems useful to do so.
 /// Double-fault handling is addressed "behind the scenes".
 pub struct HandlerTable {
     timer: Option<fn()>,
     keyboard: Option<fn(DecodedKey)>,
+    key_event: Option<fn(KeyEvent)>,
+    layout: Layout,
+    code_set: CodeSet,
+    handle_control: HandleControl,
+    mouse: Option<fn(MouseEvent)>,
     startup: Option<fn()>,
     cpu_loop: fn() -> !
 }
@@ -37,16 +59,34 @@ pub struct HandlerTable {
 impl HandlerTable {
     /// Creates a new HandlerTable with no handlers.
     pub fn new() -> Self {
-        HandlerTable {timer: None, keyboard: None, startup: None, cpu_loop: hlt_loop}
+        HandlerTable {
+            timer: None,
+            keyboard: None,
+            key_event: None,
+            layout: Layout::Us104,
+            code_set: CodeSet::Set1,
+            handle_control: HandleControl::Ignore,
+            mouse: None,
+            startup: None,
+            cpu_loop: hlt_loop,
+        }
     }
 
     /// Starts up a simple operating system using the specified handlers.
     pub fn start(self) -> ! {
         self.startup.map(|f| f());
         let fore = self.cpu_loop;
+        keyboard::init(self.layout, self.code_set, self.handle_control);
+        let mouse_found = self.mouse.is_some() && mouse::init();
+        if self.mouse.is_some() && !mouse_found {
+            let _ = writeln!(serial(), "No PS/2 mouse found");
+        }
 
         interrupts::init_idt(self);
         unsafe { interrupts::PICS.lock().initialize() };
+        if mouse_found {
+            interrupts::unmask_irq(interrupts::MOUSE_IRQ);
+        }
         x86_64::instructions::interrupts::enable();
 
         (fore)();
@@ -82,6 +122,63 @@ impl HandlerTable {
         }
     }
 
+    /// Sets the raw key event handler. Unlike the keyboard handler, it sees every press and
+    /// release, including modifier keys, before any layout is applied. The
+    /// [KeyEvent](https://docs.rs/pc-keyboard/0.5.1/pc_keyboard/struct.KeyEvent.html) struct comes
+    /// from the [pc_keyboard](https://crates.io/crates/pc-keyboard) crate.
+    ///
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn key_event(mut self, key_event_handler: fn(KeyEvent)) -> Self {
+        self.key_event = Some(key_event_handler);
+        self
+    }
+
+    /// Called by the low-level interrupt routines to handle a key press or release.
+    pub fn handle_key_event(&self, event: KeyEvent) {
+        if let Some(key_event) = self.key_event {
+            (key_event)(event)
+        }
+    }
+
+    /// Sets the layout used to decode keys for the keyboard handler. The default is US 104-key.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Sets the scancode set the keyboard sends. The default is set 1.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn code_set(mut self, code_set: CodeSet) -> Self {
+        self.code_set = code_set;
+        self
+    }
+
+    /// Sets whether Ctrl+letter is decoded as a control character or ignored. The default
+    /// ignores it.
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn handle_control(mut self, handle_control: HandleControl) -> Self {
+        self.handle_control = handle_control;
+        self
+    }
+
+    /// Sets the mouse handler. It is called with every packet the mouse sends: movement since
+    /// the last one, wheel clicks and the buttons held down. Without a mouse handler the mouse is
+    /// never switched on.
+    ///
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn mouse(mut self, mouse_handler: fn(MouseEvent)) -> Self {
+        self.mouse = Some(mouse_handler);
+        self
+    }
+
+    /// Called by the low-level interrupt routines to handle a mouse event.
+    pub fn handle_mouse(&self, event: MouseEvent) {
+        if let Some(mouse) = self.mouse {
+            (mouse)(event)
+        }
+    }
+
     /// Sets the startup handler.
     /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
     pub fn startup(mut self, startup_handler: fn()) -> Self {
@@ -3,11 +3,33 @@
             // Add this struct definition in your screen.rs file
 
 extern crate alloc;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
 use alloc::vec::Vec;
 mod allocator;
+mod barrier;
+mod console;
+mod cursor;
+mod disk;
+mod highscores;
+mod image;
+mod menu;
 mod screen;
+mod sfx;
+mod sprite;
+mod waves;
+mod window;
+use crate::barrier::Barrier;
+use crate::highscores::{Entry, INITIALS_LEN};
+use crate::image::Image;
+use crate::menu::{Action, Menu};
 use crate::screen::screenwriter;
-use crate::screen::ScreenWriter;
+use crate::screen::{Rect, TextStyle, LINE_HEIGHT};
+use crate::sfx::Effect;
+use crate::sprite::{Scene, Sprite};
+use crate::waves::{Difficulty, Wave, FINAL_LEVEL};
+use crate::window::WindowId;
 use core::cell::RefCell;
 use core::fmt::Write;
 // use alloc::boxed::Box;
@@ -16,12 +38,17 @@ use bootloader_api::info::MemoryRegionKind;
 use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
 // use core::fmt::Write;
 use core::slice;
-use kernel::HandlerTable;
-use pc_keyboard::DecodedKey;
+use kernel::block::BlockDevice;
+use kernel::tmpfs::TmpFs;
+use kernel::{ata, frames, fw_cfg, initrd, pci, rand, sb16, vfs};
+use kernel::{is_key_down, HandlerTable, MouseEvent};
+use noto_sans_mono_bitmap::{FontWeight, RasterHeight};
+use pc_keyboard::{DecodedKey, KeyCode};
+use x86_64::instructions::interrupts::without_interrupts;
 use x86_64::registers::control::Cr3;
 use x86_64::structures::paging::PageTable;
 use x86_64::VirtAddr;
-const HEAP_SIZE: usize = 1000 * 1024; // 100 KiB
+const HEAP_SIZE: usize = 16 * 1024 * 1024; // 16 MiB, room for a full-screen back buffer
 
 const BOOTLOADER_CONFIG: BootloaderConfig = {
     let mut config = BootloaderConfig::new_default();
@@ -56,31 +83,252 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
 
     let _l4_table = unsafe { active_level_4_table(VirtAddr::new(physical_offset)) };
 
-    allocator::init_heap((physical_offset + usable_region.start) as usize, HEAP_SIZE);
+    let heap_size = HEAP_SIZE.min((usable_region.end - usable_region.start) as usize);
+    allocator::init_heap((physical_offset + usable_region.start) as usize, heap_size);
+    let heap = usable_region.start..usable_region.start + heap_size as u64;
+    frames::init(&boot_info.memory_regions, physical_offset, heap);
+    let double_buffered = screenwriter().enable_double_buffering();
+    open_windows();
+    if !double_buffered {
+        let _ = writeln!(
+            kernel::serial(),
+            "Heap too small for a back buffer, drawing straight to the screen"
+        );
+        let _ = writeln!(
+            window::Writer(LOG),
+            "Heap too small for a back buffer, drawing straight to the screen"
+        );
+    }
+
+    mount_filesystems(boot_info.ramdisk_addr.into_option(), boot_info.ramdisk_len);
+
+    let pci_devices = pci::scan();
+    let _ = writeln!(window::Writer(LOG), "{} PCI devices found", pci_devices);
+    let _ = pci::dump(&mut kernel::serial());
+    for drive in ata::drives() {
+        let _ = writeln!(
+            window::Writer(LOG),
+            "ATA {:?} {:?}: {}, {} MiB",
+            drive.channel(),
+            drive.position(),
+            drive.model(),
+            drive.capacity() / (1024 * 1024)
+        );
+    }
+
+    match disk::mount() {
+        Some(kind) => {
+            let _ = writeln!(window::Writer(LOG), "Game disk mounted ({})", kind);
+        }
+        None => {
+            let _ = writeln!(kernel::serial(), "No game disk, using the built-in sprites");
+        }
+    }
+    highscores::init();
+    load_sprites();
+    init_sound();
+    seed_random();
 
     HandlerTable::new()
         .keyboard(key)
+        .mouse(mouse)
         .timer(tick)
         .startup(start)
+        .cpu_loop(main_loop)
         .start();
 }
 
+/// Runs between interrupts, for work too slow for a handler: saving the high scores.
+fn main_loop() -> ! {
+    loop {
+        if let Some(Err(error)) = highscores::save() {
+            // the log pane is drawn by the handlers too
+            without_interrupts(|| {
+                let _ = writeln!(
+                    window::Writer(LOG),
+                    "Couldn't save the high scores: {:?}",
+                    error
+                );
+            });
+        }
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Reads every sprite now, so the disk is never read by an interrupt handler while the main
+/// loop is saving to it.
+fn load_sprites() {
+    lazy_static::initialize(&SQUID_FRAMES);
+    lazy_static::initialize(&CRAB_FRAMES);
+    lazy_static::initialize(&OCTOPUS_FRAMES);
+    lazy_static::initialize(&UFO_FRAMES);
+    lazy_static::initialize(&EXPLOSION_FRAMES);
+    lazy_static::initialize(&PLAYER_FRAMES);
+    cursor::init();
+}
+
+/// Mounts the initrd the bootloader loaded read-only at `/`, or an empty tmpfs there if there
+/// isn't one, and a tmpfs at `/tmp` for scratch files.
+fn mount_filesystems(ramdisk: Option<u64>, ramdisk_len: u64) {
+    let archive = ramdisk.map(|address| unsafe {
+        slice::from_raw_parts(address as *const u8, ramdisk_len as usize)
+    });
+    let root = match archive.map(initrd::unpack) {
+        Some(Ok(initrd)) => vfs::mount_read_only("/", Box::new(initrd)),
+        Some(Err(error)) => {
+            let _ = writeln!(kernel::serial(), "Bad initrd: {:?}", error);
+            vfs::mount("/", Box::new(TmpFs::new()))
+        }
+        None => {
+            let _ = writeln!(kernel::serial(), "No initrd");
+            vfs::mount("/", Box::new(TmpFs::new()))
+        }
+    };
+    if let Err(error) = root.and_then(|()| vfs::mount("/tmp", Box::new(TmpFs::new()))) {
+        let _ = writeln!(kernel::serial(), "Mounting filesystems failed: {:?}", error);
+    }
+    if let Ok(motd) = vfs::read_file("/etc/motd") {
+        let _ = window::Writer(LOG).write_str(&String::from_utf8_lossy(&motd));
+    }
+}
+
+/// The fw_cfg file the runner puts its `GAME_SEED` in.
+const SEED_FILE: &str = "opt/sys101/seed";
+
+/// Seeds the random numbers from the hardware, so every game plays differently, unless the
+/// runner was started with `GAME_SEED` set to a number, which makes every game play the same.
+fn seed_random() {
+    if !rand::self_test() {
+        let _ = writeln!(kernel::serial(), "Random numbers fail their self-test");
+    }
+    let seed = fw_cfg::read_file(SEED_FILE)
+        .and_then(|seed| String::from_utf8(seed).ok())
+        .and_then(|seed| seed.trim().parse().ok());
+    match seed {
+        Some(seed) => {
+            rand::seed(seed);
+            let _ = writeln!(window::Writer(LOG), "Fixed random seed {}", seed);
+        }
+        None => {
+            let seed = rand::seed_from_hardware();
+            let _ = writeln!(kernel::serial(), "Random seed {}", seed);
+        }
+    }
+}
+
+/// Starts the Sound Blaster if there is one, giving it a DMA buffer from low memory. Without it,
+/// sound effects go to the PC speaker.
+fn init_sound() {
+    let Some(buffer) = frames::allocate_below(sb16::BUFFER_SIZE, sb16::DMA_LIMIT) else {
+        let _ = writeln!(kernel::serial(), "No memory below 16 MiB for sound DMA");
+        return;
+    };
+    match sb16::init(buffer) {
+        Ok(()) => {
+            let _ = writeln!(window::Writer(LOG), "Sound Blaster 16 found");
+            sfx::init();
+        }
+        Err(error) => {
+            let _ = writeln!(kernel::serial(), "No Sound Blaster 16: {:?}", error);
+        }
+    }
+}
+
 use lazy_static::lazy_static;
 use spin::Mutex;
 // row number
 const ROWS: usize = 4;
 
-const BARRIER_COLS: usize = 20;
-const BARRIER_ROWS: usize = 4;
+const BARRIER_COUNT: usize = 4;
+
+/// One line at the top of the screen for the score.
+const STATUS_BAR: WindowId = WindowId::new(0);
+/// Kernel messages at the bottom of the screen, below the player.
+const LOG: WindowId = WindowId::new(1);
+const LOG_LINES: usize = 4;
+const GAME_OVER_STYLE: TextStyle = TextStyle::new((0xff, 0x20, 0x20))
+    .size(RasterHeight::Size32)
+    .weight(FontWeight::Bold);
+const WINNER_STYLE: TextStyle = TextStyle::new((0x40, 0xff, 0x40))
+    .size(RasterHeight::Size32)
+    .weight(FontWeight::Bold);
+const HIGH_SCORE_TITLE_STYLE: TextStyle = TextStyle::new((0xff, 0xd7, 0x00))
+    .size(RasterHeight::Size20)
+    .weight(FontWeight::Bold);
+/// The score just put in the high-score table.
+const NEW_HIGH_SCORE_STYLE: TextStyle = TextStyle::new((0xff, 0xd7, 0x00));
+const TITLE_STYLE: TextStyle = TextStyle::new((0xff, 0xff, 0xff))
+    .size(RasterHeight::Size32)
+    .weight(FontWeight::Bold);
+const PAUSED_STYLE: TextStyle = TextStyle::new((0xff, 0xff, 0xff))
+    .size(RasterHeight::Size32)
+    .weight(FontWeight::Bold);
+
+// Items of the menus
+const TITLE_START: usize = 0;
+const TITLE_DIFFICULTY: usize = 1;
+const PAUSE_ITEMS: [&str; 3] = ["Resume", "Restart", "Quit to title"];
+const PAUSE_RESUME: usize = 0;
+const PAUSE_RESTART: usize = 1;
+const PAUSE_QUIT: usize = 2;
+const GAME_OVER_ITEMS: [&str; 2] = ["Play again", "Title screen"];
+const GAME_OVER_PLAY_AGAIN: usize = 0;
+const GAME_OVER_TITLE: usize = 1;
+
+/// Ticks the "wave cleared" message stays up before the next wave, about two seconds.
+const LEVEL_CLEAR_TICKS: u32 = 36;
+
+/// Where the game is. Each state has its own tick, key and drawing logic, picked in
+/// `game_tick`, `handle_key` and `render`.
+#[derive(Clone)]
+enum GameState {
+    /// Picking the difficulty before a game.
+    Title(Menu),
+    Playing,
+    /// Frozen in the middle of a wave, with the pause menu up.
+    Paused(Menu),
+    /// A moment between two waves; the next one starts when `ticks` runs out.
+    LevelClear {
+        ticks: u32,
+    },
+    /// Typing initials for the high-score table after a game that made it.
+    HighScoreEntry {
+        won: bool,
+        initials: Vec<u8>,
+    },
+    /// The game is over, won or lost, with the menu for what's next.
+    GameOver {
+        won: bool,
+        menu: Menu,
+    },
+}
+
 lazy_static! {
+    static ref STATE: Mutex<GameState> = Mutex::new(title());
+    // whether the current state's screen has to be drawn, set by every change of state
+    static ref STATE_CHANGED: Mutex<bool> = Mutex::new(true);
     static ref SCORE: Mutex<u32> = Mutex::new(0);
-    static ref GAMEOVER: Mutex<bool> = Mutex::new(false);
-    static ref WINNER: Mutex<bool> = Mutex::new(false);
+    static ref DIFFICULTY: Mutex<Difficulty> = Mutex::new(Difficulty::Normal);
+    // the wave being played, from 1 to FINAL_LEVEL
+    static ref LEVEL: Mutex<u32> = Mutex::new(1);
+    static ref LIVES: Mutex<u32> = Mutex::new(STARTING_LIVES);
+    // score at which the next extra life is given
+    static ref NEXT_EXTRA_LIFE: Mutex<u32> = Mutex::new(EXTRA_LIFE_EVERY);
+    // ticks until the player comes back after being hit; 0 while they are in play
+    static ref RESPAWN: Mutex<u32> = Mutex::new(0);
+    // ticks left in which enemy bullets pass through the player, who blinks meanwhile
+    static ref INVULNERABLE: Mutex<u32> = Mutex::new(0);
+    // where the last game's score went in the high-score table
+    static ref NEW_RANK: Mutex<Option<usize>> = Mutex::new(None);
     // tick counter from one to five
     static ref TICK_COUNTER1: Mutex<u32> = Mutex::new(0);
     static ref TICK_COUNTER2: Mutex<u32> = Mutex::new(0);
+    // ticks until the player can fire again
+    static ref FIRE_COOLDOWN: Mutex<u32> = Mutex::new(0);
+    // ticks until the enemies can fire again
+    static ref ENEMY_FIRE_COOLDOWN: Mutex<u32> = Mutex::new(0);
 
-    static ref PLAYER: Mutex<Player> = Mutex::new(Player::new(50, 50, 40, 40, (0xff, 0, 0)));
+    static ref PLAYER: Mutex<Player> = Mutex::new(Player::new(50, 50, 40, 40, PLAYER_COLOR));
     static ref ENEMIES: Mutex<RefCell<[[Option<Enemy>; 15];ROWS]>> = Mutex::new(RefCell::new(init_enemy_array()));
     // enemy movement direction (1 for right, -1 for left)
     static ref ENEMY_DX: Mutex<i32> = Mutex::new(1);
@@ -90,7 +338,71 @@ lazy_static! {
     // array of bullets
     static ref BULLETS: Mutex<RefCell<[Option<Bullet>; 10]>> = Mutex::new(RefCell::new(init_bullet_array()));
     // array of barriers
-    static ref BARRIERS: Mutex<RefCell<[[Option<Barrier>; BARRIER_COLS]; BARRIER_ROWS]>> = Mutex::new(RefCell::new(init_barrier_array()));
+    static ref BARRIERS: Mutex<RefCell<[Option<Barrier>; BARRIER_COUNT]>> = Mutex::new(RefCell::new(init_barrier_array()));
+
+    // the mystery ship, while it crosses the screen
+    static ref UFO: Mutex<Option<Ufo>> = Mutex::new(None);
+    // ticks until the mystery ship shows up again
+    static ref UFO_TIMER: Mutex<u32> = Mutex::new(0);
+
+    // explosions still playing where enemies were hit
+    static ref EXPLOSIONS: Mutex<RefCell<[Option<Sprite>; MAX_EXPLOSIONS]>> = Mutex::new(RefCell::new(init_explosion_array()));
+
+    // sprite frames, drawn white and tinted with the entity's color
+    static ref SQUID_FRAMES: [Image; 2] = [
+        disk::image("/sprites/squid_a.qoi", include_bytes!("../assets/squid_a.qoi")),
+        disk::image("/sprites/squid_b.qoi", include_bytes!("../assets/squid_b.qoi")),
+    ];
+    static ref CRAB_FRAMES: [Image; 2] = [
+        disk::image("/sprites/enemy_a.qoi", include_bytes!("../assets/enemy_a.qoi")),
+        disk::image("/sprites/enemy_b.qoi", include_bytes!("../assets/enemy_b.qoi")),
+    ];
+    static ref OCTOPUS_FRAMES: [Image; 2] = [
+        disk::image("/sprites/octopus_a.qoi", include_bytes!("../assets/octopus_a.qoi")),
+        disk::image("/sprites/octopus_b.qoi", include_bytes!("../assets/octopus_b.qoi")),
+    ];
+    static ref UFO_FRAMES: [Image; 1] = [
+        disk::image("/sprites/ufo.qoi", include_bytes!("../assets/ufo.qoi")),
+    ];
+    static ref EXPLOSION_FRAMES: [Image; 2] = [
+        disk::image("/sprites/explosion_a.qoi", include_bytes!("../assets/explosion_a.qoi")),
+        disk::image("/sprites/explosion_b.qoi", include_bytes!("../assets/explosion_b.qoi")),
+    ];
+    static ref PLAYER_FRAMES: [Image; 1] = [
+        disk::image("/sprites/player.bmp", include_bytes!("../assets/player.bmp")),
+    ];
+}
+
+/// Splits the screen into the status bar at the top, the log pane at the bottom and the game area
+/// in between.
+fn open_windows() {
+    let writer = screenwriter();
+    let (width, height) = (writer.width(), writer.height());
+
+    let status_bar = window::open(STATUS_BAR, Rect::new(0, 0, width, LINE_HEIGHT));
+    status_bar.set_cursor_enabled(false);
+    status_bar.set_colors((0xff, 0xd7, 0x00), (0x20, 0x20, 0x40));
+    status_bar.clear();
+
+    let log_height = LOG_LINES * LINE_HEIGHT;
+    window::open(LOG, Rect::new(0, height - log_height, width, log_height));
+}
+
+/// The part of the screen between the status bar and the log pane.
+fn game_area() -> Rect {
+    let height = screenwriter().height();
+    Rect::new(
+        0,
+        LINE_HEIGHT,
+        screenwriter().width(),
+        height - LINE_HEIGHT - LOG_LINES * LINE_HEIGHT,
+    )
+}
+
+/// Blanks the game area, leaving the status bar and log pane alone.
+fn clear_game_area() {
+    let area = game_area();
+    screenwriter().fill_rect(area.x, area.y, area.width, area.height, (0, 0, 0));
 }
 
 pub unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
@@ -103,97 +415,45 @@ pub unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static
     &mut *page_table_ptr // unsafe
 }
 
-const ENEMY_PATTERN: [(f64, f64); 38] = [
-    (2.0, 0.0),
-    (8.0, 0.0),
-    (3.0, 1.0),
-    (7.0, 1.0),
-    (2.0, 2.0),
-    (3.0, 2.0),
-    (4.0, 2.0),
-    (5.0, 2.0),
-    (6.0, 2.0),
-    (7.0, 2.0),
-    (8.0, 2.0),
-    (1.0, 3.0),
-    (2.0, 3.0),
-    (4.0, 3.0),
-    (5.0, 3.0),
-    (6.0, 3.0),
-    (8.0, 3.0),
-    (9.0, 3.0),
-    (0.0, 4.0),
-    (1.0, 4.0),
-    (2.0, 4.0),
-    (3.0, 4.0),
-    (4.0, 4.0),
-    (5.0, 4.0),
-    (6.0, 4.0),
-    (7.0, 4.0),
-    (8.0, 4.0),
-    (9.0, 4.0),
-    (10.0, 4.0),
-    (1.0, 5.0),
-    (2.0, 5.0),
-    (3.0, 5.0),
-    (4.0, 5.0),
-    (5.0, 5.0),
-    (6.0, 5.0),
-    (7.0, 5.0),
-    (8.0, 5.0),
-    (9.0, 5.0),
-];
-
-const PLAYER_PATTERN: [(f64, f64); 14] = [
-    (1.0, 0.0),
-    (2.0, 0.0),
-    (3.0, 0.0),
-    (4.0, 0.0),
-    (0.0, 1.0),
-    (1.0, 1.0),
-    (2.0, 1.0),
-    (3.0, 1.0),
-    (4.0, 1.0),
-    (5.0, 1.0),
-    (0.0, 2.0),
-    (1.0, 2.0),
-    (4.0, 2.0),
-    (5.0, 2.0),
-];
-
-fn round_f64_to_usize(value: f64) -> usize {
-    let int_part = value as isize; // Get the integer part
-    let frac_part = value - int_part as f64; // Subtract to get the fractional part
-    if frac_part >= 0.5 {
-        (int_part + 1) as usize
-    } else {
-        int_part as usize
-    }
-}
-fn draw_scaled_pattern(
-    writer: &mut ScreenWriter,
-    pattern: &[(f64, f64)],
-    top_left_x: usize,
-    top_left_y: usize,
-    scale_factor: f64, // Changed to f64
-    r: u8,
-    g: u8,
-    b: u8,
-) {
-    for &(offset_x, offset_y) in pattern.iter() {
-        for dx in 0..(scale_factor as usize) {
-            // Cast to usize for loop range
-            for dy in 0..(scale_factor as usize) {
-                // Cast to usize for loop range
-                let x = top_left_x + round_f64_to_usize(offset_x * scale_factor) + dx;
-                let y = top_left_y + round_f64_to_usize(offset_y * scale_factor) + dy;
-                writer.draw_pixel(x, y, r, g, b);
-            }
-        }
-    }
-}
+/// Each pixel of the sprite images becomes a square of this many screen pixels.
+const ENEMY_SCALE: usize = 3;
+const PLAYER_SCALE: usize = 6;
+
+// Sprites with a higher z are drawn on top of the ones below
+const BARRIER_Z: i32 = 0;
+const ENEMY_Z: i32 = 1;
+const PLAYER_Z: i32 = 1;
+const BULLET_Z: i32 = 2;
+const EXPLOSION_Z: i32 = 3;
+
+const STARTING_LIVES: u32 = 3;
+const MAX_LIVES: u32 = 6;
+/// Points between two extra lives.
+const EXTRA_LIFE_EVERY: u32 = 1000;
+/// Ticks the player is gone after being hit.
+const RESPAWN_TICKS: u32 = 20;
+/// Ticks of invulnerability after coming back, and how long each blink lasts during them.
+const INVULNERABLE_TICKS: u32 = 40;
+const BLINK_TICKS: u32 = 3;
+const PLAYER_COLOR: (u8, u8, u8) = (0xff, 0, 0);
+/// The life icons in the status bar are the player's sprite at this scale.
+const LIFE_ICON_SCALE: usize = 4;
+const LIFE_ICON_SPACING: usize = 8;
+
+/// Ticks between two shots while the space bar is held.
+const FIRE_COOLDOWN_TICKS: u32 = 4;
+
+const MAX_EXPLOSIONS: usize = 8;
+/// Timer ticks each frame of an explosion stays on screen.
+const EXPLOSION_FRAME_TICKS: usize = 4;
 
 fn start() {
+    place_player();
+    render();
+    screenwriter().present();
+}
+
+fn place_player() {
     let frame_info = screenwriter().info;
     let center_x = frame_info.width / 2;
     let center_y = frame_info.height - 100;
@@ -202,111 +462,52 @@ fn start() {
     let player_width = 40; // Width of the player
     let player_height = 40; // Height of the player
 
-    // Create and draw the player
     let mut player = PLAYER.lock();
     player.x = center_x - player_width / 2;
     player.y = center_y - player_height / 2;
-    player.draw(screenwriter());
-
-    let enemies_guard = ENEMIES.lock();
-    let mut enemies = enemies_guard.borrow_mut();
-
-    // Enemy and spacing dimensions
-    let enemy_width = 35;
-    let enemy_height = 35;
-    let horizontal_spacing = 10;
-    let vertical_spacing = 10;
-    let enemy_color = (0, 0, 0xff);
-
-    // Calculate the total width required for enemies including spacing
-    let total_enemies_width = (enemy_width + horizontal_spacing) * 15 - horizontal_spacing;
-    let start_x = (frame_info.width - total_enemies_width) / 2;
-
-    // Draw enemies with spacing
-    let mut writer = screenwriter();
-    for i in 0..ROWS {
-        for j in 0..15 {
-            let enemy_x = start_x + j * (enemy_width + horizontal_spacing);
-            let enemy_y = 50 + i * (enemy_height + vertical_spacing);
-            enemies[i][j] = Some(Enemy::new(
-                enemy_x,
-                enemy_y,
-                enemy_width,
-                enemy_height,
-                enemy_color,
-            ));
-            enemies[i][j].as_ref().unwrap().draw(&mut writer);
-        }
-    }
-
-    // Barrier dimensions
-    let barrier_width = 30;
-    let barrier_height = 20;
-    // grey
-    let barrier_color = (0x80, 0x80, 0x80);
-    let barrier_spacing = 20; // Updated spacing between barriers
-
-    // Calculate the total width required for barriers including new spacing
-    let total_barriers_width = (barrier_width + barrier_spacing) * BARRIER_COLS - barrier_spacing;
-    let start_x = (frame_info.width - total_barriers_width) / 2;
+}
 
-    let barriers_guard = BARRIERS.lock();
-    let mut barriers = barriers_guard.borrow_mut();
+fn tick() {
+    cursor::hide();
+    game_tick();
+    render();
+    window::tick();
+    cursor::show();
+    screenwriter().present();
+}
 
-    // Draw barriers with new spacing
-    for i in 0..BARRIER_ROWS {
-        for j in 0..BARRIER_COLS {
-            let mut barrier_x = start_x + j * (barrier_width + barrier_spacing);
-            if i % 2 == 0 {
-                barrier_x += 30;
-            } else {
-                barrier_x -= 30;
-            }
-            let barrier_y_offset = 200; // Increase this value to raise the barriers higher
-            let barrier_y =
-                frame_info.height - barrier_y_offset - i * (barrier_height + barrier_spacing);
-
-            barriers[i][j] = Some(Barrier::new(
-                barrier_x,
-                barrier_y,
-                barrier_width,
-                barrier_height,
-                barrier_color,
-            ));
-            barriers[i][j].as_ref().unwrap().draw(&mut writer);
-        }
+/// Moves the game along by one timer tick. Only playing and the pause between waves go on by
+/// themselves; the other states wait for a key.
+fn game_tick() {
+    let state = STATE.lock().clone();
+    match state {
+        GameState::Playing => playing_tick(),
+        GameState::LevelClear { ticks } => level_clear_tick(ticks),
+        _ => {}
     }
 }
 
-fn tick() {
-    if *GAMEOVER.lock() {
-        let mut tick_counter2 = TICK_COUNTER2.lock();
-        if *tick_counter2 > 5 {
-            display_game_over();
-            *tick_counter2 = 0;
-        } else {
-            *tick_counter2 += 1;
-        }
-        return;
-    }
-    if *WINNER.lock() {
-        let mut tick_counter2 = TICK_COUNTER2.lock();
-        if *tick_counter2 > 5 {
-            display_winner();
-            *tick_counter2 = 0;
-        } else {
-            *tick_counter2 += 1;
-        }
-        return;
-    }
+fn playing_tick() {
     display_score();
+    display_lives();
+    if respawn_tick() {
+        player_input();
+    }
     enemy_shoot();
+    ufo_tick();
     // Increment the tick counter
     let mut tick_counter1 = TICK_COUNTER1.lock();
     *tick_counter1 += 1;
-    if *tick_counter1 > 40 {
+    if *tick_counter1 > enemy_move_interval() {
         if !are_enemies_remaining() {
-            display_winner();
+            *tick_counter1 = 0;
+            if *LEVEL.lock() < FINAL_LEVEL {
+                set_state(GameState::LevelClear {
+                    ticks: LEVEL_CLEAR_TICKS,
+                });
+            } else {
+                finish_game(true);
+            }
             return;
         }
         enemy_movement();
@@ -324,59 +525,262 @@ fn tick() {
     } else {
         *tick_counter2 += 1;
     }
+    animate_explosions();
 }
 
-fn key(key: DecodedKey) {
-    let mut player = PLAYER.lock();
-    match key {
-        DecodedKey::RawKey(code) => {
-            let frame_info = screenwriter().info;
-            match code {
-                pc_keyboard::KeyCode::ArrowLeft if player.x > 0 => {
-                    // write!(Writer, "left").unwrap();
-                    let mut game_over = GAMEOVER.lock();
-                    let mut winner = WINNER.lock();
-                    if *game_over || *winner {
-                        reset_game();
-                        *game_over = false;
-                        *winner = false;
-                        *SCORE.lock() = 0;
-                    }
-                    player_move_left(&mut player);
-                }
-                pc_keyboard::KeyCode::ArrowRight if player.x + player.width < frame_info.width => {
-                    let mut game_over = GAMEOVER.lock();
-                    let mut winner = WINNER.lock();
-                    if *game_over || *winner {
-                        reset_game();
-                        *game_over = false;
-                        *winner = false;
-                        *SCORE.lock() = 0;
-                    }
-                    player_move_right(&mut player);
-                }
-                _ => {}
+/// Counts down the pause between two waves, then starts the next one.
+fn level_clear_tick(ticks: u32) {
+    if ticks > 0 {
+        // the message is already up, so no need to draw it again
+        *STATE.lock() = GameState::LevelClear { ticks: ticks - 1 };
+    } else {
+        next_wave();
+        set_state(GameState::Playing);
+    }
+}
+
+/// Switches to `state`, whose screen is drawn the next time the screen is brought up to date.
+/// Safe to call in the middle of the game logic, as it doesn't draw anything itself.
+fn set_state(state: GameState) {
+    *STATE.lock() = state;
+    *STATE_CHANGED.lock() = true;
+}
+
+/// The title screen with its menu.
+fn title() -> GameState {
+    GameState::Title(Menu::new(2))
+}
+
+/// Brings the screen up to date with the current state: the game itself while playing, or
+/// else the state's own screen whenever the state changed.
+fn render() {
+    let changed = core::mem::take(&mut *STATE_CHANGED.lock());
+    let state = STATE.lock().clone();
+    match state {
+        GameState::Playing => {
+            if changed {
+                redraw_game_area();
             }
+            render_scene();
         }
-        DecodedKey::Unicode(character) => {
-            if character == ' ' {
-                // Handle space bar press
-                let bullets_guard = BULLETS.lock();
-                let mut bullets = bullets_guard.borrow_mut();
-                // Add a new bullet if under the limit
-                if bullets.iter().filter(|x| x.is_some()).count() < 10 {
-                    if let Some(first_empty_slot) = bullets.iter_mut().find(|x| x.is_none()) {
-                        *first_empty_slot = Some(Bullet::new(
-                            player.x + player.width / 2,
-                            player.y - 5,
-                            5,
-                            5,
-                            //light blue
-                            (0xad, 0xd8, 0xe6),
-                        ));
-                    }
-                }
-            }
+        _ if !changed => {}
+        GameState::Title(menu) => display_title(menu),
+        GameState::Paused(menu) => display_paused(menu),
+        GameState::LevelClear { .. } => display_level_clear(),
+        GameState::HighScoreEntry { won, initials } => display_initials_prompt(won, &initials),
+        GameState::GameOver { won, menu } => display_game_over(won, menu),
+    }
+}
+
+fn key(key: DecodedKey) {
+    cursor::hide();
+    handle_key(key);
+    render();
+    cursor::show();
+    screenwriter().present();
+}
+
+fn mouse(event: MouseEvent) {
+    cursor::update(event);
+    screenwriter().present();
+}
+
+fn handle_key(key: DecodedKey) {
+    let state = STATE.lock().clone();
+    match state {
+        GameState::Title(menu) => title_key(menu, key),
+        GameState::Playing => playing_key(key),
+        GameState::Paused(menu) => paused_key(menu, key),
+        GameState::LevelClear { .. } => {}
+        GameState::HighScoreEntry { won, initials } => enter_initials(won, initials, key),
+        GameState::GameOver { won, menu } => game_over_key(won, menu, key),
+    }
+}
+
+/// Escape and P pause the game and carry on with it.
+fn is_pause_key(key: DecodedKey) -> bool {
+    matches!(
+        key,
+        DecodedKey::Unicode('\x1b' | 'p' | 'P') | DecodedKey::RawKey(KeyCode::Escape)
+    )
+}
+
+fn is_restart_key(key: DecodedKey) -> bool {
+    matches!(key, DecodedKey::Unicode('r' | 'R'))
+}
+
+/// Starts the game from the title menu, or changes the difficulty with the left and right arrows
+/// on its item.
+fn title_key(mut menu: Menu, key: DecodedKey) {
+    match menu.key(key) {
+        Action::Chosen(TITLE_START) => {
+            new_game();
+            return;
+        }
+        Action::Left(TITLE_DIFFICULTY) => {
+            let mut difficulty = DIFFICULTY.lock();
+            *difficulty = difficulty.previous();
+        }
+        Action::Right(TITLE_DIFFICULTY) | Action::Chosen(TITLE_DIFFICULTY) => {
+            let mut difficulty = DIFFICULTY.lock();
+            *difficulty = difficulty.next();
+        }
+        Action::Moved => {}
+        _ => return,
+    }
+    set_state(GameState::Title(menu));
+}
+
+/// Pauses the game. Moving and firing are polled from the key state every tick instead, so they
+/// work at the same time.
+fn playing_key(key: DecodedKey) {
+    if is_pause_key(key) {
+        set_state(GameState::Paused(Menu::new(PAUSE_ITEMS.len())));
+    }
+}
+
+fn paused_key(mut menu: Menu, key: DecodedKey) {
+    if is_pause_key(key) {
+        set_state(GameState::Playing);
+        return;
+    }
+    if is_restart_key(key) {
+        new_game();
+        return;
+    }
+    match menu.key(key) {
+        Action::Chosen(PAUSE_RESUME) => set_state(GameState::Playing),
+        Action::Chosen(PAUSE_RESTART) => new_game(),
+        Action::Chosen(PAUSE_QUIT) => set_state(title()),
+        Action::Moved => set_state(GameState::Paused(menu)),
+        _ => {}
+    }
+}
+
+/// Plays again with R or from the menu, or goes back to the title screen with escape.
+fn game_over_key(won: bool, mut menu: Menu, key: DecodedKey) {
+    if is_restart_key(key) {
+        new_game();
+        return;
+    }
+    if let DecodedKey::Unicode('\x1b') | DecodedKey::RawKey(KeyCode::Escape) = key {
+        set_state(title());
+        return;
+    }
+    match menu.key(key) {
+        Action::Chosen(GAME_OVER_PLAY_AGAIN) => new_game(),
+        Action::Chosen(GAME_OVER_TITLE) => set_state(title()),
+        Action::Moved => set_state(GameState::GameOver { won, menu }),
+        _ => {}
+    }
+}
+
+/// The title screen: the game's name and the menu to start a game, above the high scores.
+fn display_title(menu: Menu) {
+    let writer = screenwriter();
+    clear_game_area();
+    let area = game_area();
+    let width = writer.info.width;
+    let centered = |text: &str, style: TextStyle| width.saturating_sub(style.text_width(text)) / 2;
+
+    let mut y = area.y + area.height / 8;
+    let name = "SPACE INVADERS";
+    writer.set_position(centered(name, TITLE_STYLE), y);
+    writer.write_styled(name, TITLE_STYLE);
+    y += TITLE_STYLE.line_height() + LINE_HEIGHT;
+
+    let difficulty = format!("Difficulty: < {} >", DIFFICULTY.lock().name());
+    y = menu.draw(&["Start game", &difficulty], y);
+    y += LINE_HEIGHT;
+    let hint = "Up and down to choose, left and right to change, Enter to start";
+    writer.set_position(centered(hint, TextStyle::default()), y);
+    let _ = write!(writer, "{}", hint);
+    y += 2 * LINE_HEIGHT;
+
+    display_high_scores(y);
+}
+
+/// The pause menu, in a box in the middle of the game, which is drawn again on resuming.
+fn display_paused(menu: Menu) {
+    let writer = screenwriter();
+    let area = game_area();
+    let title = "PAUSED";
+    let box_width = PAUSED_STYLE
+        .text_width(title)
+        .max(20 * TextStyle::default().char_width());
+    let box_height = PAUSED_STYLE.line_height() + (PAUSE_ITEMS.len() + 1) * LINE_HEIGHT;
+    let x = area.width.saturating_sub(box_width) / 2;
+    let mut y = area.y + area.height.saturating_sub(box_height) / 2;
+    writer.fill_rect(
+        x.saturating_sub(LINE_HEIGHT),
+        y.saturating_sub(LINE_HEIGHT),
+        box_width + 2 * LINE_HEIGHT,
+        box_height + 2 * LINE_HEIGHT,
+        (0, 0, 0),
+    );
+    writer.set_position(
+        area.width.saturating_sub(PAUSED_STYLE.text_width(title)) / 2,
+        y,
+    );
+    writer.write_styled(title, PAUSED_STYLE);
+    y += PAUSED_STYLE.line_height() + LINE_HEIGHT;
+    menu.draw(&PAUSE_ITEMS, y);
+}
+
+/// The message between two waves.
+fn display_level_clear() {
+    let writer = screenwriter();
+    clear_game_area();
+    let area = game_area();
+    let width = writer.info.width;
+    let centered = |text: &str, style: TextStyle| width.saturating_sub(style.text_width(text)) / 2;
+
+    let level = *LEVEL.lock();
+    let mut y = area.y + area.height / 3;
+    let message = format!("WAVE {} CLEARED", level);
+    writer.set_position(centered(&message, WINNER_STYLE), y);
+    writer.write_styled(&message, WINNER_STYLE);
+    y += WINNER_STYLE.line_height();
+    let next = format!("Get ready for wave {}", level + 1);
+    writer.set_position(centered(&next, TextStyle::default()), y);
+    let _ = write!(writer, "{}", next);
+}
+
+/// Moves the player while an arrow key is held and fires while the space bar is held. The keys
+/// are read from the key state table, so moving and firing work at the same time.
+fn player_input() {
+    let mut player = PLAYER.lock();
+    if is_key_down(KeyCode::ArrowLeft) {
+        player_move_left(&mut player);
+    }
+    if is_key_down(KeyCode::ArrowRight) {
+        player_move_right(&mut player);
+    }
+
+    let mut fire_cooldown = FIRE_COOLDOWN.lock();
+    if *fire_cooldown > 0 {
+        *fire_cooldown -= 1;
+    } else if is_key_down(KeyCode::Spacebar) {
+        fire(&player);
+        *fire_cooldown = FIRE_COOLDOWN_TICKS;
+    }
+}
+
+fn fire(player: &Player) {
+    let bullets_guard = BULLETS.lock();
+    let mut bullets = bullets_guard.borrow_mut();
+    // Add a new bullet if under the limit
+    if bullets.iter().filter(|x| x.is_some()).count() < 10 {
+        if let Some(first_empty_slot) = bullets.iter_mut().find(|x| x.is_none()) {
+            *first_empty_slot = Some(Bullet::new(
+                player.x + player.width / 2,
+                player.y - 5,
+                5,
+                5,
+                //light blue
+                (0xad, 0xd8, 0xe6),
+            ));
+            sfx::play(Effect::Shoot);
         }
     }
 }
@@ -386,7 +790,7 @@ pub struct Player {
     pub y: usize,
     pub width: usize,
     pub height: usize,
-    pub color: (u8, u8, u8),
+    pub sprite: Sprite,
 }
 
 impl Player {
@@ -396,60 +800,27 @@ impl Player {
             y,
             width,
             height,
-            color,
-        }
-    }
-
-    pub fn draw(&self, writer: &mut ScreenWriter) {
-        draw_scaled_pattern(
-            writer,
-            &PLAYER_PATTERN,
-            self.x,
-            self.y,
-            6.6,
-            self.color.0,
-            self.color.1,
-            self.color.2,
-        );
-    }
-
-    pub fn erase(&self, writer: &mut ScreenWriter, background_color: (u8, u8, u8)) {
-        for dx in 0..self.width {
-            for dy in 0..self.height {
-                writer.draw_pixel(
-                    self.x + dx,
-                    self.y + dy,
-                    background_color.0,
-                    background_color.1,
-                    background_color.2,
-                );
-            }
+            sprite: Sprite::animated(&*PLAYER_FRAMES, PLAYER_SCALE, color).z(PLAYER_Z),
         }
     }
 }
 
 fn player_move_left(player: &mut Player) {
-    let mut writer = screenwriter();
-    player.erase(&mut writer, (0, 0, 0));
     if player.x >= 10 {
         player.x -= 10;
     } else {
         player.x = 0; // Prevent overflow by setting to minimum value
     }
-    player.draw(&mut writer);
 }
 
 fn player_move_right(player: &mut Player) {
-    let mut writer = screenwriter();
     // get screen size
     let frame_info = screenwriter().info;
-    player.erase(&mut writer, (0, 0, 0));
     if player.x + player.width < frame_info.width - 10 {
         player.x += 10;
     } else {
         player.x = frame_info.width - 10 - player.width; // Prevent overflow by setting to maximum value
     }
-    player.draw(&mut writer);
 }
 
 struct Bullet {
@@ -457,7 +828,7 @@ struct Bullet {
     y: usize,
     width: usize,
     height: usize,
-    color: (u8, u8, u8),
+    sprite: Sprite,
 }
 
 impl Bullet {
@@ -467,34 +838,7 @@ impl Bullet {
             y,
             width,
             height,
-            color,
-        }
-    }
-
-    pub fn draw(&self, writer: &mut ScreenWriter) {
-        for dx in 0..self.width {
-            for dy in 0..self.height {
-                writer.draw_pixel(
-                    self.x + dx,
-                    self.y + dy,
-                    self.color.0,
-                    self.color.1,
-                    self.color.2,
-                );
-            }
-        }
-    }
-    pub fn erase(&self, writer: &mut ScreenWriter, background_color: (u8, u8, u8)) {
-        for dx in 0..self.width {
-            for dy in 0..self.height {
-                writer.draw_pixel(
-                    self.x + dx,
-                    self.y + dy,
-                    background_color.0,
-                    background_color.1,
-                    background_color.2,
-                );
-            }
+            sprite: Sprite::solid(width, height, color).z(BULLET_Z),
         }
     }
 }
@@ -520,21 +864,8 @@ fn check_collision_between_player_bullet_and_enemy(bullet: &Bullet, enemy: &Enem
         || bullet_bottom < enemy.y)
 }
 
-// check collisioin between bullet and barrier
-fn check_collision_between_player_bullet_and_barrier(bullet: &Bullet, barrier: &Barrier) -> bool {
-    let bullet_right = bullet.x + bullet.width;
-    let bullet_bottom = bullet.y + bullet.height;
-    let barrier_right = barrier.x + barrier.width;
-    let barrier_bottom = barrier.y + barrier.height;
-
-    !(bullet.x > barrier_right
-        || bullet_right < barrier.x
-        || bullet.y > barrier_bottom
-        || bullet_bottom < barrier.y)
-}
-
 fn bullet_movement() {
-    let mut writer = screenwriter();
+    let writer = screenwriter();
     let bullets_guard = BULLETS.lock();
     let mut bullets = bullets_guard.borrow_mut();
     let enemies_guard = ENEMIES.lock();
@@ -544,27 +875,41 @@ fn bullet_movement() {
 
     let mut bullets_to_remove = Vec::new();
     let mut enemies_to_remove = Vec::new();
-    let mut barriers_to_remove = Vec::new();
 
     for (i, bullet_opt) in bullets.iter_mut().enumerate() {
         if let Some(bullet) = bullet_opt {
-            bullet.erase(&mut writer, (0, 0, 0));
-
             // Check if bullet goes out of screen or collides
-            if bullet.y <= 30 {
+            if bullet.y <= game_area().y + 30 {
+                bullet.sprite.erase(writer);
                 bullets_to_remove.push(i); // Bullet goes out of screen
             } else {
+                let from = bullet.y + bullet.height;
                 bullet.y -= 30; // Move bullet
+                                // Check if bullet runs into a barrier on its way up
+                if shoot_barriers(&mut barriers[..], bullet.x, bullet.width, from, bullet.y) {
+                    bullet.sprite.erase(writer);
+                    bullets_to_remove.push(i);
+                    continue;
+                }
+                if shoot_ufo(bullet) {
+                    bullet.sprite.erase(writer);
+                    bullets_to_remove.push(i);
+                    continue;
+                }
                 let mut hit = false;
 
                 for (j, enemy_opt) in enemies.iter_mut().enumerate() {
                     for (k, enemy) in enemy_opt.iter_mut().enumerate() {
                         if let Some(enemy) = enemy {
                             if check_collision_between_player_bullet_and_enemy(bullet, enemy) {
-                                enemy_killed();
+                                enemy_killed(enemy.kind.points());
                                 enemies_to_remove.push((j, k));
                                 hit = true;
-                                enemy.erase(&mut writer, (0, 0, 0));
+                                // the bullet is on top, so it goes first
+                                bullet.sprite.erase(writer);
+                                enemy.sprite.erase(writer);
+                                spawn_explosion(enemy.x, enemy.y);
+                                sfx::play(Effect::Explosion);
                                 break;
                             }
                         }
@@ -575,21 +920,6 @@ fn bullet_movement() {
                         break;
                     }
                 }
-
-                // Check if bullet collides with barrier
-                for (j, barrier_row) in barriers.iter_mut().enumerate() {
-                    for (k, barrier_opt) in barrier_row.iter_mut().enumerate() {
-                        if let Some(barrier) = barrier_opt {
-                            if check_collision_between_player_bullet_and_barrier(bullet, barrier) {
-                                barriers_to_remove.push((k, j));
-                                bullet.erase(&mut writer, (0, 0, 0));
-                                barrier.erase(&mut writer, (0, 0, 0));
-                                bullets_to_remove.push(i);
-                                break;
-                            }
-                        }
-                    }
-                }
             }
         }
     }
@@ -603,88 +933,104 @@ fn bullet_movement() {
     for (i, j) in enemies_to_remove.iter() {
         enemies[*i][*j] = None;
     }
+}
 
-    // Remove hit barriers
-    for (i, j) in barriers_to_remove.iter() {
-        barriers[*j][*i] = None;
-    }
+/// Sends a shot through the barriers from screen row `from` to `to`, blowing a hole in the first
+/// one it runs into. Returns whether it hit one.
+fn shoot_barriers(
+    barriers: &mut [Option<Barrier>],
+    x: usize,
+    width: usize,
+    from: usize,
+    to: usize,
+) -> bool {
+    barriers
+        .iter_mut()
+        .flatten()
+        .any(|barrier| barrier.shoot(x, width, from, to))
+}
 
-    // Redraw remaining bullets
-    for bullet_opt in bullets.iter() {
-        if let Some(bullet) = bullet_opt {
-            bullet.draw(&mut writer);
+pub struct Enemy {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub kind: EnemyKind,
+    pub sprite: Sprite,
+}
+
+/// The kinds of invader, a row or two of each, worth more the higher up they start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnemyKind {
+    Squid,
+    Crab,
+    Octopus,
+}
+
+impl EnemyKind {
+    /// The kind filling row `row`, counting from the top.
+    fn for_row(row: usize) -> EnemyKind {
+        match row {
+            0 => EnemyKind::Squid,
+            1 | 2 => EnemyKind::Crab,
+            _ => EnemyKind::Octopus,
         }
     }
 
-    // Redraw remaining enemies
-    for enemy_row in enemies.iter_mut() {
-        for enemy_opt in enemy_row {
-            if let Some(enemy) = enemy_opt {
-                enemy.draw(&mut writer);
-            }
+    fn points(self) -> u32 {
+        match self {
+            EnemyKind::Squid => 30,
+            EnemyKind::Crab => 20,
+            EnemyKind::Octopus => 10,
         }
     }
 
-    // Redraw remaining barriers
-    for barrier_row in barriers.iter_mut() {
-        for barrier_opt in barrier_row {
-            if let Some(barrier) = barrier_opt {
-                barrier.draw(&mut writer);
-            }
+    fn color(self) -> (u8, u8, u8) {
+        match self {
+            EnemyKind::Squid => (0xff, 0x40, 0xff),
+            EnemyKind::Crab => (0x40, 0x80, 0xff),
+            EnemyKind::Octopus => (0x40, 0xff, 0x40),
         }
     }
-}
 
-#[derive(Copy, Clone)]
-pub struct Enemy {
-    pub x: usize,
-    pub y: usize,
-    pub width: usize,
-    pub height: usize,
-    pub color: (u8, u8, u8),
+    fn frames(self) -> &'static [Image] {
+        match self {
+            EnemyKind::Squid => &*SQUID_FRAMES,
+            EnemyKind::Crab => &*CRAB_FRAMES,
+            EnemyKind::Octopus => &*OCTOPUS_FRAMES,
+        }
+    }
 }
 
 const ARRAY_REPEAT_VALUE: Option<Enemy> = None;
+const EMPTY_ENEMY_ROW: [Option<Enemy>; 15] = [ARRAY_REPEAT_VALUE; 15];
 fn init_enemy_array() -> [[Option<Enemy>; 15]; ROWS] {
-    [[ARRAY_REPEAT_VALUE; 15]; ROWS] // Initialize all enemies to None
+    [EMPTY_ENEMY_ROW; ROWS] // Initialize all enemies to None
 }
 
 impl Enemy {
-    pub fn new(x: usize, y: usize, width: usize, height: usize, color: (u8, u8, u8)) -> Self {
+    pub fn new(x: usize, y: usize, width: usize, height: usize, kind: EnemyKind) -> Self {
         Enemy {
             x,
             y,
             width,
             height,
-            color,
+            kind,
+            sprite: Sprite::animated(kind.frames(), ENEMY_SCALE, kind.color()).z(ENEMY_Z),
         }
     }
+}
 
-    pub fn draw(&self, writer: &mut ScreenWriter) {
-        draw_scaled_pattern(
-            writer,
-            &ENEMY_PATTERN,
-            self.x,
-            self.y,
-            3.0,
-            self.color.0,
-            self.color.1,
-            self.color.2,
-        );
-    }
-    pub fn erase(&self, writer: &mut ScreenWriter, background_color: (u8, u8, u8)) {
-        for dx in 0..self.width {
-            for dy in 0..self.height {
-                writer.draw_pixel(
-                    self.x + dx,
-                    self.y + dy,
-                    background_color.0,
-                    background_color.1,
-                    background_color.2,
-                );
-            }
-        }
-    }
+/// Enemy steps come this often at the most, however few enemies are left.
+const MIN_MOVE_INTERVAL: u32 = 4;
+
+/// How far the tick counter goes before the enemies step: the wave's interval, shrinking as
+/// they are shot down, so the last few rush about like in the arcade original.
+fn enemy_move_interval() -> u32 {
+    let enemies_guard = ENEMIES.lock();
+    let alive = enemies_guard.borrow().iter().flatten().flatten().count() as u32;
+    let total = (ROWS * 15) as u32;
+    (current_wave().move_interval * alive / total).max(MIN_MOVE_INTERVAL)
 }
 
 fn enemy_movement() {
@@ -692,6 +1038,7 @@ fn enemy_movement() {
     let mut enemies = enemies_guard.borrow_mut();
     let mut enemy_dx = ENEMY_DX.lock();
     let frame_info = screenwriter().info;
+    let step = current_wave().step as i32;
 
     // Find the positions of the foremost enemies
     let (first_x, last_x) = find_foremost_enemies_positions(&*enemies);
@@ -702,33 +1049,32 @@ fn enemy_movement() {
         move_enemies_down(&mut enemies, 100); // Move all enemies down by 50 pixels
     } else {
         // Continue moving enemies in the current horizontal direction
-        let mut writer = screenwriter();
-        for enemy_opt in enemies.iter_mut() {
-            for enemy in enemy_opt.iter_mut() {
-                if let Some(enemy) = enemy {
-                    enemy.erase(&mut writer, (0, 0, 0));
-                    enemy.x = (enemy.x as i32 + *enemy_dx * 15) as usize; // Move the enemy
-                    enemy.draw(&mut writer);
-                }
-            }
+        for enemy in enemies.iter_mut().flatten().flatten() {
+            enemy.x = (enemy.x as i32 + *enemy_dx * step) as usize; // Move the enemy
+        }
+    }
+
+    // Switch pose with every step, like the arcade original
+    for enemy in enemies.iter_mut().flatten().flatten() {
+        enemy.sprite.next_frame();
+    }
+
+    // Enemies low enough to reach the barriers wipe out whatever they march over
+    let barriers_guard = BARRIERS.lock();
+    let mut barriers = barriers_guard.borrow_mut();
+    for enemy in enemies.iter().flatten().flatten() {
+        for barrier in barriers.iter_mut().flatten() {
+            barrier.clear_rect(enemy.x, enemy.y, enemy.width, enemy.height);
         }
     }
 }
 
 fn move_enemies_down(enemies: &mut [[Option<Enemy>; 15]; ROWS], down_step: usize) {
-    let mut writer = screenwriter();
-    for enemy_opt in enemies.iter_mut() {
-        for enemy in enemy_opt.iter_mut() {
-            if let Some(enemy) = enemy {
-                enemy.erase(&mut writer, (0, 0, 0));
-                enemy.y += down_step; // Move the enemy down
-                let frame_info = screenwriter().info;
-                if enemy.y > frame_info.height - 100 - 50 {
-                    let mut is_game_over = GAMEOVER.lock();
-                    *is_game_over = true;
-                }
-                enemy.draw(&mut writer);
-            }
+    for enemy in enemies.iter_mut().flatten().flatten() {
+        enemy.y += down_step; // Move the enemy down
+        let frame_info = screenwriter().info;
+        if enemy.y > frame_info.height - 100 - 50 {
+            end_game();
         }
     }
 }
@@ -738,7 +1084,7 @@ struct EnemyBullet {
     y: usize,
     width: usize,
     height: usize,
-    color: (u8, u8, u8),
+    sprite: Sprite,
 }
 
 impl EnemyBullet {
@@ -748,34 +1094,7 @@ impl EnemyBullet {
             y,
             width,
             height,
-            color,
-        }
-    }
-
-    pub fn draw(&self, writer: &mut ScreenWriter) {
-        for dx in 0..self.width {
-            for dy in 0..self.height {
-                writer.draw_pixel(
-                    self.x + dx,
-                    self.y + dy,
-                    self.color.0,
-                    self.color.1,
-                    self.color.2,
-                );
-            }
-        }
-    }
-    pub fn erase(&self, writer: &mut ScreenWriter, background_color: (u8, u8, u8)) {
-        for dx in 0..self.width {
-            for dy in 0..self.height {
-                writer.draw_pixel(
-                    self.x + dx,
-                    self.y + dy,
-                    background_color.0,
-                    background_color.1,
-                    background_color.2,
-                );
-            }
+            sprite: Sprite::solid(width, height, color).z(BULLET_Z),
         }
     }
 }
@@ -797,6 +1116,13 @@ fn enemy_shoot() {
     let enemy_bullets_guard = ENEMY_BULLETS.lock();
     let mut enemy_bullets = enemy_bullets_guard.borrow_mut();
 
+    let mut cooldown = ENEMY_FIRE_COOLDOWN.lock();
+    if *cooldown > 0 {
+        *cooldown -= 1;
+        return;
+    }
+    *cooldown = current_wave().fire_interval - 1;
+
     if let Some((x, y)) = select_random_enemy_position(&*enemies) {
         // Add a new bullet if under the limit
         if enemy_bullets.iter().filter(|x| x.is_some()).count() < 10 {
@@ -807,78 +1133,62 @@ fn enemy_shoot() {
     }
 }
 
+/// Picks a column with enemies left in it at random, and returns where the lowest enemy in that
+/// column is, as only the enemy at the front of a column can fire.
 fn select_random_enemy_position(enemies: &[[Option<Enemy>; 15]]) -> Option<(usize, usize)> {
-    let mut available_enemies = Vec::new();
-
-    for (j, enemy_opt) in enemies[0].iter().enumerate() {
-        if enemy_opt.is_some() {
-            available_enemies.push(j);
-        }
-    }
-
-    if available_enemies.is_empty() {
-        None
-    } else {
-        // Use a simple counter to select an enemy
-        let counter = *TICK_COUNTER1.lock();
-        let enemy_index = counter as usize % available_enemies.len();
-        let enemy_col = available_enemies[enemy_index];
-        enemies[0][enemy_col]
-            .as_ref()
+    let lowest_in_column = |column: usize| {
+        enemies
+            .iter()
+            .rev()
+            .find_map(|row| row[column].as_ref())
             .map(|enemy| (enemy.x, enemy.y))
+    };
+    let columns: Vec<usize> = (0..15)
+        .filter(|&column| lowest_in_column(column).is_some())
+        .collect();
+    if columns.is_empty() {
+        return None;
     }
+    let column = columns[rand::below(columns.len() as u32) as usize];
+    lowest_in_column(column)
 }
 
 fn enemy_bullet_movement() {
-    let mut writer = screenwriter();
+    let writer = screenwriter();
     let enemy_bullets_guard = ENEMY_BULLETS.lock();
     let mut enemy_bullets = enemy_bullets_guard.borrow_mut();
     let barriers_guard = BARRIERS.lock();
     let mut barriers = barriers_guard.borrow_mut();
 
     let mut bullets_to_remove = Vec::new();
-    let mut barriers_to_remove = Vec::new();
 
     let player = PLAYER.lock();
 
     for (i, bullet_opt) in enemy_bullets.iter_mut().enumerate() {
         if let Some(bullet) = bullet_opt {
-            bullet.erase(&mut writer, (0, 0, 0));
-            let mut hit = false;
             // Check for collision with player
-            if check_collision_between_enemy_bullet_and_player(&bullet, &player) {
-                let mut is_game_over = GAMEOVER.lock();
-                *is_game_over = true;
+            if check_collision_between_enemy_bullet_and_player(&bullet, &player)
+                && player_hit(player.x, player.y)
+            {
+                bullets_to_remove.push(i);
+                bullet.sprite.erase(writer);
+                continue;
             }
 
-            // Check if bullet collides with barrier
-            for (j, barrier_row) in barriers.iter().enumerate() {
-                for (k, barrier_opt) in barrier_row.iter().enumerate() {
-                    if let Some(barrier) = barrier_opt {
-                        if check_collision_between_enemy_bullet_and_barrier(bullet, barrier) {
-                            hit = true;
-                            barriers_to_remove.push((k, j));
-                            bullets_to_remove.push(i);
-                            barrier.erase(&mut writer, (0, 0, 0));
-                            break;
-                        }
-                    }
-                }
+            // Check if bullet runs into a barrier on its way down
+            let to = bullet.y + bullet.height + 30;
+            if shoot_barriers(&mut barriers[..], bullet.x, bullet.width, bullet.y, to) {
+                bullets_to_remove.push(i);
+                bullet.sprite.erase(writer);
+                continue;
             }
 
             // Check if bullet goes off-screen
-            let frame_info = screenwriter().info;
-            if bullet.y + bullet.height + 30 >= frame_info.height {
+            if to >= game_area().bottom() {
+                bullet.sprite.erase(writer);
                 bullets_to_remove.push(i); // Bullet goes off the bottom of the screen
             } else {
-                if !hit {
-                    bullet.y += 30; // Move bullet downwards
-
-                    // Redraw the bullet at its new position
-                    bullet.draw(&mut writer);
-                } else {
-                    bullet.erase(&mut writer, (0, 0, 0));
-                }
+                bullet.y += 30; // Move bullet downwards
             }
         }
     }
@@ -887,27 +1197,6 @@ fn enemy_bullet_movement() {
     for &bullet_index in bullets_to_remove.iter().rev() {
         enemy_bullets[bullet_index] = None;
     }
-
-    // Remove hit barriers
-    for (i, j) in barriers_to_remove.iter() {
-        barriers[*j][*i] = None;
-    }
-
-    // Redraw remaining bullets
-    for bullet_opt in enemy_bullets.iter() {
-        if let Some(bullet) = bullet_opt {
-            bullet.draw(&mut writer);
-        }
-    }
-
-    // Redraw remaining barriers
-    for barrier_row in barriers.iter_mut() {
-        for barrier_opt in barrier_row.iter_mut() {
-            if let Some(barrier) = barrier_opt {
-                barrier.draw(&mut writer);
-            }
-        }
-    }
 }
 
 fn check_collision_between_enemy_bullet_and_player(bullet: &EnemyBullet, player: &Player) -> bool {
@@ -922,21 +1211,6 @@ fn check_collision_between_enemy_bullet_and_player(bullet: &EnemyBullet, player:
         || bullet_bottom < player.y)
 }
 
-fn check_collision_between_enemy_bullet_and_barrier(
-    bullet: &EnemyBullet,
-    barrier: &Barrier,
-) -> bool {
-    let bullet_right = bullet.x + bullet.width;
-    let bullet_bottom = bullet.y + bullet.height;
-    let barrier_right = barrier.x + barrier.width;
-    let barrier_bottom = barrier.y + barrier.height;
-
-    !(bullet.x > barrier_right
-        || bullet_right < barrier.x
-        || bullet.y > barrier_bottom
-        || bullet_bottom < barrier.y)
-}
-
 fn find_foremost_enemies_positions(enemies: &[[Option<Enemy>; 15]]) -> (usize, usize) {
     let mut first_x = usize::MAX;
     let mut last_x = 0;
@@ -958,126 +1232,233 @@ fn find_foremost_enemies_positions(enemies: &[[Option<Enemy>; 15]]) -> (usize, u
     (first_x, last_x)
 }
 
-#[derive(Copy, Clone)]
-struct Barrier {
-    x: usize,
-    y: usize,
-    width: usize,
-    height: usize,
-    color: (u8, u8, u8),
+fn init_barrier_array() -> [Option<Barrier>; BARRIER_COUNT] {
+    const ARRAY_REPEAT_VALUE: Option<Barrier> = None;
+    [ARRAY_REPEAT_VALUE; BARRIER_COUNT]
 }
 
-impl Barrier {
-    pub fn new(x: usize, y: usize, width: usize, height: usize, color: (u8, u8, u8)) -> Self {
-        Barrier {
-            x,
-            y,
-            width,
-            height,
-            color,
-        }
+fn display_score() {
+    // Rewrite the status bar from its first column, erasing whatever was left after the score
+    let score = SCORE.lock();
+    let _ = write!(
+        window::Writer(STATUS_BAR),
+        "\x1b[H Score: {}   Level: {} ({})\x1b[K",
+        *score,
+        *LEVEL.lock(),
+        DIFFICULTY.lock().name()
+    );
+}
+
+/// Ends the game when the player is out of lives or the enemies land, playing the game over
+/// tune. Only the first call does anything, as it can happen more than once in a tick.
+fn end_game() {
+    if matches!(*STATE.lock(), GameState::Playing) {
+        sfx::play(Effect::GameOver);
+        finish_game(false);
     }
+}
 
-    pub fn draw(&self, writer: &mut ScreenWriter) {
-        for dx in 0..self.width {
-            for dy in 0..self.height {
-                writer.draw_pixel(
-                    self.x + dx,
-                    self.y + dy,
-                    self.color.0,
-                    self.color.1,
-                    self.color.2,
-                );
-            }
-        }
+/// Leaves the game for the initials prompt if the score made the high-score table, or else
+/// straight for the end screen.
+fn finish_game(won: bool) {
+    if highscores::qualifies(*SCORE.lock()) {
+        set_state(GameState::HighScoreEntry {
+            won,
+            initials: Vec::new(),
+        });
+    } else {
+        set_state(game_over(won));
     }
-    pub fn erase(&self, writer: &mut ScreenWriter, background_color: (u8, u8, u8)) {
-        for dx in 0..self.width {
-            for dy in 0..self.height {
-                writer.draw_pixel(
-                    self.x + dx,
-                    self.y + dy,
-                    background_color.0,
-                    background_color.1,
-                    background_color.2,
-                );
-            }
-        }
+}
+
+fn game_over(won: bool) -> GameState {
+    GameState::GameOver {
+        won,
+        menu: Menu::new(GAME_OVER_ITEMS.len()),
     }
 }
 
-fn init_barrier_array() -> [[Option<Barrier>; BARRIER_COLS]; BARRIER_ROWS] {
-    const ARRAY_REPEAT_VALUE: Option<Barrier> = None;
-    let mut barriers = [[ARRAY_REPEAT_VALUE; BARRIER_COLS]; BARRIER_ROWS];
-    // Alternatively, you can use a loop to initialize each element to None
-    for barrier_row in barriers.iter_mut() {
-        for barrier in barrier_row.iter_mut() {
-            *barrier = None;
+/// Types the player's initials for the high-score table: letters, backspace, and enter once
+/// there is at least one letter, which puts the score in the table.
+fn enter_initials(won: bool, mut initials: Vec<u8>, key: DecodedKey) {
+    match key {
+        DecodedKey::Unicode(c) if c.is_ascii_alphabetic() && initials.len() < INITIALS_LEN => {
+            initials.push(c.to_ascii_uppercase() as u8);
         }
+        DecodedKey::Unicode('\x08') => {
+            initials.pop();
+        }
+        DecodedKey::Unicode('\n') if !initials.is_empty() => {
+            let mut padded = [b' '; INITIALS_LEN];
+            padded[..initials.len()].copy_from_slice(&initials);
+            let entry = Entry {
+                initials: padded,
+                score: *SCORE.lock(),
+            };
+            *NEW_RANK.lock() = highscores::insert(entry);
+            set_state(game_over(won));
+            return;
+        }
+        _ => return,
     }
-    barriers
+    set_state(GameState::HighScoreEntry { won, initials });
 }
 
-fn display_score() {
-    let writer = screenwriter();
-
-    // Set the cursor position for score display at the top left
-    let score_display_x = 10; // A small margin from the left edge
-    let score_display_y = 10; // A small margin from the top edge
+fn enemy_killed(points: u32) {
+    let mut score = SCORE.lock();
+    *score += points;
+    award_extra_lives(*score);
+}
 
-    // Clear the score display area if needed, or draw over it directly
+/// Gives a life for every `EXTRA_LIFE_EVERY` points, up to `MAX_LIVES`.
+fn award_extra_lives(score: u32) {
+    let mut next_extra_life = NEXT_EXTRA_LIFE.lock();
+    while score >= *next_extra_life {
+        *next_extra_life += EXTRA_LIFE_EVERY;
+        let mut lives = LIVES.lock();
+        if *lives < MAX_LIVES {
+            *lives += 1;
+            let _ = writeln!(window::Writer(LOG), "Extra life!");
+        }
+    }
+}
 
-    // Set the cursor position
-    writer.set_position(score_display_x, score_display_y);
+/// Takes a life when an enemy bullet hits the player at (`x`, `y`), unless they are still
+/// blinking after coming back. With the last life the game is over; otherwise the player
+/// explodes and comes back after a moment. Returns whether the hit counted.
+fn player_hit(x: usize, y: usize) -> bool {
+    if *RESPAWN.lock() > 0 || *INVULNERABLE.lock() > 0 {
+        return false;
+    }
+    let mut lives = LIVES.lock();
+    *lives = lives.saturating_sub(1);
+    if *lives == 0 {
+        drop(lives);
+        end_game();
+        return true;
+    }
+    spawn_explosion(x, y);
+    sfx::play(Effect::Explosion);
+    *RESPAWN.lock() = RESPAWN_TICKS;
+    true
+}
 
-    // Get and display the current score
-    let score = SCORE.lock();
-    let _ = write!(writer, "Score: {}", *score);
+/// Counts down the player's respawn, putting them back in the middle, blinking, when it's over,
+/// and then their invulnerability. Returns whether the player is in play.
+fn respawn_tick() -> bool {
+    let mut respawn = RESPAWN.lock();
+    if *respawn > 0 {
+        *respawn -= 1;
+        if *respawn == 0 {
+            place_player();
+            *INVULNERABLE.lock() = INVULNERABLE_TICKS;
+        }
+        return false;
+    }
+    let mut invulnerable = INVULNERABLE.lock();
+    *invulnerable = invulnerable.saturating_sub(1);
+    true
 }
 
-fn enemy_killed() {
-    // Increment the score by 10 for each enemy killed
-    let mut score = SCORE.lock();
-    *score += 10;
+/// Whether the player is on screen: not while respawning, and every other blink while
+/// invulnerable.
+fn player_visible() -> bool {
+    let invulnerable = *INVULNERABLE.lock();
+    *RESPAWN.lock() == 0 && (invulnerable == 0 || (invulnerable / BLINK_TICKS) & 1 == 0)
 }
 
-fn display_game_over() {
+/// Draws a small ship for each life left at the right end of the status bar.
+fn display_lives() {
     let writer = screenwriter();
-    writer.clear(); // Clear the entire screen
-
-    // set player to None
-    // let mut player = PLAYER.lock();
-    // player.erase(&mut writer, (0, 0, 0));
-    // *player = Player::new(0, 0, 0, 0, (0, 0, 0));
+    let image = &PLAYER_FRAMES[0];
+    let width = image.width() * LIFE_ICON_SCALE;
+    let y = LINE_HEIGHT.saturating_sub(image.height() * LIFE_ICON_SCALE) / 2;
+    for i in 0..*LIVES.lock() as usize {
+        let Some(x) = writer
+            .width()
+            .checked_sub((i + 1) * (width + LIFE_ICON_SPACING))
+        else {
+            break;
+        };
+        writer.draw_image_tinted(image, x as isize, y as isize, LIFE_ICON_SCALE, PLAYER_COLOR);
+    }
+}
 
-    // Set the position for the Game Over message
-    let message_x = writer.info.width / 2 - 40; // Adjust as needed
-    let message_y = writer.info.height / 2;
-    writer.set_position(message_x, message_y);
-    let _ = write!(writer, "GAME OVER");
-    writer.set_position(message_x - 80, message_y + 20); // Adjust Y position for next line
-    let _ = write!(writer, "Move left or right to retry");
+/// The end of a game: the score's place in the table, and what to do next.
+fn display_game_over(won: bool, menu: Menu) {
+    let mut y = display_end_message(won);
+    y = menu.draw(&GAME_OVER_ITEMS, y);
+    y += LINE_HEIGHT;
+    let writer = screenwriter();
+    let hint = "R to play again, Esc for the title screen";
+    let hint_width = TextStyle::default().text_width(hint);
+    writer.set_position(writer.info.width.saturating_sub(hint_width) / 2, y);
+    let _ = write!(writer, "{}", hint);
+    y += 2 * LINE_HEIGHT;
 
-    // // Display the Retry message
-    // writer.set_position(message_x - 30, message_y + 20); // Adjust Y position for next line
-    // let _ = write!(writer, "Press R to Retry");
+    display_high_scores(y);
 }
 
-fn display_winner() {
-    let mut winner = WINNER.lock();
-    *winner = true;
+/// Asks for the player's initials for the high-score table, showing the ones typed so far.
+fn display_initials_prompt(won: bool, initials: &[u8]) {
     let writer = screenwriter();
-    writer.clear(); // Clear the screen
+    let width = writer.info.width;
+    let centered = |text: &str, style: TextStyle| width.saturating_sub(style.text_width(text)) / 2;
+
+    let mut y = display_end_message(won);
+    let mut typed = [b'_'; INITIALS_LEN];
+    typed[..initials.len()].copy_from_slice(initials);
+    let typed = core::str::from_utf8(&typed).unwrap_or("");
+    let prompt = format!("New high score! Type your initials: {}", typed);
+    writer.set_position(centered(&prompt, NEW_HIGH_SCORE_STYLE), y);
+    writer.write_styled(&prompt, NEW_HIGH_SCORE_STYLE);
+    y += LINE_HEIGHT;
+    let done = "Press Enter when done";
+    writer.set_position(centered(done, TextStyle::default()), y);
+    let _ = write!(writer, "{}", done);
+}
 
-    // Set the position for the winning message
-    let message_x = writer.info.width / 2 - 40; // Adjust as needed
-    let message_y = writer.info.height / 2;
-    writer.set_position(message_x, message_y);
-    let _ = write!(writer, "YOU WIN!");
-    writer.set_position(message_x - 35, message_y + 20); // Adjust Y position for next line
-    let _ = write!(writer, "Press R to Restart");
+/// Blanks the game area and shows how the game ended in big letters. Returns the `y` below it.
+fn display_end_message(won: bool) -> usize {
+    let (message, style) = if won {
+        ("YOU WIN!", WINNER_STYLE)
+    } else {
+        ("GAME OVER", GAME_OVER_STYLE)
+    };
+    let writer = screenwriter();
+    clear_game_area();
+    let area = game_area();
+    let y = area.y + area.height / 8;
+    writer.set_position(
+        writer.info.width.saturating_sub(style.text_width(message)) / 2,
+        y,
+    );
+    writer.write_styled(message, style);
+    y + style.line_height()
+}
 
-    // Optionally display a restart message or any other information
+/// Lists the high scores, centered, starting at `y`, with the last game's score picked out.
+fn display_high_scores(mut y: usize) {
+    let writer = screenwriter();
+    let width = writer.info.width;
+    let centered = |text: &str, style: TextStyle| width.saturating_sub(style.text_width(text)) / 2;
+
+    let title = "HIGH SCORES";
+    writer.set_position(centered(title, HIGH_SCORE_TITLE_STYLE), y);
+    writer.write_styled(title, HIGH_SCORE_TITLE_STYLE);
+    y += HIGH_SCORE_TITLE_STYLE.line_height();
+    let new_rank = *NEW_RANK.lock();
+    for (rank, entry) in highscores::entries().iter().enumerate() {
+        let line = format!("{:>2}. {}  {:>7}", rank + 1, entry.initials(), entry.score);
+        let style = if new_rank == Some(rank) {
+            NEW_HIGH_SCORE_STYLE
+        } else {
+            TextStyle::default()
+        };
+        writer.set_position(centered(&line, style), y);
+        writer.write_styled(&line, style);
+        y += LINE_HEIGHT;
+    }
 }
 
 fn are_enemies_remaining() -> bool {
@@ -1094,8 +1475,122 @@ fn are_enemies_remaining() -> bool {
     false // No enemies remaining
 }
 
+/// The mystery ship, crossing the top of the screen from one side to the other.
+struct Ufo {
+    /// Left edge; off screen when it comes on and goes off.
+    x: isize,
+    y: usize,
+    /// Pixels it moves each tick, negative going left.
+    dx: isize,
+    sprite: Sprite,
+}
+
+const UFO_SPEED: isize = 6;
+const UFO_COLOR: (u8, u8, u8) = (0xff, 0x20, 0x20);
+/// Bonus for shooting the mystery ship, one picked at random.
+const UFO_POINTS: [u32; 4] = [50, 100, 150, 300];
+/// The mystery ship comes between this many ticks and twice as many after the last one.
+const UFO_MIN_DELAY: u32 = 300;
+
+fn ufo_delay() -> u32 {
+    UFO_MIN_DELAY + rand::below(UFO_MIN_DELAY)
+}
+
+/// Moves the mystery ship along, removing it once it's gone off the other side, or sends out
+/// the next one, from a random side, when it's time.
+fn ufo_tick() {
+    let mut ufo_guard = UFO.lock();
+    let width = screenwriter().width() as isize;
+    if let Some(ufo) = ufo_guard.as_mut() {
+        ufo.x += ufo.dx;
+        let (ufo_width, _) = ufo.sprite.size();
+        if ufo.x + (ufo_width as isize) < 0 || ufo.x > width {
+            ufo.sprite.erase(screenwriter());
+            *ufo_guard = None;
+        }
+        return;
+    }
+
+    let mut timer = UFO_TIMER.lock();
+    if *timer > 0 {
+        *timer -= 1;
+        return;
+    }
+    *timer = ufo_delay();
+    let sprite = Sprite::animated(&*UFO_FRAMES, ENEMY_SCALE, UFO_COLOR).z(ENEMY_Z);
+    let (ufo_width, _) = sprite.size();
+    let (x, dx) = if rand::below(2) == 0 {
+        (-(ufo_width as isize), UFO_SPEED)
+    } else {
+        (width, -UFO_SPEED)
+    };
+    *ufo_guard = Some(Ufo {
+        x,
+        y: game_area().y + 4,
+        dx,
+        sprite,
+    });
+}
+
+/// Blows up the mystery ship for a bonus if `bullet` went through it on its way up since it
+/// last moved.
+fn shoot_ufo(bullet: &Bullet) -> bool {
+    let mut ufo_guard = UFO.lock();
+    let Some(ufo) = ufo_guard.as_mut() else {
+        return false;
+    };
+    let (width, height) = ufo.sprite.size();
+    let bullet_x = bullet.x as isize;
+    let hit = bullet_x + (bullet.width as isize) > ufo.x
+        && bullet_x < ufo.x + width as isize
+        && bullet.y < ufo.y + height
+        // the bullet moved 30 pixels at once, so it counts if it passed the ship on the way
+        && bullet.y + bullet.height + 30 > ufo.y;
+    if !hit {
+        return false;
+    }
+    let points = UFO_POINTS[rand::below(UFO_POINTS.len() as u32) as usize];
+    ufo.sprite.erase(screenwriter());
+    spawn_explosion(ufo.x.max(0) as usize, ufo.y);
+    *ufo_guard = None;
+    sfx::play(Effect::Explosion);
+    enemy_killed(points);
+    let _ = writeln!(window::Writer(LOG), "Mystery ship: {} points", points);
+    true
+}
+
+/// Starts over from the first wave, with no score and all lives.
+fn new_game() {
+    *LEVEL.lock() = 1;
+    *SCORE.lock() = 0;
+    *NEW_RANK.lock() = None;
+    *LIVES.lock() = STARTING_LIVES;
+    *NEXT_EXTRA_LIFE.lock() = EXTRA_LIFE_EVERY;
+    *RESPAWN.lock() = 0;
+    *INVULNERABLE.lock() = 0;
+    place_player();
+    reset_game();
+    set_state(GameState::Playing);
+}
+
+/// Starts the next wave, keeping the score.
+fn next_wave() {
+    *LEVEL.lock() += 1;
+    reset_game();
+    let _ = writeln!(window::Writer(LOG), "Wave {}", *LEVEL.lock());
+}
+
+/// The settings for the wave being played.
+fn current_wave() -> Wave {
+    waves::wave(*LEVEL.lock(), *DIFFICULTY.lock())
+}
+
+/// Sets up the wave in `LEVEL`: fresh enemies and barriers, and no bullets.
 fn reset_game() {
     let frame_info = screenwriter().info;
+    let wave = current_wave();
+    *ENEMY_DX.lock() = 1;
+    *ENEMY_FIRE_COOLDOWN.lock() = wave.fire_interval;
     // restore enemies
     let enemies_guard = ENEMIES.lock();
     let mut enemies = enemies_guard.borrow_mut();
@@ -1105,66 +1600,44 @@ fn reset_game() {
     let enemy_height = 35;
     let horizontal_spacing = 10;
     let vertical_spacing = 10;
-    let enemy_color = (0, 0, 0xff);
 
     // Calculate the total width required for enemies including spacing
     let total_enemies_width = (enemy_width + horizontal_spacing) * 15 - horizontal_spacing;
     let start_x = (frame_info.width - total_enemies_width) / 2;
 
-    // Draw enemies with spacing
-    let mut writer = screenwriter();
-    writer.clear(); // Clear the screen
+    // Place enemies with spacing
+    clear_game_area();
     for i in 0..ROWS {
         for j in 0..15 {
             let enemy_x = start_x + j * (enemy_width + horizontal_spacing);
-            let enemy_y = 50 + i * (enemy_height + vertical_spacing);
+            let enemy_y = wave.start_y + i * (enemy_height + vertical_spacing);
             enemies[i][j] = Some(Enemy::new(
                 enemy_x,
                 enemy_y,
                 enemy_width,
                 enemy_height,
-                enemy_color,
+                EnemyKind::for_row(i),
             ));
-            enemies[i][j].as_ref().unwrap().draw(&mut writer);
         }
     }
 
     // Barrier dimensions
-    let barrier_width = 30;
-    let barrier_height = 20;
+    let barrier_width = barrier::WIDTH * barrier::SCALE;
+    let barrier_height = barrier::HEIGHT * barrier::SCALE;
     // grey
     let barrier_color = (0x80, 0x80, 0x80);
-    let barrier_spacing = 20; // Updated spacing between barriers
 
-    // Calculate the total width required for barriers including new spacing
-    let total_barriers_width = (barrier_width + barrier_spacing) * BARRIER_COLS - barrier_spacing;
-    let start_x = (frame_info.width - total_barriers_width) / 2;
+    // Spread the barriers evenly, with the same gap at either edge as between them
+    let barrier_spacing = (frame_info.width - barrier_width * BARRIER_COUNT) / (BARRIER_COUNT + 1);
+    let barrier_y_offset = 180; // Increase this value to raise the barriers higher
+    let barrier_y = frame_info.height - barrier_y_offset - barrier_height;
 
     let barriers_guard = BARRIERS.lock();
     let mut barriers = barriers_guard.borrow_mut();
 
-    // Draw barriers with new spacing
-    for i in 0..BARRIER_ROWS {
-        for j in 0..BARRIER_COLS {
-            let mut barrier_x = start_x + j * (barrier_width + barrier_spacing);
-            if i % 2 == 0 {
-                barrier_x += 30;
-            } else {
-                barrier_x -= 30;
-            }
-            let barrier_y_offset = 200; // Increase this value to raise the barriers higher
-            let barrier_y =
-                frame_info.height - barrier_y_offset - i * (barrier_height + barrier_spacing);
-
-            barriers[i][j] = Some(Barrier::new(
-                barrier_x,
-                barrier_y,
-                barrier_width,
-                barrier_height,
-                barrier_color,
-            ));
-            barriers[i][j].as_ref().unwrap().draw(&mut writer);
-        }
+    for (i, barrier) in barriers.iter_mut().enumerate() {
+        let barrier_x = barrier_spacing + i * (barrier_width + barrier_spacing);
+        *barrier = Some(Barrier::new(barrier_x, barrier_y, barrier_color).z(BARRIER_Z));
     }
 
     // remove bullets
@@ -1180,4 +1653,129 @@ fn reset_game() {
     for bullet in enemy_bullets.iter_mut() {
         *bullet = None;
     }
+
+    // remove explosions
+    let explosions_guard = EXPLOSIONS.lock();
+    let mut explosions = explosions_guard.borrow_mut();
+    for explosion in explosions.iter_mut() {
+        *explosion = None;
+    }
+
+    // the screen was cleared under the player and the mystery ship
+    PLAYER.lock().sprite.forget();
+    *UFO.lock() = None;
+    *UFO_TIMER.lock() = ufo_delay();
+}
+
+fn init_explosion_array() -> [Option<Sprite>; MAX_EXPLOSIONS] {
+    const ARRAY_REPEAT_VALUE: Option<Sprite> = None;
+    [ARRAY_REPEAT_VALUE; MAX_EXPLOSIONS]
+}
+
+/// Starts an explosion where an enemy was hit. Does nothing if `MAX_EXPLOSIONS` are already
+/// playing.
+fn spawn_explosion(x: usize, y: usize) {
+    let explosions_guard = EXPLOSIONS.lock();
+    let mut explosions = explosions_guard.borrow_mut();
+    if let Some(slot) = explosions.iter_mut().find(|x| x.is_none()) {
+        let mut explosion = Sprite::animated(&*EXPLOSION_FRAMES, ENEMY_SCALE, (0xff, 0xa0, 0x20))
+            .frame_ticks(EXPLOSION_FRAME_TICKS)
+            .once()
+            .z(EXPLOSION_Z);
+        explosion.move_to(x as isize, y as isize);
+        *slot = Some(explosion);
+    }
+}
+
+/// Plays the explosions one tick further and removes the ones that are over.
+fn animate_explosions() {
+    let writer = screenwriter();
+    let explosions_guard = EXPLOSIONS.lock();
+    let mut explosions = explosions_guard.borrow_mut();
+    for slot in explosions.iter_mut() {
+        if let Some(explosion) = slot {
+            explosion.tick();
+            if explosion.is_finished() {
+                explosion.erase(writer);
+                *slot = None;
+            }
+        }
+    }
+}
+
+/// Puts an entity's sprite at the entity's position and adds it to the scene.
+fn place<'a>(scene: &mut Scene<'a>, sprite: &'a mut Sprite, x: usize, y: usize) {
+    sprite.move_to(x as isize, y as isize);
+    scene.add(sprite);
+}
+
+/// Blanks the game area and marks every sprite as off screen, so the next `render_scene` draws
+/// them all again, e.g. after the pause menu covered them.
+fn redraw_game_area() {
+    clear_game_area();
+    PLAYER.lock().sprite.forget();
+    for enemy in ENEMIES.lock().borrow_mut().iter_mut().flatten().flatten() {
+        enemy.sprite.forget();
+    }
+    for barrier in BARRIERS.lock().borrow_mut().iter_mut().flatten() {
+        barrier.sprite.forget();
+    }
+    for bullet in BULLETS.lock().borrow_mut().iter_mut().flatten() {
+        bullet.sprite.forget();
+    }
+    for bullet in ENEMY_BULLETS.lock().borrow_mut().iter_mut().flatten() {
+        bullet.sprite.forget();
+    }
+    for explosion in EXPLOSIONS.lock().borrow_mut().iter_mut().flatten() {
+        explosion.forget();
+    }
+    if let Some(ufo) = UFO.lock().as_mut() {
+        ufo.sprite.forget();
+    }
+}
+
+/// Brings the sprites on screen up to date with the game, redrawing only what moved, animated
+/// or overlaps something that did.
+fn render_scene() {
+    let mut player_guard = PLAYER.lock();
+    let enemies_guard = ENEMIES.lock();
+    let mut enemies = enemies_guard.borrow_mut();
+    let barriers_guard = BARRIERS.lock();
+    let mut barriers = barriers_guard.borrow_mut();
+    let bullets_guard = BULLETS.lock();
+    let mut bullets = bullets_guard.borrow_mut();
+    let enemy_bullets_guard = ENEMY_BULLETS.lock();
+    let mut enemy_bullets = enemy_bullets_guard.borrow_mut();
+    let explosions_guard = EXPLOSIONS.lock();
+    let mut explosions = explosions_guard.borrow_mut();
+    let mut ufo = UFO.lock();
+
+    let mut scene = Scene::new();
+    let player = &mut *player_guard;
+    if player_visible() {
+        place(&mut scene, &mut player.sprite, player.x, player.y);
+    } else {
+        player.sprite.erase(screenwriter());
+    }
+    for enemy in enemies.iter_mut().flatten().flatten() {
+        place(&mut scene, &mut enemy.sprite, enemy.x, enemy.y);
+    }
+    if let Some(ufo) = ufo.as_mut() {
+        ufo.sprite.move_to(ufo.x, ufo.y as isize);
+        scene.add(&mut ufo.sprite);
+    }
+    for barrier in barriers.iter_mut().flatten() {
+        place(&mut scene, &mut barrier.sprite, barrier.x, barrier.y);
+    }
+    for bullet in bullets.iter_mut().flatten() {
+        place(&mut scene, &mut bullet.sprite, bullet.x, bullet.y);
+    }
+    for bullet in enemy_bullets.iter_mut().flatten() {
+        place(&mut scene, &mut bullet.sprite, bullet.x, bullet.y);
+    }
+    // explosions don't follow anything, they stay where they were spawned
+    for explosion in explosions.iter_mut().flatten() {
+        scene.add(explosion);
+    }
+    scene.render(screenwriter());
 }
@@ -0,0 +1,72 @@
+use crate::screen::{screenwriter, TextStyle};
+use alloc::format;
+use pc_keyboard::{DecodedKey, KeyCode};
+
+/// How the selected item is drawn.
+const SELECTED_STYLE: TextStyle = TextStyle::new((0xff, 0xd7, 0x00));
+
+/// A list of items picked with the up and down arrows and chosen with enter or the space bar.
+/// The menu only keeps track of which item is selected; the labels are given when it is drawn,
+/// so they can show settings that change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Menu {
+    selected: usize,
+    len: usize,
+}
+
+/// What a key did to a menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// The key isn't one the menu uses.
+    None,
+    /// Another item was selected.
+    Moved,
+    /// Enter or the space bar on this item.
+    Chosen(usize),
+    /// The left or right arrow on this item, for items with a setting to change.
+    Left(usize),
+    Right(usize),
+}
+
+impl Menu {
+    /// A menu of `len` items with the first one selected.
+    pub const fn new(len: usize) -> Self {
+        Menu { selected: 0, len }
+    }
+
+    /// Moves the selection for the arrow keys, wrapping around at either end.
+    pub fn key(&mut self, key: DecodedKey) -> Action {
+        match key {
+            DecodedKey::RawKey(KeyCode::ArrowUp) if self.len > 0 => {
+                self.selected = (self.selected + self.len - 1) % self.len;
+                Action::Moved
+            }
+            DecodedKey::RawKey(KeyCode::ArrowDown) if self.len > 0 => {
+                self.selected = (self.selected + 1) % self.len;
+                Action::Moved
+            }
+            DecodedKey::RawKey(KeyCode::ArrowLeft) => Action::Left(self.selected),
+            DecodedKey::RawKey(KeyCode::ArrowRight) => Action::Right(self.selected),
+            DecodedKey::Unicode('\n' | ' ') => Action::Chosen(self.selected),
+            _ => Action::None,
+        }
+    }
+
+    /// Draws `labels` centered, one per line, starting at `y`, with the selected one picked
+    /// out. Returns the `y` below the last one.
+    pub fn draw(&self, labels: &[&str], mut y: usize) -> usize {
+        let writer = screenwriter();
+        let width = writer.info.width;
+        for (i, label) in labels.iter().enumerate() {
+            let (text, style) = if i == self.selected {
+                (format!("> {} <", label), SELECTED_STYLE)
+            } else {
+                (format!("  {}  ", label), TextStyle::default())
+            };
+            writer.set_position(width.saturating_sub(style.text_width(&text)) / 2, y);
+            writer.write_styled(&text, style);
+            y += style.line_height();
+        }
+        y
+    }
+}
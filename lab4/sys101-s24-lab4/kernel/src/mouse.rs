@@ -0,0 +1,214 @@
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+// PS/2 controller ports. Writing to the status port sends a command to the controller itself.
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+// Controller commands
+const ENABLE_AUX: u8 = 0xA8;
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const WRITE_AUX: u8 = 0xD4;
+
+// Bits of the controller configuration byte
+const CONFIG_AUX_INTERRUPT: u8 = 1 << 1;
+const CONFIG_AUX_CLOCK_DISABLED: u8 = 1 << 5;
+
+// Mouse commands
+const SET_DEFAULTS: u8 = 0xF6;
+const ENABLE_REPORTING: u8 = 0xF4;
+const SET_SAMPLE_RATE: u8 = 0xF3;
+const GET_ID: u8 = 0xF2;
+const ACK: u8 = 0xFA;
+
+/// Device ID of a mouse that switched to 4-byte packets with a scroll wheel.
+const WHEEL_MOUSE_ID: u8 = 3;
+
+/// How many times to poll the controller before giving up on it.
+const TIMEOUT: usize = 100_000;
+
+// Bits of the first byte of a packet
+const LEFT_BUTTON: u8 = 1 << 0;
+const RIGHT_BUTTON: u8 = 1 << 1;
+const MIDDLE_BUTTON: u8 = 1 << 2;
+const ALWAYS_ONE: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+/// Which mouse buttons are held down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MouseButtons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// One packet from the mouse: how far it moved since the last one and the state of its buttons.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MouseEvent {
+    /// Movement to the right, in mouse counts.
+    pub dx: i16,
+    /// Movement down the screen, in mouse counts. The mouse itself reports up as positive; this
+    /// is flipped to match screen coordinates.
+    pub dy: i16,
+    /// Scroll wheel clicks, positive towards the user. Always 0 on mice without a wheel.
+    pub wheel: i8,
+    pub buttons: MouseButtons,
+}
+
+/// Collects the bytes of a packet as they arrive, one interrupt at a time.
+pub(crate) struct PacketDecoder {
+    bytes: [u8; 4],
+    count: usize,
+    /// 3 for a plain mouse, 4 when it has a wheel.
+    packet_size: usize,
+}
+
+impl PacketDecoder {
+    const fn new() -> Self {
+        PacketDecoder {
+            bytes: [0; 4],
+            count: 0,
+            packet_size: 3,
+        }
+    }
+
+    /// Adds one byte from the mouse and returns the event once a packet is complete.
+    pub(crate) fn add_byte(&mut self, byte: u8) -> Option<MouseEvent> {
+        // The first byte always has bit 3 set. Dropping bytes until one does gets us back in
+        // step if a byte was lost.
+        if self.count == 0 && byte & ALWAYS_ONE == 0 {
+            return None;
+        }
+        self.bytes[self.count] = byte;
+        self.count += 1;
+        if self.count < self.packet_size {
+            return None;
+        }
+        self.count = 0;
+        Some(self.decode())
+    }
+
+    fn decode(&self) -> MouseEvent {
+        let flags = self.bytes[0];
+        // The movement is a 9 bit two's complement number: the sign bit is in the first byte
+        let mut dx = self.bytes[1] as i16 - if flags & X_SIGN != 0 { 0x100 } else { 0 };
+        let mut dy = self.bytes[2] as i16 - if flags & Y_SIGN != 0 { 0x100 } else { 0 };
+        // An overflowed value is garbage, so leave that axis alone
+        if flags & X_OVERFLOW != 0 {
+            dx = 0;
+        }
+        if flags & Y_OVERFLOW != 0 {
+            dy = 0;
+        }
+        let wheel = if self.packet_size == 4 {
+            // The low 4 bits are a two's complement number
+            ((self.bytes[3] << 4) as i8) >> 4
+        } else {
+            0
+        };
+        MouseEvent {
+            dx,
+            dy: -dy,
+            wheel,
+            buttons: MouseButtons {
+                left: flags & LEFT_BUTTON != 0,
+                right: flags & RIGHT_BUTTON != 0,
+                middle: flags & MIDDLE_BUTTON != 0,
+            },
+        }
+    }
+}
+
+pub(crate) static DECODER: Mutex<PacketDecoder> = Mutex::new(PacketDecoder::new());
+
+/// Turns on the auxiliary PS/2 device, switches it to wheel mode if it has one and enables
+/// IRQ12. Must be called with interrupts disabled, since it polls the controller. Returns
+/// `false` if no mouse answered.
+pub(crate) fn init() -> bool {
+    init_device().is_some()
+}
+
+fn init_device() -> Option<()> {
+    write_command(ENABLE_AUX)?;
+
+    write_command(READ_CONFIG)?;
+    let config = read_data()?;
+    write_command(WRITE_CONFIG)?;
+    write_data((config | CONFIG_AUX_INTERRUPT) & !CONFIG_AUX_CLOCK_DISABLED)?;
+
+    send_to_mouse(SET_DEFAULTS)?;
+
+    // The "magic knock": a mouse with a wheel changes its ID from 0 to 3 after these sample
+    // rates and sends 4-byte packets from then on.
+    for rate in [200, 100, 80] {
+        send_to_mouse(SET_SAMPLE_RATE)?;
+        send_to_mouse(rate)?;
+    }
+    send_to_mouse(GET_ID)?;
+    if read_data()? == WHEEL_MOUSE_ID {
+        DECODER.lock().packet_size = 4;
+    }
+
+    send_to_mouse(ENABLE_REPORTING)
+}
+
+/// Sends a byte to the mouse and waits for it to acknowledge it.
+fn send_to_mouse(byte: u8) -> Option<()> {
+    write_command(WRITE_AUX)?;
+    write_data(byte)?;
+    if read_data()? == ACK {
+        Some(())
+    } else {
+        None
+    }
+}
+
+fn write_command(command: u8) -> Option<()> {
+    wait_for_input_space()?;
+    unsafe { Port::new(STATUS_PORT).write(command) };
+    Some(())
+}
+
+fn write_data(byte: u8) -> Option<()> {
+    wait_for_input_space()?;
+    unsafe { Port::new(DATA_PORT).write(byte) };
+    Some(())
+}
+
+fn read_data() -> Option<u8> {
+    for _ in 0..TIMEOUT {
+        if read_status() & STATUS_OUTPUT_FULL != 0 {
+            return Some(unsafe { Port::new(DATA_PORT).read() });
+        }
+    }
+    None
+}
+
+fn wait_for_input_space() -> Option<()> {
+    for _ in 0..TIMEOUT {
+        if read_status() & STATUS_INPUT_FULL == 0 {
+            return Some(());
+        }
+    }
+    None
+}
+
+fn read_status() -> u8 {
+    unsafe { Port::new(STATUS_PORT).read() }
+}
+
+/// Reads the byte the mouse just sent. Called from the IRQ12 handler.
+pub(crate) fn read_byte() -> Option<u8> {
+    if read_status() & STATUS_OUTPUT_FULL != 0 {
+        Some(unsafe { Port::new(DATA_PORT).read() })
+    } else {
+        None
+    }
+}
@@ -0,0 +1,386 @@
+//! PCI configuration space access through I/O ports 0xCF8/0xCFC (configuration mechanism #1),
+//! and a registry of the devices found by [`scan`].
+
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+const ENABLE: u32 = 1 << 31;
+
+// Offsets into the configuration header shared by every header type
+const VENDOR_ID: u8 = 0x00;
+const COMMAND: u8 = 0x04;
+const REVISION: u8 = 0x08;
+const HEADER_TYPE: u8 = 0x0E;
+const BAR0: u8 = 0x10;
+const INTERRUPT_LINE: u8 = 0x3C;
+
+const MULTI_FUNCTION: u8 = 0x80;
+/// Header type of an ordinary device. Bridges have another layout, with only two BARs.
+const HEADER_GENERAL: u8 = 0x00;
+const HEADER_PCI_BRIDGE: u8 = 0x01;
+const NO_DEVICE: u16 = 0xFFFF;
+
+pub const COMMAND_IO_SPACE: u16 = 1 << 0;
+pub const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+pub const COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+/// Where a function sits on the PCI buses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        PciAddress {
+            bus,
+            device,
+            function,
+        }
+    }
+
+    fn select(&self, offset: u8) {
+        let address = ENABLE
+            | (self.bus as u32) << 16
+            | (self.device as u32 & 0x1F) << 11
+            | (self.function as u32 & 0x07) << 8
+            | (offset as u32 & 0xFC);
+        unsafe { Port::new(CONFIG_ADDRESS).write(address) };
+    }
+
+    /// Reads the aligned configuration dword holding `offset`.
+    pub fn read_u32(&self, offset: u8) -> u32 {
+        self.select(offset);
+        unsafe { Port::new(CONFIG_DATA).read() }
+    }
+
+    pub fn write_u32(&self, offset: u8, value: u32) {
+        self.select(offset);
+        unsafe { Port::new(CONFIG_DATA).write(value) };
+    }
+
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read_u32(offset) >> ((offset & 2) * 8)) as u16
+    }
+
+    /// Writes just the word at `offset`, with a 16-bit access to its half of the data port. A
+    /// read-modify-write of the whole dword would write the other half back too, which for
+    /// COMMAND would clear any status bits set in STATUS next to it, as those clear on a 1.
+    pub fn write_u16(&self, offset: u8, value: u16) {
+        self.select(offset);
+        unsafe { Port::new(CONFIG_DATA + u16::from(offset & 2)).write(value) };
+    }
+
+    pub fn read_u8(&self, offset: u8) -> u8 {
+        (self.read_u32(offset) >> ((offset & 3) * 8)) as u8
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// A decoded base address register: where a device's registers or memory show up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        address: u64,
+        size: u64,
+        prefetchable: bool,
+        /// Takes two BAR slots, the next one holding the upper half of the address.
+        is_64_bit: bool,
+    },
+    Io {
+        port: u16,
+        size: u16,
+    },
+}
+
+/// A PCI function, with the parts of its configuration header drivers usually need.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: u8,
+    /// The legacy PIC IRQ the firmware routed the device to, or 0xFF if none.
+    pub interrupt_line: u8,
+    /// 1 to 4 for INTA# to INTD#, or 0 if the device doesn't interrupt.
+    pub interrupt_pin: u8,
+    pub bars: [Option<Bar>; 6],
+}
+
+impl PciDevice {
+    /// Reads the configuration header of the function at `address`, if there is one.
+    pub fn probe(address: PciAddress) -> Option<Self> {
+        let id = address.read_u32(VENDOR_ID);
+        let vendor_id = id as u16;
+        if vendor_id == NO_DEVICE {
+            return None;
+        }
+        let class = address.read_u32(REVISION);
+        let header_type = address.read_u8(HEADER_TYPE) & !MULTI_FUNCTION;
+        let interrupt = address.read_u32(INTERRUPT_LINE);
+        let bar_count = match header_type {
+            HEADER_GENERAL => 6,
+            HEADER_PCI_BRIDGE => 2,
+            _ => 0,
+        };
+        Some(PciDevice {
+            address,
+            vendor_id,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            header_type,
+            interrupt_line: interrupt as u8,
+            interrupt_pin: (interrupt >> 8) as u8,
+            bars: read_bars(address, bar_count),
+        })
+    }
+
+    pub fn command(&self) -> u16 {
+        self.address.read_u16(COMMAND)
+    }
+
+    pub fn set_command(&self, command: u16) {
+        self.address.write_u16(COMMAND, command);
+    }
+
+    /// Lets the device decode its I/O and memory BARs and do DMA on its own.
+    pub fn enable_bus_master(&self) {
+        self.set_command(
+            self.command() | COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE | COMMAND_BUS_MASTER,
+        );
+    }
+
+    /// A name for the device's class, as lspci prints it.
+    pub fn class_name(&self) -> &'static str {
+        class_name(self.class, self.subclass)
+    }
+
+    pub fn vendor_name(&self) -> Option<&'static str> {
+        vendor_name(self.vendor_id)
+    }
+}
+
+/// Decodes and sizes the BARs. Sizing writes all ones to each BAR and reads back which address
+/// bits stuck, so decoding is switched off meanwhile.
+fn read_bars(address: PciAddress, count: usize) -> [Option<Bar>; 6] {
+    let mut bars = [None; 6];
+    let command = address.read_u16(COMMAND);
+    address.write_u16(
+        COMMAND,
+        command & !(COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE),
+    );
+
+    let mut index = 0;
+    while index < count {
+        let offset = BAR0 + index as u8 * 4;
+        let value = address.read_u32(offset);
+        let mask = size_mask(address, offset, value);
+        if value & 1 == 1 {
+            let size = !(mask & !0x3) as u16;
+            if mask != 0 {
+                bars[index] = Some(Bar::Io {
+                    port: (value & !0x3) as u16,
+                    size: size.wrapping_add(1),
+                });
+            }
+            index += 1;
+        } else {
+            let is_64_bit = (value >> 1) & 0x3 == 0x2;
+            let mut base = (value & !0xF) as u64;
+            let mut mask = (mask & !0xF) as u64 | 0xFFFF_FFFF_0000_0000;
+            if is_64_bit && index + 1 < count {
+                let high = address.read_u32(offset + 4);
+                base |= (high as u64) << 32;
+                mask = (mask & 0xFFFF_FFFF) | (size_mask(address, offset + 4, high) as u64) << 32;
+            }
+            if mask & 0xFFFF_FFFF != 0 {
+                bars[index] = Some(Bar::Memory {
+                    address: base,
+                    size: (!mask).wrapping_add(1),
+                    prefetchable: value & 0x8 != 0,
+                    is_64_bit,
+                });
+            }
+            index += if is_64_bit { 2 } else { 1 };
+        }
+    }
+
+    address.write_u16(COMMAND, command);
+    bars
+}
+
+/// Writes all ones to a BAR, reads back the mask of address bits it decodes and puts the
+/// original value back.
+fn size_mask(address: PciAddress, offset: u8, original: u32) -> u32 {
+    address.write_u32(offset, 0xFFFF_FFFF);
+    let mask = address.read_u32(offset);
+    address.write_u32(offset, original);
+    mask
+}
+
+static DEVICES: Mutex<Vec<PciDevice>> = Mutex::new(Vec::new());
+
+/// Walks every bus, device and function, filling the registry with what it finds, and returns
+/// how many functions there are. Scanning again starts the registry over.
+pub fn scan() -> usize {
+    let mut devices = DEVICES.lock();
+    devices.clear();
+    for bus in 0..=255 {
+        for device in 0..32 {
+            let Some(first) = PciDevice::probe(PciAddress::new(bus, device, 0)) else {
+                continue;
+            };
+            let multi_function = first.address.read_u8(HEADER_TYPE) & MULTI_FUNCTION != 0;
+            devices.push(first);
+            if multi_function {
+                for function in 1..8 {
+                    if let Some(found) = PciDevice::probe(PciAddress::new(bus, device, function)) {
+                        devices.push(found);
+                    }
+                }
+            }
+        }
+    }
+    devices.len()
+}
+
+/// Every function found by the last [`scan`].
+pub fn devices() -> Vec<PciDevice> {
+    DEVICES.lock().clone()
+}
+
+/// The first device with the given vendor and device IDs.
+pub fn find(vendor_id: u16, device_id: u16) -> Option<PciDevice> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|device| device.vendor_id == vendor_id && device.device_id == device_id)
+        .copied()
+}
+
+/// The first device of the given class and subclass.
+pub fn find_class(class: u8, subclass: u8) -> Option<PciDevice> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|device| device.class == class && device.subclass == subclass)
+        .copied()
+}
+
+/// Writes one line per function found by the last [`scan`], lspci style, with its BARs and IRQ
+/// indented below it.
+pub fn dump(out: &mut impl Write) -> fmt::Result {
+    for device in DEVICES.lock().iter() {
+        write!(out, "{} {}: ", device.address, device.class_name())?;
+        if let Some(vendor) = device.vendor_name() {
+            write!(out, "{} ", vendor)?;
+        }
+        writeln!(
+            out,
+            "[{:04x}:{:04x}] (rev {:02x})",
+            device.vendor_id, device.device_id, device.revision
+        )?;
+        for (index, bar) in device.bars.iter().enumerate() {
+            match bar {
+                Some(Bar::Memory {
+                    address,
+                    size,
+                    prefetchable,
+                    is_64_bit,
+                }) => writeln!(
+                    out,
+                    "\tBAR{}: Memory at {:#x} ({}-bit, {}) [size={:#x}]",
+                    index,
+                    address,
+                    if *is_64_bit { 64 } else { 32 },
+                    if *prefetchable {
+                        "prefetchable"
+                    } else {
+                        "non-prefetchable"
+                    },
+                    size
+                )?,
+                Some(Bar::Io { port, size }) => writeln!(
+                    out,
+                    "\tBAR{}: I/O ports at {:#x} [size={}]",
+                    index, port, size
+                )?,
+                None => {}
+            }
+        }
+        if device.interrupt_pin != 0 {
+            writeln!(
+                out,
+                "\tInterrupt: pin {} routed to IRQ {}",
+                (b'A' + device.interrupt_pin - 1) as char,
+                device.interrupt_line
+            )?;
+        }
+    }
+    Ok(())
+}
+
+pub fn vendor_name(vendor_id: u16) -> Option<&'static str> {
+    Some(match vendor_id {
+        0x8086 => "Intel Corporation",
+        0x1022 => "Advanced Micro Devices, Inc.",
+        0x10DE => "NVIDIA Corporation",
+        0x10EC => "Realtek Semiconductor Co., Ltd.",
+        0x1234 => "QEMU/Bochs",
+        0x1AF4 => "Red Hat, Inc. (virtio)",
+        0x1B36 => "Red Hat, Inc.",
+        0x15AD => "VMware",
+        0x80EE => "InnoTek (VirtualBox)",
+        0x1274 => "Ensoniq",
+        _ => return None,
+    })
+}
+
+pub fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x00, _) => "Unclassified device",
+        (0x01, 0x00) => "SCSI storage controller",
+        (0x01, 0x01) => "IDE interface",
+        (0x01, 0x05) => "ATA controller",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "Non-Volatile memory controller",
+        (0x01, _) => "Mass storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA compatible controller",
+        (0x03, _) => "Display controller",
+        (0x04, 0x01) => "Multimedia audio controller",
+        (0x04, 0x03) => "Audio device",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x07, _) => "Communication controller",
+        (0x08, _) => "System peripheral",
+        (0x09, _) => "Input device controller",
+        (0x0C, 0x03) => "USB controller",
+        (0x0C, 0x05) => "SMBus",
+        (0x0C, _) => "Serial bus controller",
+        _ => "Unknown class",
+    }
+}
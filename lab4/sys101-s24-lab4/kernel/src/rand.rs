@@ -0,0 +1,148 @@
+//! A seedable pseudo-random number generator for games: PCG32
+//! (https://www.pcg-random.org/download.html), small and fast, but no good for cryptography.
+//!
+//! There is one generator for the whole kernel. [`seed_from_hardware`] seeds it from whatever
+//! the machine offers, so every boot plays differently; [`seed`] gives it a fixed seed instead,
+//! so the same seed makes the same numbers every time, which is handy for reproducing a bug.
+
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+use x86_64::instructions::random::RdRand;
+
+const MULTIPLIER: u64 = 6364136223846793005;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+/// Status register A; bit 7 is set while the RTC is updating its registers.
+const RTC_STATUS_A: u8 = 0x0A;
+const RTC_UPDATING: u8 = 0x80;
+/// Seconds, minutes, hours, day of the month, month and year.
+const RTC_TIME_REGISTERS: [u8; 6] = [0x00, 0x02, 0x04, 0x07, 0x08, 0x09];
+
+/// A PCG32 generator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+    /// Picks one of 2^63 different sequences; always odd.
+    increment: u64,
+}
+
+impl Rng {
+    /// A generator that makes the same numbers every time for the same seed.
+    pub const fn new(seed: u64) -> Self {
+        Rng::with_sequence(seed, seed)
+    }
+
+    /// PCG's own seeding, which picks the starting point and the sequence separately.
+    const fn with_sequence(seed: u64, sequence: u64) -> Self {
+        let mut rng = Rng {
+            state: 0,
+            increment: (sequence << 1) | 1,
+        };
+        rng.step();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.step();
+        rng
+    }
+
+    const fn step(&mut self) {
+        self.state = self
+            .state
+            .wrapping_mul(MULTIPLIER)
+            .wrapping_add(self.increment);
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.step();
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    /// A number below `bound`, every one as likely as the others. 0 if `bound` is 0.
+    pub fn below(&mut self, bound: u32) -> u32 {
+        if bound == 0 {
+            return 0;
+        }
+        // Throw away the top few numbers that would make the lower results more likely
+        let threshold = bound.wrapping_neg() % bound;
+        loop {
+            let n = self.next_u32();
+            if n >= threshold {
+                return n % bound;
+            }
+        }
+    }
+}
+
+static RNG: Mutex<Rng> = Mutex::new(Rng::new(0));
+
+/// Seeds the kernel's generator with `seed`, for numbers that are the same on every run.
+pub fn seed(seed: u64) {
+    *RNG.lock() = Rng::new(seed);
+}
+
+/// Seeds the kernel's generator from the hardware: RDRAND if the CPU has it, mixed with the time
+/// stamp counter and the real-time clock. Returns the seed, so a run can be played again with
+/// [`seed`].
+pub fn seed_from_hardware() -> u64 {
+    let mut value = mix(0, unsafe { core::arch::x86_64::_rdtsc() });
+    if let Some(random) = RdRand::new().and_then(RdRand::get_u64) {
+        value = mix(value, random);
+    }
+    value = mix(value, read_rtc());
+    seed(value);
+    value
+}
+
+/// A number below `bound` from the kernel's generator. 0 if `bound` is 0.
+pub fn below(bound: u32) -> u32 {
+    RNG.lock().below(bound)
+}
+
+/// Checks the generator against known answers: PCG32's reference numbers, so it is the real
+/// thing, and the first few from [`Rng::new`] and [`Rng::below`] for one seed, so a seed keeps
+/// playing the same game it did before. Returns false if any number is wrong.
+pub fn self_test() -> bool {
+    // from the pcg32-demo program in the reference implementation
+    let mut reference = Rng::with_sequence(42, 54);
+    let reference_ok = [0xa15c02b7, 0x7b47f409, 0xba1d3330]
+        .iter()
+        .all(|&n| reference.next_u32() == n);
+    let mut rng = Rng::new(42);
+    let new_ok = [0x40b29785, 0x0a8b3706, 0x2f091207]
+        .iter()
+        .all(|&n| rng.next_u32() == n);
+    let mut rng = Rng::new(42);
+    let below_ok = [1, 0, 1, 5, 8, 5, 5, 6].iter().all(|&n| rng.below(10) == n);
+    reference_ok && new_ok && below_ok
+}
+
+/// Adds `value` to `hash` with the SplitMix64 finalizer, so every bit of `value` changes about
+/// half the bits of the result.
+fn mix(hash: u64, value: u64) -> u64 {
+    let mut z = (hash ^ value).wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// The date and time from the real-time clock, packed into a number as they are, without
+/// caring whether it counts in BCD or binary.
+fn read_rtc() -> u64 {
+    let mut address = Port::<u8>::new(CMOS_ADDRESS);
+    let mut data = Port::<u8>::new(CMOS_DATA);
+    let mut read = |register: u8| unsafe {
+        address.write(register);
+        data.read()
+    };
+    // the registers can't be trusted halfway through an update, which takes under 2 ms
+    for _ in 0..100_000 {
+        if read(RTC_STATUS_A) & RTC_UPDATING == 0 {
+            break;
+        }
+    }
+    RTC_TIME_REGISTERS
+        .iter()
+        .fold(0, |time, &register| (time << 8) | read(register) as u64)
+}
@@ -0,0 +1,330 @@
+//! Sound Blaster 16 driver, as emulated by QEMU's `-device sb16`.
+//!
+//! The card plays 16-bit signed mono samples at [`SAMPLE_RATE`] from a small ring buffer in
+//! low physical memory, using 16-bit auto-init DMA. The buffer is split in two halves: while
+//! the card plays one, the interrupt handler mixes the sounds that are playing into the other.
+
+use crate::frames::{self, DmaBuffer};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+pub const SAMPLE_RATE: u32 = 22_050;
+
+/// Size in bytes of the DMA ring buffer handed to [`init`]. Each half holds about 46 ms of
+/// sound, which is how long a new sound may wait before it starts.
+pub const BUFFER_SIZE: usize = 4096;
+const HALF_SAMPLES: usize = BUFFER_SIZE / 2 / 2;
+
+/// Most sounds that can play at the same time.
+pub const MAX_VOICES: usize = 8;
+
+pub const IRQ: u8 = 5;
+
+// DSP ports, at the default base address 0x220
+const MIXER_ADDRESS: u16 = 0x224;
+const MIXER_DATA: u16 = 0x225;
+const DSP_RESET: u16 = 0x226;
+const DSP_READ: u16 = 0x22A;
+/// Writing sends a byte to the DSP; reading gives bit 7 set while it is busy.
+const DSP_WRITE: u16 = 0x22C;
+/// Bit 7 set when there is a byte to read.
+const DSP_READ_STATUS: u16 = 0x22E;
+/// Reading acknowledges a 16-bit DMA interrupt.
+const DSP_ACK_16: u16 = 0x22F;
+
+const DSP_READY: u8 = 0xAA;
+const SET_OUTPUT_RATE: u8 = 0x41;
+const SPEAKER_ON: u8 = 0xD1;
+const GET_VERSION: u8 = 0xE1;
+/// 16-bit output, auto-init, FIFO on.
+const START_16_BIT_AUTO: u8 = 0xB6;
+const MODE_MONO_SIGNED: u8 = 0x10;
+const EXIT_16_BIT_AUTO: u8 = 0xD9;
+
+// Mixer registers picking the IRQ and DMA channels
+const MIXER_IRQ: u8 = 0x80;
+const MIXER_IRQ_5: u8 = 0x02;
+const MIXER_DMA: u8 = 0x81;
+const MIXER_DMA_1_AND_5: u8 = 0x22;
+
+// 16-bit DMA channel 5, on the second DMA controller
+const DMA_MASK: u16 = 0xD4;
+const DMA_MODE: u16 = 0xD6;
+const DMA_CLEAR_FLIP_FLOP: u16 = 0xD8;
+const DMA_5_ADDRESS: u16 = 0xC4;
+const DMA_5_COUNT: u16 = 0xC6;
+const DMA_5_PAGE: u16 = 0x8B;
+/// Channel 5 within its controller.
+const DMA_5: u8 = 1;
+const DMA_MASK_ON: u8 = 0x04;
+/// Single transfers, auto-init, memory to device.
+const DMA_MODE_PLAYBACK: u8 = 0x58;
+
+/// ISA DMA only reaches the first 16 MiB of physical memory...
+pub const DMA_LIMIT: u64 = 16 * 1024 * 1024;
+/// ...and a 16-bit transfer cannot cross a 128 KiB boundary.
+const DMA_16_BOUNDARY: u64 = 128 * 1024;
+
+/// How many times to poll the DSP before giving up on it.
+const TIMEOUT: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sb16Error {
+    /// Nothing answered the DSP reset.
+    NotFound,
+    /// The DSP is older than version 4 and cannot do 16-bit output.
+    TooOld(u8),
+    /// The buffer is too small, not below 16 MiB or crosses a 128 KiB boundary.
+    BadBuffer,
+}
+
+/// Samples a sound is made of, in either of the formats the Sound Blaster knows.
+#[derive(Debug, Clone, Copy)]
+pub enum Pcm {
+    /// Unsigned 8-bit samples, 128 being silence.
+    U8(&'static [u8]),
+    /// Signed 16-bit samples.
+    I16(&'static [i16]),
+}
+
+impl Pcm {
+    fn len(&self) -> usize {
+        match self {
+            Pcm::U8(samples) => samples.len(),
+            Pcm::I16(samples) => samples.len(),
+        }
+    }
+
+    fn sample(&self, index: usize) -> i32 {
+        match self {
+            Pcm::U8(samples) => (samples[index] as i32 - 128) << 8,
+            Pcm::I16(samples) => samples[index] as i32,
+        }
+    }
+}
+
+/// A sound being played.
+#[derive(Clone, Copy)]
+struct Voice {
+    pcm: Pcm,
+    position: usize,
+    volume: u8,
+}
+
+struct Mixer {
+    voices: [Option<Voice>; MAX_VOICES],
+    /// The ring buffer. `None` until a card is set up.
+    buffer: Option<DmaBuffer>,
+    /// The half of the ring buffer to fill on the next interrupt.
+    next_half: usize,
+}
+
+static MIXER: Mutex<Mixer> = Mutex::new(Mixer {
+    voices: [None; MAX_VOICES],
+    buffer: None,
+    next_half: 0,
+});
+
+/// Resets the card and starts it playing from `buffer`, which must be at least [`BUFFER_SIZE`]
+/// bytes below [`DMA_LIMIT`], as given by
+/// [`frames::allocate_below`](crate::frames::allocate_below). Call this before interrupts are
+/// enabled; IRQ5 is unmasked here. If there is no card that will do, the buffer is given back
+/// with [`frames::free`], as low memory is scarce.
+pub fn init(mut buffer: DmaBuffer) -> Result<(), Sb16Error> {
+    if let Err(error) = start(&mut buffer) {
+        // the DMA channel may already point at the buffer
+        unsafe { Port::new(DMA_MASK).write(DMA_MASK_ON | DMA_5) };
+        frames::free(buffer);
+        return Err(error);
+    }
+    {
+        let mut mixer = MIXER.lock();
+        mixer.buffer = Some(buffer);
+        mixer.next_half = 0;
+    }
+    crate::interrupts::unmask_irq(IRQ);
+    Ok(())
+}
+
+fn start(buffer: &mut DmaBuffer) -> Result<(), Sb16Error> {
+    let physical_address = buffer.physical_address();
+    let end = physical_address + BUFFER_SIZE as u64;
+    if buffer.len() < BUFFER_SIZE
+        || physical_address & 1 != 0
+        || end > DMA_LIMIT
+        || physical_address / DMA_16_BOUNDARY != (end - 1) / DMA_16_BOUNDARY
+    {
+        return Err(Sb16Error::BadBuffer);
+    }
+
+    reset_dsp()?;
+    write_dsp(GET_VERSION)?;
+    let major = read_dsp()?;
+    let _minor = read_dsp()?;
+    if major < 4 {
+        return Err(Sb16Error::TooOld(major));
+    }
+
+    write_mixer(MIXER_IRQ, MIXER_IRQ_5);
+    write_mixer(MIXER_DMA, MIXER_DMA_1_AND_5);
+
+    buffer.as_mut_slice().fill(0);
+    program_dma(physical_address);
+
+    write_dsp(SPEAKER_ON)?;
+    write_dsp(SET_OUTPUT_RATE)?;
+    write_dsp((SAMPLE_RATE >> 8) as u8)?;
+    write_dsp(SAMPLE_RATE as u8)?;
+    // An interrupt comes at the end of every half of the buffer
+    let block = HALF_SAMPLES - 1;
+    write_dsp(START_16_BIT_AUTO)?;
+    write_dsp(MODE_MONO_SIGNED)?;
+    write_dsp(block as u8)?;
+    write_dsp((block >> 8) as u8)?;
+    Ok(())
+}
+
+/// True once [`init`] found a card.
+pub fn is_available() -> bool {
+    without_interrupts(|| MIXER.lock().buffer.is_some())
+}
+
+/// Starts playing a sound, mixed with whatever else is playing, at a volume from 0 to 255.
+/// Returns `false` if there is no card or all [`MAX_VOICES`] are busy.
+pub fn play(pcm: Pcm, volume: u8) -> bool {
+    // The interrupt handler takes the lock too
+    without_interrupts(|| {
+        let mut mixer = MIXER.lock();
+        if mixer.buffer.is_none() {
+            return false;
+        }
+        match mixer.voices.iter_mut().find(|voice| voice.is_none()) {
+            Some(slot) => {
+                *slot = Some(Voice {
+                    pcm,
+                    position: 0,
+                    volume,
+                });
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// Cuts off every sound that is playing.
+pub fn stop_all() {
+    without_interrupts(|| MIXER.lock().voices = [None; MAX_VOICES]);
+}
+
+/// Stops the card altogether. It can only be started again with [`init`].
+pub fn shut_down() {
+    without_interrupts(|| {
+        let mut mixer = MIXER.lock();
+        if mixer.buffer.take().is_some() {
+            let _ = write_dsp(EXIT_16_BIT_AUTO);
+        }
+        mixer.voices = [None; MAX_VOICES];
+    });
+}
+
+/// Called by the IRQ5 handler when the card finished playing half of the ring buffer. Refills
+/// that half with the next stretch of every voice, added together.
+pub(crate) fn handle_interrupt() {
+    unsafe { Port::<u8>::new(DSP_ACK_16).read() };
+
+    let mut mixer = MIXER.lock();
+    let Mixer {
+        voices,
+        buffer,
+        next_half,
+    } = &mut *mixer;
+    let Some(buffer) = buffer.as_mut() else {
+        return;
+    };
+    let half = &mut buffer.as_mut_slice()
+        [*next_half * HALF_SAMPLES * 2..(*next_half + 1) * HALF_SAMPLES * 2];
+    for (index, bytes) in half.chunks_exact_mut(2).enumerate() {
+        let mut sum = 0;
+        for voice in voices.iter().flatten() {
+            let position = voice.position + index;
+            if position < voice.pcm.len() {
+                sum += voice.pcm.sample(position) * voice.volume as i32 / 256;
+            }
+        }
+        let sample = sum.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        bytes.copy_from_slice(&sample.to_le_bytes());
+    }
+    for slot in voices.iter_mut() {
+        if let Some(voice) = slot {
+            voice.position += HALF_SAMPLES;
+            if voice.position >= voice.pcm.len() {
+                *slot = None;
+            }
+        }
+    }
+    *next_half = 1 - *next_half;
+}
+
+fn reset_dsp() -> Result<(), Sb16Error> {
+    let mut reset = Port::<u8>::new(DSP_RESET);
+    unsafe { reset.write(1) };
+    // The reset line has to stay up for 3 microseconds; each port read takes about one
+    for _ in 0..10 {
+        unsafe { Port::<u8>::new(DSP_READ_STATUS).read() };
+    }
+    unsafe { reset.write(0) };
+    match read_dsp() {
+        Ok(DSP_READY) => Ok(()),
+        _ => Err(Sb16Error::NotFound),
+    }
+}
+
+fn write_dsp(byte: u8) -> Result<(), Sb16Error> {
+    let mut port = Port::<u8>::new(DSP_WRITE);
+    for _ in 0..TIMEOUT {
+        if unsafe { port.read() } & 0x80 == 0 {
+            unsafe { port.write(byte) };
+            return Ok(());
+        }
+    }
+    Err(Sb16Error::NotFound)
+}
+
+fn read_dsp() -> Result<u8, Sb16Error> {
+    for _ in 0..TIMEOUT {
+        if unsafe { Port::<u8>::new(DSP_READ_STATUS).read() } & 0x80 != 0 {
+            return Ok(unsafe { Port::new(DSP_READ).read() });
+        }
+    }
+    Err(Sb16Error::NotFound)
+}
+
+fn write_mixer(register: u8, value: u8) {
+    unsafe {
+        Port::new(MIXER_ADDRESS).write(register);
+        Port::new(MIXER_DATA).write(value);
+    }
+}
+
+/// Points DMA channel 5 at the ring buffer, looping over it forever. 16-bit channels count
+/// in words, and their address is in words within a 128 KiB page.
+fn program_dma(physical_address: u64) {
+    let words = (BUFFER_SIZE / 2 - 1) as u16;
+    let address = ((physical_address >> 1) & 0xFFFF) as u16;
+    let page = ((physical_address >> 16) & 0xFE) as u8;
+    unsafe {
+        Port::new(DMA_MASK).write(DMA_MASK_ON | DMA_5);
+        Port::new(DMA_CLEAR_FLIP_FLOP).write(0u8);
+        Port::new(DMA_MODE).write(DMA_MODE_PLAYBACK | DMA_5);
+        let mut address_port = Port::new(DMA_5_ADDRESS);
+        address_port.write(address as u8);
+        address_port.write((address >> 8) as u8);
+        let mut count_port = Port::new(DMA_5_COUNT);
+        count_port.write(words as u8);
+        count_port.write((words >> 8) as u8);
+        Port::new(DMA_5_PAGE).write(page);
+        Port::new(DMA_MASK).write(DMA_5);
+    }
+}
@@ -1,10 +1,12 @@
 // Original code from rust-osdev/bootloader crate https://github.com/rust-osdev/bootloader
 
+use alloc::vec::Vec;
 use core::{fmt, ptr};
-use noto_sans_mono_bitmap::{FontWeight, get_raster, RasterizedChar};
+use noto_sans_mono_bitmap::{FontWeight, get_raster, get_raster_width, RasterHeight};
 use bootloader_api::info::{FrameBuffer, FrameBufferInfo, PixelFormat};
 use noto_sans_mono_bitmap::RasterHeight::Size16;
 use kernel::RacyCell;
+use crate::image::Image;
 
 
 
@@ -34,8 +36,140 @@ pub fn init(buffer: &'static mut FrameBuffer) {
 /// Additional vertical space between lines
 const LINE_SPACING: usize = 0;
 
+/// Width of one character of the default font.
+pub const CHAR_WIDTH: usize = get_raster_width(FontWeight::Regular, Size16);
+
+/// Height of one line of text in the default font, including the line spacing.
+pub const LINE_HEIGHT: usize = Size16 as usize + LINE_SPACING;
+
+/// Drawn in place of characters the font has no glyph for.
+const REPLACEMENT_CHAR: char = '\u{FFFD}';
+
+/// Colors, size and weight used to draw text. The font comes in 16, 20, 24 and 32 pixel rasters,
+/// each in light, regular and bold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextStyle {
+    pub foreground: (u8, u8, u8),
+    /// Fills the whole character cell behind the glyph. With `None` the glyph is alpha-blended
+    /// over whatever is already on screen.
+    pub background: Option<(u8, u8, u8)>,
+    pub height: RasterHeight,
+    pub weight: FontWeight,
+}
+
+impl TextStyle {
+    /// Regular 16 pixel text in the given color on a black background.
+    pub const fn new(foreground: (u8, u8, u8)) -> Self {
+        TextStyle {
+            foreground,
+            background: Some((0, 0, 0)),
+            height: Size16,
+            weight: FontWeight::Regular,
+        }
+    }
+
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub const fn background(mut self, background: Option<(u8, u8, u8)>) -> Self {
+        self.background = background;
+        self
+    }
+
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub const fn size(mut self, height: RasterHeight) -> Self {
+        self.height = height;
+        self
+    }
+
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub const fn weight(mut self, weight: FontWeight) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub const fn char_width(&self) -> usize {
+        get_raster_width(self.weight, self.height)
+    }
+
+    pub const fn line_height(&self) -> usize {
+        self.height as usize + LINE_SPACING
+    }
+
+    /// Width in pixels of `text` drawn on a single line.
+    pub fn text_width(&self, text: &str) -> usize {
+        text.chars().count() * self.char_width()
+    }
+}
+
+impl Default for TextStyle {
+    /// The green-on-black look of the original logger.
+    fn default() -> Self {
+        TextStyle::new((0x3f, 0xff, 0x7f))
+    }
+}
+
+/// Number of dirty rectangles tracked between two calls to `present`. Once they are all in use,
+/// new rectangles get merged into the existing one that grows the least.
+const MAX_DIRTY_RECTS: usize = 64;
+
+/// An axis-aligned rectangle in screen coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Rect { x, y, width, height }
+    }
+
+    pub fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> usize {
+        self.y + self.height
+    }
+
+    pub fn area(&self) -> usize {
+        self.width * self.height
+    }
+
+    /// Smallest rectangle containing both `self` and `other`.
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect::new(x, y, self.right().max(other.right()) - x, self.bottom().max(other.bottom()) - y)
+    }
+
+    /// True if the two rectangles have at least one pixel in common.
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.x < other.right()
+            && other.x < self.right()
+            && self.y < other.bottom()
+            && other.y < self.bottom()
+    }
+
+    /// True if the two rectangles overlap or share an edge.
+    fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.right()
+            && other.x <= self.right()
+            && self.y <= other.bottom()
+            && other.y <= self.bottom()
+    }
+}
+
 pub struct ScreenWriter {
     framebuffer: &'static mut [u8],
+    /// Off-screen copy of the framebuffer. When present, all drawing goes here and only reaches
+    /// the screen on `present`. `None` means immediate mode.
+    back_buffer: Option<Vec<u8>>,
+    dirty: [Rect; MAX_DIRTY_RECTS],
+    dirty_count: usize,
+    packer: PixelPacker,
+    style: TextStyle,
     pub info: FrameBufferInfo,
     x_pos: usize,
     y_pos: usize,
@@ -45,6 +179,11 @@ impl ScreenWriter {
     pub fn new(framebuffer: &'static mut [u8], info: FrameBufferInfo) -> Self {
         let mut logger = Self {
             framebuffer,
+            back_buffer: None,
+            dirty: [Rect::default(); MAX_DIRTY_RECTS],
+            dirty_count: 0,
+            packer: PixelPacker::new(info.pixel_format),
+            style: TextStyle::default(),
             info,
             x_pos: 0,
             y_pos: 0,
@@ -53,6 +192,65 @@ impl ScreenWriter {
         logger
     }
 
+    /// Tries to allocate an off-screen back buffer on the heap. From then on drawing is not
+    /// visible until [`present`](Self::present) is called. Returns `false` and stays in immediate
+    /// mode if the heap is too small to hold a copy of the framebuffer.
+    pub fn enable_double_buffering(&mut self) -> bool {
+        if self.back_buffer.is_some() {
+            return true;
+        }
+        let mut back_buffer = Vec::new();
+        if back_buffer.try_reserve_exact(self.framebuffer.len()).is_err() {
+            return false;
+        }
+        back_buffer.extend_from_slice(self.framebuffer);
+        self.back_buffer = Some(back_buffer);
+        self.dirty_count = 0;
+        true
+    }
+
+    pub fn is_double_buffered(&self) -> bool {
+        self.back_buffer.is_some()
+    }
+
+    /// Copies the parts of the back buffer that changed since the last call to the framebuffer.
+    /// Does nothing in immediate mode.
+    pub fn present(&mut self) {
+        if let Some(back_buffer) = self.back_buffer.as_ref() {
+            let stride = usize::from(self.info.stride);
+            let bytes_per_pixel = usize::from(self.info.bytes_per_pixel);
+            for rect in &self.dirty[..self.dirty_count] {
+                for y in rect.y..rect.bottom() {
+                    let start = (y * stride + rect.x) * bytes_per_pixel;
+                    let end = start + rect.width * bytes_per_pixel;
+                    self.framebuffer[start..end].copy_from_slice(&back_buffer[start..end]);
+                    let _ = unsafe { ptr::read_volatile(&self.framebuffer[start]) };
+                }
+            }
+        }
+        self.dirty_count = 0;
+    }
+
+    /// Records that `rect` has to be copied to the screen on the next `present`.
+    fn mark_dirty(&mut self, rect: Rect) {
+        if self.back_buffer.is_none() || rect.area() == 0 {
+            return;
+        }
+        let count = self.dirty_count;
+        if let Some(existing) = self.dirty[..count].iter_mut().find(|r| r.touches(&rect)) {
+            *existing = existing.union(&rect);
+        } else if count < MAX_DIRTY_RECTS {
+            self.dirty[count] = rect;
+            self.dirty_count += 1;
+        } else if let Some(existing) = self
+            .dirty
+            .iter_mut()
+            .min_by_key(|r| r.union(&rect).area() - r.area())
+        {
+            *existing = existing.union(&rect);
+        }
+    }
+
         /// Sets the position of the cursor.
     pub fn set_position(&mut self, x: usize, y: usize) {
         self.x_pos = x;
@@ -60,7 +258,7 @@ impl ScreenWriter {
     }
 
     fn newline(&mut self) {
-        self.y_pos += Size16 as usize + LINE_SPACING;
+        self.y_pos += self.style.line_height();
         self.carriage_return()
     }
 
@@ -72,85 +270,540 @@ impl ScreenWriter {
     pub fn clear(&mut self) {
         self.x_pos = 0;
         self.y_pos = 0;
-        self.framebuffer.fill(0);
+        match self.back_buffer.as_mut() {
+            Some(back_buffer) => {
+                back_buffer.fill(0);
+                self.dirty_count = 0;
+                self.mark_dirty(Rect::new(0, 0, self.width(), self.height()));
+            }
+            None => self.framebuffer.fill(0),
+        }
     }
 
-    fn width(&self) -> usize {
+    pub fn width(&self) -> usize {
         self.info.width.into()
     }
 
-    fn height(&self) -> usize {
+    pub fn height(&self) -> usize {
         self.info.height.into()
     }
 
+    /// Style used by `write!` and friends from now on.
+    pub fn set_style(&mut self, style: TextStyle) {
+        self.style = style;
+    }
+
+    pub fn style(&self) -> TextStyle {
+        self.style
+    }
+
+    /// Writes `text` at the cursor in the given style, leaving the current style untouched.
+    pub fn write_styled(&mut self, text: &str, style: TextStyle) {
+        let previous = self.style;
+        self.style = style;
+        for c in text.chars() {
+            self.write_char(c);
+        }
+        self.style = previous;
+    }
+
     fn write_char(&mut self, c: char) {
         match c {
             '\n' => self.newline(),
             '\r' => self.carriage_return(),
             c => {
-                match get_raster(c, FontWeight::Regular, Size16) {
-                    Some(bitmap_char) => {
-                        if self.x_pos + bitmap_char.width() > self.width() {
-                            self.newline();
-                        }
-                        if self.y_pos + bitmap_char.height() > self.height() {
-                            self.clear();
-                        }
-                        self.write_rendered_char(bitmap_char);
-                    },
-                    None => {}
+                let style = self.style;
+                if self.x_pos + style.char_width() > self.width() {
+                    self.newline();
+                }
+                if self.y_pos + style.height as usize > self.height() {
+                    self.clear();
                 }
+                self.x_pos += self.draw_char(self.x_pos, self.y_pos, c, &style);
             }
         }
     }
 
-    fn write_rendered_char(&mut self, rendered_char: RasterizedChar) {
-        for (y, row) in rendered_char.raster().iter().enumerate() {
-            for (x, byte) in row.iter().enumerate() {
-                self.write_pixel(self.x_pos + x, self.y_pos + y, *byte);
+    pub fn write_pixel(&mut self, x: usize, y: usize, intensity: u8) {
+        let color = self.encode(intensity / 4, intensity, intensity / 2);
+        self.put_pixel(x, y, color);
+    }
+
+    /// Draws a single pixel. Pixels outside the screen are ignored.
+    pub fn draw_pixel(&mut self, x: usize, y: usize, r: u8, g: u8, b: u8) {
+        let color = self.encode(r, g, b);
+        self.put_pixel(x, y, color);
+    }
+
+    /// Signed version of [`draw_pixel`](Self::draw_pixel) for things that slide off the top or
+    /// left edge of the screen.
+    pub fn draw_pixel_at(&mut self, x: isize, y: isize, r: u8, g: u8, b: u8) {
+        if x >= 0 && y >= 0 {
+            self.draw_pixel(x as usize, y as usize, r, g, b);
+        }
+    }
+
+    /// Fills a `width` x `height` rectangle. Parts outside the screen are clipped.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: (u8, u8, u8)) {
+        self.fill_rect_at(x as isize, y as isize, width, height, color);
+    }
+
+    /// Signed version of [`fill_rect`](Self::fill_rect); the rectangle may start off-screen.
+    pub fn fill_rect_at(&mut self, x: isize, y: isize, width: usize, height: usize, color: (u8, u8, u8)) {
+        if let Some(rect) = self.clip(x, y, width, height) {
+            let color = self.encode(color.0, color.1, color.2);
+            for row in rect.y..rect.bottom() {
+                self.put_span(rect.x, row, rect.width, color);
             }
         }
-        self.x_pos += rendered_char.width();
     }
 
-    pub fn write_pixel(&mut self, x: usize, y: usize, intensity: u8) {
-        let pixel_offset = y * usize::from(self.info.stride) + x;
-        let color = match self.info.pixel_format {
-            PixelFormat::Rgb => [intensity / 4, intensity, intensity / 2, 0],
-            PixelFormat::Bgr => [intensity / 2, intensity, intensity / 4, 0],
-            other => {
-                // set a supported (but invalid) pixel format before panicking to avoid a double
-                // panic; it might not be readable though
-                self.info.pixel_format = PixelFormat::Rgb;
-                panic!("pixel format {:?} not supported in logger", other)
+    /// Draws the one pixel wide outline of a `width` x `height` rectangle.
+    pub fn draw_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: (u8, u8, u8)) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.draw_hline(x, y, width, color);
+        self.draw_hline(x, y.saturating_add(height - 1), width, color);
+        self.fill_rect(x, y, 1, height, color);
+        self.fill_rect(x.saturating_add(width - 1), y, 1, height, color);
+    }
+
+    /// Draws a horizontal line of `length` pixels starting at (x, y), one row at a time.
+    pub fn draw_hline(&mut self, x: usize, y: usize, length: usize, color: (u8, u8, u8)) {
+        self.fill_rect(x, y, length, 1, color);
+    }
+
+    /// Draws a vertical line of `length` pixels starting at (x, y).
+    pub fn draw_vline(&mut self, x: usize, y: usize, length: usize, color: (u8, u8, u8)) {
+        self.fill_rect(x, y, 1, length, color);
+    }
+
+    /// Draws a line from (x0, y0) to (x1, y1) using Bresenham's algorithm.
+    pub fn draw_line(&mut self, x0: usize, y0: usize, x1: usize, y1: usize, color: (u8, u8, u8)) {
+        let color = self.encode(color.0, color.1, color.2);
+        let (mut x, mut y) = (x0 as isize, y0 as isize);
+        let (x1, y1) = (x1 as isize, y1 as isize);
+        let dx = (x1 - x).abs();
+        let dy = -(y1 - y).abs();
+        let step_x = if x < x1 { 1 } else { -1 };
+        let step_y = if y < y1 { 1 } else { -1 };
+        let mut error = dx + dy;
+        loop {
+            self.put_clipped_span(x, y, 1, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Draws the outline of a circle centered on (cx, cy) with the midpoint algorithm.
+    pub fn draw_circle(&mut self, cx: usize, cy: usize, radius: usize, color: (u8, u8, u8)) {
+        let color = self.encode(color.0, color.1, color.2);
+        let (cx, cy) = (cx as isize, cy as isize);
+        for (x, y) in CirclePoints::new(radius) {
+            for (px, py) in [(x, y), (y, x), (-y, x), (-x, y), (-x, -y), (-y, -x), (y, -x), (x, -y)] {
+                self.put_clipped_span(cx + px, cy + py, 1, color);
+            }
+        }
+    }
+
+    /// Fills a circle centered on (cx, cy), one horizontal span per row.
+    pub fn fill_circle(&mut self, cx: usize, cy: usize, radius: usize, color: (u8, u8, u8)) {
+        let color = self.encode(color.0, color.1, color.2);
+        let (cx, cy) = (cx as isize, cy as isize);
+        for (x, y) in CirclePoints::new(radius) {
+            self.put_clipped_span(cx - x, cy + y, (2 * x + 1) as usize, color);
+            self.put_clipped_span(cx - x, cy - y, (2 * x + 1) as usize, color);
+            self.put_clipped_span(cx - y, cy + x, (2 * y + 1) as usize, color);
+            self.put_clipped_span(cx - y, cy - x, (2 * y + 1) as usize, color);
+        }
+    }
+
+    /// Copies a `width` x `height` block of RGBA pixels (4 bytes each, row by row) to (x, y).
+    /// Fully transparent pixels are skipped, partially transparent ones are blended with what is
+    /// already on screen. Parts outside the screen are clipped. Nothing is drawn if `rgba` is
+    /// shorter than `width * height * 4` bytes.
+    pub fn blit(&mut self, x: usize, y: usize, width: usize, height: usize, rgba: &[u8]) {
+        self.blit_at(x as isize, y as isize, width, height, rgba);
+    }
+
+    /// Signed version of [`blit`](Self::blit); the image may hang over any edge of the screen.
+    pub fn blit_at(&mut self, x: isize, y: isize, width: usize, height: usize, rgba: &[u8]) {
+        self.blit_scaled(x, y, width, height, rgba, 1, None);
+    }
+
+    /// Draws a decoded image with its top left corner at (x, y), each image pixel becoming a
+    /// `scale` x `scale` block. Transparency works as in [`blit`](Self::blit).
+    pub fn draw_image(&mut self, image: &Image, x: isize, y: isize, scale: usize) {
+        self.blit_scaled(x, y, image.width(), image.height(), image.pixels(), scale, None);
+    }
+
+    /// Like [`draw_image`](Self::draw_image), but multiplies every pixel by `tint`, so a white
+    /// image can be drawn in any color.
+    pub fn draw_image_tinted(
+        &mut self,
+        image: &Image,
+        x: isize,
+        y: isize,
+        scale: usize,
+        tint: (u8, u8, u8),
+    ) {
+        self.blit_scaled(x, y, image.width(), image.height(), image.pixels(), scale, Some(tint));
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn blit_scaled(
+        &mut self,
+        x: isize,
+        y: isize,
+        width: usize,
+        height: usize,
+        rgba: &[u8],
+        scale: usize,
+        tint: Option<(u8, u8, u8)>,
+    ) {
+        let scale = scale.max(1);
+        // A slice too short for the size given, or a size too big to exist, draws nothing rather
+        // than taking the kernel down
+        let needed = width.checked_mul(height).and_then(|pixels| pixels.checked_mul(4));
+        if needed.is_none_or(|needed| rgba.len() < needed) {
+            return;
+        }
+        let (Some(scaled_width), Some(scaled_height)) = (width.checked_mul(scale), height.checked_mul(scale)) else {
+            return;
+        };
+        let Some(visible) = self.clip(x, y, scaled_width, scaled_height) else {
+            return;
+        };
+        for row in visible.y..visible.bottom() {
+            let source_row = (row as isize - y) as usize / scale * width;
+            for column in visible.x..visible.right() {
+                let offset = (source_row + (column as isize - x) as usize / scale) * 4;
+                let [mut r, mut g, mut b, a] =
+                    [rgba[offset], rgba[offset + 1], rgba[offset + 2], rgba[offset + 3]];
+                if let Some(tint) = tint {
+                    r = multiply(r, tint.0);
+                    g = multiply(g, tint.1);
+                    b = multiply(b, tint.2);
+                }
+                match a {
+                    0 => {}
+                    255 => self.draw_pixel(column, row, r, g, b),
+                    alpha => {
+                        let (dr, dg, db) = self.read_pixel(column, row);
+                        self.draw_pixel(
+                            column,
+                            row,
+                            blend(r, dr, alpha),
+                            blend(g, dg, alpha),
+                            blend(b, db, alpha),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws `c` with its top left corner at (x, y) and returns how far to advance to the next
+    /// character. The glyph's intensity is used as alpha, blending the foreground over the style's
+    /// background or, without one, over what is already on screen. Characters missing from the
+    /// font get a replacement glyph.
+    pub fn draw_char(&mut self, x: usize, y: usize, c: char, style: &TextStyle) -> usize {
+        let foreground = style.foreground;
+        let Some(rendered_char) = get_raster(c, style.weight, style.height)
+            .or_else(|| get_raster(REPLACEMENT_CHAR, style.weight, style.height))
+        else {
+            if let Some(background) = style.background {
+                self.fill_rect(x, y, style.char_width(), style.line_height(), background);
             }
+            return style.char_width();
         };
-        let bytes_per_pixel = self.info.bytes_per_pixel;
-        let byte_offset = pixel_offset * usize::from(bytes_per_pixel);
-        self.framebuffer[byte_offset..(byte_offset + usize::from(bytes_per_pixel))]
-            .copy_from_slice(&color[..usize::from(bytes_per_pixel)]);
-        let _ = unsafe { ptr::read_volatile(&self.framebuffer[byte_offset]) };
+        for (dy, row) in rendered_char.raster().iter().enumerate() {
+            for (dx, &intensity) in row.iter().enumerate() {
+                let background = match style.background {
+                    Some(background) => background,
+                    None if intensity == 0 => continue,
+                    None => self.read_pixel(x + dx, y + dy),
+                };
+                self.draw_pixel(
+                    x + dx,
+                    y + dy,
+                    blend(foreground.0, background.0, intensity),
+                    blend(foreground.1, background.1, intensity),
+                    blend(foreground.2, background.2, intensity),
+                );
+            }
+        }
+        rendered_char.width()
     }
 
-    pub fn draw_pixel(&mut self, x: usize, y: usize, r: u8, g: u8, b: u8) {
-        let pixel_offset = y * usize::from(self.info.stride) + x;
-        let color = match self.info.pixel_format {
-            PixelFormat::Rgb => [r, g, b, 0],
-            PixelFormat::Bgr => [b, g, r, 0],
-            other => {
-                // set a supported (but invalid) pixel format before panicking to avoid a double
-                // panic; it might not be readable though
-                self.info.pixel_format = PixelFormat::Rgb;
-                panic!("pixel format {:?} not supported in logger", other)
+    /// Moves everything inside `area` up by `pixels` rows and fills the rows uncovered at the
+    /// bottom with `fill`. Full-width areas are moved with a single memmove.
+    pub fn scroll_up(&mut self, area: Rect, pixels: usize, fill: (u8, u8, u8)) {
+        let Some(area) = self.clip(area.x as isize, area.y as isize, area.width, area.height) else {
+            return;
+        };
+        if pixels >= area.height {
+            self.fill_rect(area.x, area.y, area.width, area.height, fill);
+            return;
+        }
+        let stride = usize::from(self.info.stride);
+        let bytes_per_pixel = usize::from(self.info.bytes_per_pixel);
+        let full_width = area.x == 0 && area.width == self.width();
+        let double_buffered = self.back_buffer.is_some();
+        let buffer = match self.back_buffer.as_mut() {
+            Some(back_buffer) => &mut back_buffer[..],
+            None => &mut self.framebuffer[..],
+        };
+        let kept_rows = area.height - pixels;
+        if full_width {
+            let destination = area.y * stride * bytes_per_pixel;
+            let source = destination + pixels * stride * bytes_per_pixel;
+            buffer.copy_within(source..source + kept_rows * stride * bytes_per_pixel, destination);
+        } else {
+            for row in area.y..area.y + kept_rows {
+                let destination = (row * stride + area.x) * bytes_per_pixel;
+                let source = destination + pixels * stride * bytes_per_pixel;
+                buffer.copy_within(source..source + area.width * bytes_per_pixel, destination);
+            }
+        }
+        if double_buffered {
+            self.mark_dirty(area);
+        }
+        self.fill_rect(area.x, area.y + kept_rows, area.width, pixels, fill);
+    }
+
+    /// Inverts every pixel inside the given rectangle. Doing it twice restores the original.
+    pub fn invert_rect(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        let Some(rect) = self.clip(x as isize, y as isize, width, height) else {
+            return;
+        };
+        for row in rect.y..rect.bottom() {
+            for column in rect.x..rect.right() {
+                let (r, g, b) = self.read_pixel(column, row);
+                self.draw_pixel(column, row, !r, !g, !b);
             }
+        }
+    }
+
+    /// Copies the raw pixels of a rectangle, which must already be clipped to the screen, into
+    /// `saved`, replacing its contents. [`restore_rect`](Self::restore_rect) puts them back.
+    pub fn save_rect(&self, rect: Rect, saved: &mut Vec<u8>) {
+        let stride = self.info.stride;
+        let bytes_per_pixel = self.info.bytes_per_pixel;
+        let source = match self.back_buffer.as_ref() {
+            Some(back_buffer) => &back_buffer[..],
+            None => &self.framebuffer[..],
         };
+        saved.clear();
+        for row in rect.y..rect.bottom() {
+            let start = (row * stride + rect.x) * bytes_per_pixel;
+            saved.extend_from_slice(&source[start..start + rect.width * bytes_per_pixel]);
+        }
+    }
+
+    /// Writes pixels saved by [`save_rect`](Self::save_rect) back into the same rectangle.
+    pub fn restore_rect(&mut self, rect: Rect, saved: &[u8]) {
+        let stride = self.info.stride;
         let bytes_per_pixel = self.info.bytes_per_pixel;
-        let byte_offset = pixel_offset * usize::from(bytes_per_pixel);
-        self.framebuffer[byte_offset..(byte_offset + usize::from(bytes_per_pixel))]
-            .copy_from_slice(&color[..usize::from(bytes_per_pixel)]);
-        let _ = unsafe { ptr::read_volatile(&self.framebuffer[byte_offset]) };
+        let row_bytes = rect.width * bytes_per_pixel;
+        if row_bytes == 0 || saved.len() < rect.height * row_bytes {
+            return;
+        }
+        let double_buffered = self.back_buffer.is_some();
+        let target = match self.back_buffer.as_mut() {
+            Some(back_buffer) => &mut back_buffer[..],
+            None => &mut self.framebuffer[..],
+        };
+        for (row, pixels) in (rect.y..rect.bottom()).zip(saved.chunks_exact(row_bytes)) {
+            let start = (row * stride + rect.x) * bytes_per_pixel;
+            target[start..start + row_bytes].copy_from_slice(pixels);
+        }
+        if double_buffered {
+            self.mark_dirty(rect);
+        }
+    }
+
+    /// Reads back the color of a pixel, from the back buffer if there is one. Pixels outside the
+    /// screen read as black.
+    pub fn read_pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+        if x >= self.width() || y >= self.height() {
+            return (0, 0, 0);
+        }
+        let bytes_per_pixel = usize::from(self.info.bytes_per_pixel);
+        let byte_offset = (y * usize::from(self.info.stride) + x) * bytes_per_pixel;
+        let source = match self.back_buffer.as_ref() {
+            Some(back_buffer) => &back_buffer[byte_offset..],
+            None => &self.framebuffer[byte_offset..],
+        };
+        self.packer.unpack(&source[..bytes_per_pixel])
+    }
+
+    /// Intersects a rectangle with the screen. Returns `None` if nothing of it is visible.
+    pub fn clip(&self, x: isize, y: isize, width: usize, height: usize) -> Option<Rect> {
+        let left = x.max(0);
+        let top = y.max(0);
+        let right = x.saturating_add(width as isize).min(self.width() as isize);
+        let bottom = y.saturating_add(height as isize).min(self.height() as isize);
+        if left >= right || top >= bottom {
+            None
+        } else {
+            Some(Rect::new(left as usize, top as usize, (right - left) as usize, (bottom - top) as usize))
+        }
+    }
+
+    fn encode(&self, r: u8, g: u8, b: u8) -> [u8; 4] {
+        self.packer.pack(r, g, b)
+    }
+
+    /// Stores an already encoded pixel, either in the back buffer or straight on the screen.
+    /// Pixels outside the screen are dropped.
+    fn put_pixel(&mut self, x: usize, y: usize, color: [u8; 4]) {
+        if x < self.width() && y < self.height() {
+            self.put_span(x, y, 1, color);
+        }
+    }
+
+    /// Clips a horizontal span to the screen before writing it.
+    fn put_clipped_span(&mut self, x: isize, y: isize, length: usize, color: [u8; 4]) {
+        if let Some(span) = self.clip(x, y, length, 1) {
+            self.put_span(span.x, span.y, span.width, color);
+        }
+    }
+
+    /// Writes `length` pixels of the same encoded color in one row, starting at (x, y). The span
+    /// must already be clipped to the screen.
+    fn put_span(&mut self, x: usize, y: usize, length: usize, color: [u8; 4]) {
+        let bytes_per_pixel = usize::from(self.info.bytes_per_pixel);
+        let start = (y * usize::from(self.info.stride) + x) * bytes_per_pixel;
+        let end = start + length * bytes_per_pixel;
+        let double_buffered = self.back_buffer.is_some();
+        let target = match self.back_buffer.as_mut() {
+            Some(back_buffer) => &mut back_buffer[start..end],
+            None => &mut self.framebuffer[start..end],
+        };
+        for pixel in target.chunks_exact_mut(bytes_per_pixel) {
+            pixel.copy_from_slice(&color[..bytes_per_pixel]);
+        }
+        if double_buffered {
+            self.mark_dirty(Rect::new(x, y, length, 1));
+        } else {
+            let _ = unsafe { ptr::read_volatile(&self.framebuffer[start]) };
+        }
+    }
+}
+
+/// Converts between (r, g, b) colors and the bytes of one framebuffer pixel. It is worked out
+/// once from the pixel format, so drawing doesn't have to look at the format for every pixel.
+#[derive(Clone, Copy)]
+struct PixelPacker {
+    pack: fn(&PixelPacker, u8, u8, u8) -> [u8; 4],
+    unpack: fn(&PixelPacker, &[u8]) -> (u8, u8, u8),
+    /// Bit offsets of the red, green and blue values inside a little-endian pixel.
+    shifts: [u8; 3],
+}
+
+impl PixelPacker {
+    fn new(format: PixelFormat) -> Self {
+        match format {
+            PixelFormat::Rgb => Self::masked(0, 8, 16),
+            PixelFormat::Bgr => Self::masked(16, 8, 0),
+            PixelFormat::Unknown { red_position, green_position, blue_position } => {
+                Self::masked(red_position, green_position, blue_position)
+            }
+            PixelFormat::U8 => PixelPacker { pack: pack_gray, unpack: unpack_gray, shifts: [0; 3] },
+            other => panic!("pixel format {:?} not supported in logger", other),
+        }
+    }
+
+    fn masked(red: u8, green: u8, blue: u8) -> Self {
+        PixelPacker { pack: pack_masked, unpack: unpack_masked, shifts: [red, green, blue] }
+    }
+
+    fn pack(&self, r: u8, g: u8, b: u8) -> [u8; 4] {
+        (self.pack)(self, r, g, b)
     }
 
+    fn unpack(&self, pixel: &[u8]) -> (u8, u8, u8) {
+        (self.unpack)(self, pixel)
+    }
+}
+
+fn pack_masked(packer: &PixelPacker, r: u8, g: u8, b: u8) -> [u8; 4] {
+    let [red, green, blue] = packer.shifts;
+    ((u32::from(r) << red) | (u32::from(g) << green) | (u32::from(b) << blue)).to_le_bytes()
+}
+
+fn unpack_masked(packer: &PixelPacker, pixel: &[u8]) -> (u8, u8, u8) {
+    let mut bytes = [0; 4];
+    bytes[..pixel.len()].copy_from_slice(pixel);
+    let value = u32::from_le_bytes(bytes);
+    let [red, green, blue] = packer.shifts;
+    ((value >> red) as u8, (value >> green) as u8, (value >> blue) as u8)
+}
+
+/// Grayscale framebuffers store one luminance byte per pixel (ITU-R BT.601 weights).
+fn pack_gray(_packer: &PixelPacker, r: u8, g: u8, b: u8) -> [u8; 4] {
+    let luma = (u32::from(r) * 77 + u32::from(g) * 150 + u32::from(b) * 29) >> 8;
+    [luma as u8, 0, 0, 0]
+}
+
+fn unpack_gray(_packer: &PixelPacker, pixel: &[u8]) -> (u8, u8, u8) {
+    (pixel[0], pixel[0], pixel[0])
+}
+
+/// Scales a color channel by a tint channel, treating 255 as 1.
+fn multiply(channel: u8, tint: u8) -> u8 {
+    (u16::from(channel) * u16::from(tint) / 255) as u8
+}
+
+/// Mixes a source channel over a destination channel with the given alpha.
+fn blend(source: u8, destination: u8, alpha: u8) -> u8 {
+    let alpha = u16::from(alpha);
+    ((u16::from(source) * alpha + u16::from(destination) * (255 - alpha)) / 255) as u8
+}
+
+/// Walks one octant of a circle with the midpoint algorithm, yielding (x, y) offsets from the
+/// center with `x >= y`. The other seven octants follow by symmetry.
+struct CirclePoints {
+    x: isize,
+    y: isize,
+    error: isize,
+}
+
+impl CirclePoints {
+    fn new(radius: usize) -> Self {
+        CirclePoints { x: radius as isize, y: 0, error: 1 - radius as isize }
+    }
+}
+
+impl Iterator for CirclePoints {
+    type Item = (isize, isize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.x < self.y {
+            return None;
+        }
+        let point = (self.x, self.y);
+        self.y += 1;
+        if self.error < 0 {
+            self.error += 2 * self.y + 1;
+        } else {
+            self.x -= 1;
+            self.error += 2 * (self.y - self.x) + 1;
+        }
+        Some(point)
+    }
 }
 
 unsafe impl Send for ScreenWriter {}
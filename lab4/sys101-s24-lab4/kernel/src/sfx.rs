@@ -0,0 +1,130 @@
+use alloc::vec::Vec;
+use kernel::sb16::{self, Pcm, SAMPLE_RATE};
+use kernel::speaker::{self, Note};
+use lazy_static::lazy_static;
+
+/// The game's sound effects. They play on the Sound Blaster, mixed together, when there is one,
+/// and fall back to beeps on the PC speaker otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    Shoot,
+    Explosion,
+    GameOver,
+}
+
+// Speaker versions, with note lengths in timer ticks
+const SHOOT_NOTES: &[Note] = &[Note::new(1200, 1), Note::new(900, 1)];
+const EXPLOSION_NOTES: &[Note] = &[Note::new(220, 1), Note::new(150, 1), Note::new(100, 2)];
+const GAME_OVER_NOTES: &[Note] = &[
+    Note::new(392, 3),
+    Note::new(330, 3),
+    Note::new(262, 3),
+    Note::new(196, 8),
+];
+
+/// Samples in one timer tick, about 55 ms.
+const TICK_SAMPLES: usize = SAMPLE_RATE as usize * 55 / 1000;
+const VOLUME: u8 = 160;
+
+lazy_static! {
+    // Sound Blaster versions, synthesized by `init`
+    static ref SHOOT_PCM: Vec<i16> = sweep(1400, 400, TICK_SAMPLES * 3, 6000);
+    static ref EXPLOSION_PCM: Vec<i16> = noise(TICK_SAMPLES * 8, 9000);
+    static ref GAME_OVER_PCM: Vec<i16> = melody(GAME_OVER_NOTES, 6000);
+}
+
+/// Synthesizes the Sound Blaster effects up front, rather than in the middle of a game tick the
+/// first time each one plays.
+pub fn init() {
+    if sb16::is_available() {
+        lazy_static::initialize(&SHOOT_PCM);
+        lazy_static::initialize(&EXPLOSION_PCM);
+        lazy_static::initialize(&GAME_OVER_PCM);
+    }
+}
+
+/// Plays an effect, on top of whatever the Sound Blaster is already playing.
+pub fn play(effect: Effect) {
+    if sb16::is_available() {
+        let pcm = match effect {
+            Effect::Shoot => &SHOOT_PCM[..],
+            Effect::Explosion => &EXPLOSION_PCM[..],
+            Effect::GameOver => &GAME_OVER_PCM[..],
+        };
+        sb16::play(Pcm::I16(pcm), VOLUME);
+    } else {
+        speaker::play(match effect {
+            Effect::Shoot => SHOOT_NOTES,
+            Effect::Explosion => EXPLOSION_NOTES,
+            Effect::GameOver => GAME_OVER_NOTES,
+        });
+    }
+}
+
+/// A square wave sliding from one frequency to another, fading out as it goes.
+fn sweep(from: u32, to: u32, length: usize, amplitude: i16) -> Vec<i16> {
+    let mut samples = Vec::with_capacity(length);
+    let mut phase = 0u32;
+    for i in 0..length {
+        let frequency = from as i64 + (to as i64 - from as i64) * i as i64 / length as i64;
+        phase = phase.wrapping_add(square_step(frequency as u32));
+        samples.push(fade(square(phase, amplitude), i, length));
+    }
+    samples
+}
+
+/// Rumbling white noise, smoothed to take the hiss off, fading out.
+fn noise(length: usize, amplitude: i16) -> Vec<i16> {
+    let mut samples = Vec::with_capacity(length);
+    let mut state = 0x2545_f491u32;
+    let mut smoothed = 0i32;
+    for i in 0..length {
+        // xorshift32
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let white = (state >> 16) as i32 - 0x8000;
+        smoothed = (smoothed * 7 + white * amplitude as i32 / 0x8000) / 8;
+        samples.push(fade(smoothed as i16, i, length));
+    }
+    samples
+}
+
+/// The same notes the speaker plays, as square waves.
+fn melody(notes: &[Note], amplitude: i16) -> Vec<i16> {
+    let length = notes.iter().map(|note| note.ticks as usize).sum::<usize>() * TICK_SAMPLES;
+    let mut samples = Vec::with_capacity(length);
+    for note in notes {
+        let step = square_step(note.frequency);
+        let mut phase = 0u32;
+        for _ in 0..note.ticks as usize * TICK_SAMPLES {
+            phase = phase.wrapping_add(step);
+            let sample = if note.frequency == 0 {
+                0
+            } else {
+                square(phase, amplitude)
+            };
+            samples.push(sample);
+        }
+    }
+    samples
+}
+
+/// How far a square wave of the given frequency moves on in one sample, with a whole period
+/// being 2^32.
+fn square_step(frequency: u32) -> u32 {
+    (((frequency as u64) << 32) / SAMPLE_RATE as u64) as u32
+}
+
+fn square(phase: u32, amplitude: i16) -> i16 {
+    if phase < 1 << 31 {
+        amplitude
+    } else {
+        -amplitude
+    }
+}
+
+/// Scales a sample down linearly to silence at the end of the sound.
+fn fade(sample: i16, index: usize, length: usize) -> i16 {
+    (sample as i64 * (length - index) as i64 / length as i64) as i16
+}
@@ -0,0 +1,135 @@
+//! The PC speaker, driven by channel 2 of the programmable interval timer (PIT).
+//!
+//! Sounds never block: [`beep`] and [`play`] start them and the timer interrupt moves on to the
+//! next note, so durations are counted in timer ticks (about 55 ms each).
+
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+/// Input clock of the PIT, in Hz.
+const PIT_FREQUENCY: u32 = 1_193_182;
+const PIT_CHANNEL_2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+/// Channel 2, low byte then high byte, mode 3 (square wave), binary counting.
+const CHANNEL_2_SQUARE_WAVE: u8 = 0b1011_0110;
+
+/// Port B of the keyboard controller. Bit 0 gates PIT channel 2 and bit 1 connects its output
+/// to the speaker.
+const SPEAKER_PORT: u16 = 0x61;
+const SPEAKER_ON: u8 = 0b11;
+
+/// One note of a melody. A frequency of 0 is a rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note {
+    /// Pitch in Hz.
+    pub frequency: u32,
+    /// How long the note lasts, in timer ticks.
+    pub ticks: u32,
+}
+
+impl Note {
+    pub const fn new(frequency: u32, ticks: u32) -> Self {
+        Note { frequency, ticks }
+    }
+
+    /// Silence for the given number of ticks.
+    pub const fn rest(ticks: u32) -> Self {
+        Note::new(0, ticks)
+    }
+}
+
+/// Where the speaker is in the sound it is playing.
+struct Sequencer {
+    /// Notes still to come after the current one.
+    queue: &'static [Note],
+    /// Ticks left of the current note. 0 when nothing is playing.
+    remaining: u32,
+}
+
+static SEQUENCER: Mutex<Sequencer> = Mutex::new(Sequencer {
+    queue: &[],
+    remaining: 0,
+});
+
+/// Starts a tone that keeps sounding until [`stop`] is called or another sound starts.
+pub fn start_tone(frequency: u32) {
+    if frequency == 0 {
+        silence();
+        return;
+    }
+    let divisor = (PIT_FREQUENCY / frequency).clamp(1, u16::MAX as u32) as u16;
+    unsafe {
+        Port::new(PIT_COMMAND).write(CHANNEL_2_SQUARE_WAVE);
+        let mut channel = Port::new(PIT_CHANNEL_2);
+        channel.write(divisor as u8);
+        channel.write((divisor >> 8) as u8);
+
+        let mut speaker = Port::<u8>::new(SPEAKER_PORT);
+        let state = speaker.read();
+        if state & SPEAKER_ON != SPEAKER_ON {
+            speaker.write(state | SPEAKER_ON);
+        }
+    }
+}
+
+/// Silences the speaker and drops whatever was still queued.
+pub fn stop() {
+    let mut sequencer = SEQUENCER.lock();
+    sequencer.queue = &[];
+    sequencer.remaining = 0;
+    silence();
+}
+
+/// Plays a tone for `ticks` timer ticks, replacing whatever was playing.
+pub fn beep(frequency: u32, ticks: u32) {
+    let mut sequencer = SEQUENCER.lock();
+    sequencer.queue = &[];
+    sequencer.remaining = ticks.max(1);
+    start_tone(frequency);
+}
+
+/// Plays a melody, replacing whatever was playing.
+pub fn play(melody: &'static [Note]) {
+    let mut sequencer = SEQUENCER.lock();
+    sequencer.queue = melody;
+    sequencer.remaining = 0;
+    next_note(&mut sequencer);
+}
+
+/// True while a beep or melody is sounding.
+pub fn is_playing() -> bool {
+    SEQUENCER.lock().remaining > 0
+}
+
+/// Counts one timer tick of the current note and moves on to the next when it is over. Called by
+/// the timer interrupt handler.
+pub(crate) fn tick() {
+    let mut sequencer = SEQUENCER.lock();
+    if sequencer.remaining == 0 {
+        return;
+    }
+    sequencer.remaining -= 1;
+    if sequencer.remaining == 0 {
+        next_note(&mut sequencer);
+    }
+}
+
+fn next_note(sequencer: &mut Sequencer) {
+    match sequencer.queue.split_first() {
+        Some((note, rest)) => {
+            sequencer.queue = rest;
+            sequencer.remaining = note.ticks.max(1);
+            start_tone(note.frequency);
+        }
+        None => silence(),
+    }
+}
+
+/// Disconnects the speaker without touching the sequencer.
+fn silence() {
+    let mut speaker = Port::<u8>::new(SPEAKER_PORT);
+    unsafe {
+        let state = speaker.read();
+        speaker.write(state & !SPEAKER_ON);
+    }
+}
@@ -0,0 +1,410 @@
+use crate::image::Image;
+use crate::screen::{Rect, ScreenWriter};
+use alloc::vec::Vec;
+
+/// Most sprites one [`Scene`] can hold.
+pub const MAX_SPRITES: usize = 256;
+
+/// What a sprite looks like.
+#[derive(Clone, Copy)]
+pub enum Look {
+    /// A filled rectangle.
+    Solid {
+        width: usize,
+        height: usize,
+        color: (u8, u8, u8),
+    },
+    /// Animation frames shown one after another. Every image pixel becomes a `scale` x `scale`
+    /// square and is multiplied by `tint`.
+    Frames {
+        frames: &'static [Image],
+        scale: usize,
+        tint: (u8, u8, u8),
+    },
+    /// A `width` x `height` bitmask kept by the sprite, which can be changed after it is made.
+    /// Every set bit becomes a `scale` x `scale` square of `color`.
+    Bitmap {
+        width: usize,
+        height: usize,
+        scale: usize,
+        color: (u8, u8, u8),
+    },
+}
+
+/// Where and how a sprite was last drawn.
+#[derive(Clone, Copy)]
+struct Shown {
+    x: isize,
+    y: isize,
+    frame: usize,
+    /// The part of the sprite that landed on screen.
+    area: Option<Rect>,
+    /// Whether the pixels under `area` were saved. If not, erasing paints it black.
+    saved: bool,
+}
+
+/// Something drawn on top of the background that can move and animate. Before drawing, the
+/// sprite saves the pixels it is about to cover and puts them back when it is erased, so it can
+/// be moved over anything without knowing the background color.
+pub struct Sprite {
+    look: Look,
+    frame: usize,
+    /// Timer ticks each frame stays up; 0 means frames only change on `next_frame`.
+    frame_ticks: usize,
+    ticks: usize,
+    looping: bool,
+    finished: bool,
+    z: i32,
+    x: isize,
+    y: isize,
+    shown: Option<Shown>,
+    /// Raw pixels that were under the sprite when it was drawn.
+    under: Vec<u8>,
+    /// The bits of a [`Look::Bitmap`], row by row.
+    bits: Vec<bool>,
+    /// Whether `bits` changed since the sprite was last drawn.
+    changed: bool,
+}
+
+impl Sprite {
+    pub fn new(look: Look) -> Self {
+        Sprite {
+            look,
+            frame: 0,
+            frame_ticks: 0,
+            ticks: 0,
+            looping: true,
+            finished: false,
+            z: 0,
+            x: 0,
+            y: 0,
+            shown: None,
+            under: Vec::new(),
+            bits: Vec::new(),
+            changed: false,
+        }
+    }
+
+    /// A sprite that is just a filled rectangle.
+    pub fn solid(width: usize, height: usize, color: (u8, u8, u8)) -> Self {
+        Sprite::new(Look::Solid {
+            width,
+            height,
+            color,
+        })
+    }
+
+    /// A sprite cycling through `frames`, tinted with `tint`.
+    pub fn animated(frames: &'static [Image], scale: usize, tint: (u8, u8, u8)) -> Self {
+        Sprite::new(Look::Frames {
+            frames,
+            scale,
+            tint,
+        })
+    }
+
+    /// A sprite showing the bitmask `bits`, `width` bits a row, in `color`. Missing bits are
+    /// clear.
+    pub fn bitmap(
+        width: usize,
+        height: usize,
+        scale: usize,
+        color: (u8, u8, u8),
+        mut bits: Vec<bool>,
+    ) -> Self {
+        bits.resize(width * height, false);
+        let mut sprite = Sprite::new(Look::Bitmap {
+            width,
+            height,
+            scale,
+            color,
+        });
+        sprite.bits = bits;
+        sprite
+    }
+
+    /// The bits of a bitmap sprite, row by row; empty for any other sprite.
+    pub fn bits(&self) -> &[bool] {
+        &self.bits
+    }
+
+    /// Lets the bits of a bitmap sprite be changed. It is redrawn the next time its scene is
+    /// rendered.
+    pub fn bits_mut(&mut self) -> &mut [bool] {
+        self.changed = true;
+        &mut self.bits
+    }
+
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn frame_ticks(mut self, ticks: usize) -> Self {
+        self.frame_ticks = ticks;
+        self
+    }
+
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn z(mut self, z: i32) -> Self {
+        self.z = z;
+        self
+    }
+
+    /// Returns Self for chained [Builder pattern construction](https://doc.rust-lang.org/1.0.0/style/ownership/builders.html).
+    pub fn once(mut self) -> Self {
+        self.looping = false;
+        self
+    }
+
+    /// Moves the sprite. It shows up at the new position the next time it is drawn.
+    pub fn move_to(&mut self, x: isize, y: isize) {
+        self.x = x;
+        self.y = y;
+    }
+
+    /// Counts one timer tick and switches to the next frame when the current one has been up
+    /// for `frame_ticks` ticks.
+    pub fn tick(&mut self) {
+        if self.frame_ticks == 0 || self.finished {
+            return;
+        }
+        self.ticks += 1;
+        if self.ticks >= self.frame_ticks {
+            self.ticks = 0;
+            self.next_frame();
+        }
+    }
+
+    /// Switches to the next frame. Looping sprites start over after the last one; the others
+    /// stay on it and count as finished.
+    pub fn next_frame(&mut self) {
+        let frame_count = match self.look {
+            Look::Solid { .. } | Look::Bitmap { .. } => 1,
+            Look::Frames { frames, .. } => frames.len(),
+        };
+        if self.frame + 1 < frame_count {
+            self.frame += 1;
+        } else if self.looping {
+            self.frame = 0;
+        } else {
+            self.finished = true;
+        }
+    }
+
+    /// True once a sprite built with [`once`](Self::once) has played all its frames.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Size in pixels of the current frame.
+    pub fn size(&self) -> (usize, usize) {
+        match self.look {
+            Look::Solid { width, height, .. } => (width, height),
+            Look::Bitmap {
+                width,
+                height,
+                scale,
+                ..
+            } => (width * scale, height * scale),
+            Look::Frames { frames, scale, .. } => frames.get(self.frame).map_or((0, 0), |image| {
+                (image.width() * scale, image.height() * scale)
+            }),
+        }
+    }
+
+    /// True if the sprite moved, changed frame or had its bits changed since it was last drawn,
+    /// or was never drawn.
+    pub fn needs_redraw(&self) -> bool {
+        match self.shown {
+            Some(shown) => {
+                shown.x != self.x || shown.y != self.y || shown.frame != self.frame || self.changed
+            }
+            None => true,
+        }
+    }
+
+    /// Draws the sprite at its current position and frame, erasing it from where it was before.
+    pub fn draw(&mut self, writer: &mut ScreenWriter) {
+        self.erase(writer);
+        let (width, height) = self.size();
+        let area = writer.clip(self.x, self.y, width, height);
+        let mut saved = false;
+        if let Some(area) = area {
+            let bytes = area.area() * writer.info.bytes_per_pixel;
+            self.under.clear();
+            // The buffer is reused from one draw to the next, so it only grows the first time
+            if self.under.try_reserve_exact(bytes).is_ok() {
+                writer.save_rect(area, &mut self.under);
+                saved = true;
+            }
+        }
+        match self.look {
+            Look::Solid {
+                width,
+                height,
+                color,
+            } => writer.fill_rect_at(self.x, self.y, width, height, color),
+            Look::Frames {
+                frames,
+                scale,
+                tint,
+            } => {
+                if let Some(image) = frames.get(self.frame) {
+                    writer.draw_image_tinted(image, self.x, self.y, scale, tint);
+                }
+            }
+            Look::Bitmap {
+                width,
+                scale,
+                color,
+                ..
+            } => {
+                // one rectangle for each run of set bits in a row
+                for (row, bits) in self.bits.chunks_exact(width.max(1)).enumerate() {
+                    let mut column = 0;
+                    while column < bits.len() {
+                        let run = bits[column..].iter().take_while(|&&bit| bit).count();
+                        if run > 0 {
+                            writer.fill_rect_at(
+                                self.x + (column * scale) as isize,
+                                self.y + (row * scale) as isize,
+                                run * scale,
+                                scale,
+                                color,
+                            );
+                        }
+                        column += run + 1;
+                    }
+                }
+            }
+        }
+        self.changed = false;
+        self.shown = Some(Shown {
+            x: self.x,
+            y: self.y,
+            frame: self.frame,
+            area,
+            saved,
+        });
+    }
+
+    /// Takes the sprite off the screen, putting back what was under it.
+    pub fn erase(&mut self, writer: &mut ScreenWriter) {
+        let Some(shown) = self.shown.take() else {
+            return;
+        };
+        if let Some(area) = shown.area {
+            if shown.saved {
+                writer.restore_rect(area, &self.under);
+            } else {
+                writer.fill_rect(area.x, area.y, area.width, area.height, (0, 0, 0));
+            }
+        }
+    }
+
+    /// Marks the sprite as not on screen without touching the screen, for when whatever was
+    /// under it has been painted over anyway.
+    pub fn forget(&mut self) {
+        self.shown = None;
+    }
+
+    /// The part of the screen the sprite covers right now, if it is drawn.
+    fn shown_area(&self) -> Option<Rect> {
+        self.shown.and_then(|shown| shown.area)
+    }
+}
+
+/// The sprites making up one frame. A scene is built on the stack each time the screen is
+/// brought up to date, so it doesn't hold on to any heap memory.
+pub struct Scene<'a> {
+    sprites: [Option<&'a mut Sprite>; MAX_SPRITES],
+    len: usize,
+}
+
+impl<'a> Scene<'a> {
+    pub fn new() -> Self {
+        Scene {
+            sprites: core::array::from_fn(|_| None),
+            len: 0,
+        }
+    }
+
+    /// Adds a sprite to the scene. Sprites past [`MAX_SPRITES`] are left out.
+    pub fn add(&mut self, sprite: &'a mut Sprite) {
+        if self.len < MAX_SPRITES {
+            self.sprites[self.len] = Some(sprite);
+            self.len += 1;
+        }
+    }
+
+    /// Redraws the sprites that moved or changed frame, along with every sprite overlapping
+    /// them. Those are erased from the highest z down, so each one restores what was under it,
+    /// and then drawn from the lowest z up, so higher z ends up on top. Sprites that didn't
+    /// change and don't overlap a change are left alone.
+    pub fn render(&mut self, writer: &mut ScreenWriter) {
+        let count = self.len;
+        let mut old_area = [None; MAX_SPRITES];
+        let mut new_area = [None; MAX_SPRITES];
+        let mut redraw = [false; MAX_SPRITES];
+        for (i, sprite) in self.sprites[..count].iter().enumerate() {
+            if let Some(sprite) = sprite {
+                let (width, height) = sprite.size();
+                old_area[i] = sprite.shown_area();
+                new_area[i] = writer.clip(sprite.x, sprite.y, width, height);
+                redraw[i] = sprite.needs_redraw();
+            }
+        }
+
+        // Anything under or over a sprite being redrawn has to be redrawn as well, which can in
+        // turn pull in its own neighbours
+        loop {
+            let mut grew = false;
+            for i in 0..count {
+                let Some(area) = old_area[i] else {
+                    continue;
+                };
+                if redraw[i] {
+                    continue;
+                }
+                let overlaps = (0..count).any(|j| {
+                    redraw[j]
+                        && [old_area[j], new_area[j]]
+                            .iter()
+                            .flatten()
+                            .any(|other| other.intersects(&area))
+                });
+                if overlaps {
+                    redraw[i] = true;
+                    grew = true;
+                }
+            }
+            if !grew {
+                break;
+            }
+        }
+
+        let mut order = [0; MAX_SPRITES];
+        let mut order_len = 0;
+        for i in (0..count).filter(|&i| redraw[i]) {
+            order[order_len] = i;
+            order_len += 1;
+        }
+        let order = &mut order[..order_len];
+        let sprites = &mut self.sprites;
+        order.sort_unstable_by_key(|&i| (sprites[i].as_ref().map_or(0, |sprite| sprite.z), i));
+
+        for &i in order.iter().rev() {
+            if let Some(sprite) = sprites[i].as_mut() {
+                sprite.erase(writer);
+            }
+        }
+        for &i in order.iter() {
+            if let Some(sprite) = sprites[i].as_mut() {
+                sprite.draw(writer);
+            }
+        }
+    }
+}
+
+impl Default for Scene<'_> {
+    fn default() -> Self {
+        Scene::new()
+    }
+}
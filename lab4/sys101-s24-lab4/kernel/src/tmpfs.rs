@@ -0,0 +1,184 @@
+//! A filesystem kept entirely on the heap, lost when the machine stops.
+//!
+//! File contents can also borrow memory that lives forever, like the initrd's, so unpacking an
+//! archive into a [`TmpFs`] copies nothing until a file is written.
+
+use crate::vfs::{DirEntry, FileKind, FileSystem, Inode, Metadata, VfsError};
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+enum Node {
+    File(Cow<'static, [u8]>),
+    /// Names and inodes of the entries, in the order they were made.
+    Directory(Vec<(String, Inode)>),
+}
+
+pub struct TmpFs {
+    /// Indexed by inode; removed nodes leave `None` behind, so an inode is never reused.
+    nodes: Vec<Option<Node>>,
+}
+
+const ROOT: Inode = 0;
+
+/// Largest a file can grow, so a write far past the end of one can't eat the whole heap.
+pub const MAX_FILE_SIZE: usize = 4 * 1024 * 1024;
+
+impl TmpFs {
+    /// An empty filesystem: just the root directory.
+    pub fn new() -> Self {
+        TmpFs {
+            nodes: alloc::vec![Some(Node::Directory(Vec::new()))],
+        }
+    }
+
+    fn node(&mut self, inode: Inode) -> Result<&mut Node, VfsError> {
+        self.nodes
+            .get_mut(inode)
+            .and_then(Option::as_mut)
+            .ok_or(VfsError::NotFound)
+    }
+
+    fn directory(&mut self, inode: Inode) -> Result<&mut Vec<(String, Inode)>, VfsError> {
+        match self.node(inode)? {
+            Node::Directory(entries) => Ok(entries),
+            Node::File(_) => Err(VfsError::NotADirectory),
+        }
+    }
+
+    fn file(&mut self, inode: Inode) -> Result<&mut Cow<'static, [u8]>, VfsError> {
+        match self.node(inode)? {
+            Node::File(data) => Ok(data),
+            Node::Directory(_) => Err(VfsError::IsADirectory),
+        }
+    }
+
+    /// Grows or shrinks `contents` to `len` bytes, padding with zeroes, without letting it get
+    /// past [`MAX_FILE_SIZE`] or panicking when the heap is full.
+    fn resize(contents: &mut Vec<u8>, len: usize) -> Result<(), VfsError> {
+        if len > MAX_FILE_SIZE {
+            return Err(VfsError::NoSpace);
+        }
+        if let Some(extra) = len.checked_sub(contents.len()) {
+            contents.try_reserve(extra).map_err(|_| VfsError::NoSpace)?;
+        }
+        contents.resize(len, 0);
+        Ok(())
+    }
+
+    /// Makes the file `inode` hold `data` without copying it.
+    pub(crate) fn set_contents(
+        &mut self,
+        inode: Inode,
+        data: &'static [u8],
+    ) -> Result<(), VfsError> {
+        *self.file(inode)? = Cow::Borrowed(data);
+        Ok(())
+    }
+}
+
+impl Default for TmpFs {
+    fn default() -> Self {
+        TmpFs::new()
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn root(&self) -> Inode {
+        ROOT
+    }
+
+    fn lookup(&mut self, dir: Inode, name: &str) -> Result<Inode, VfsError> {
+        self.directory(dir)?
+            .iter()
+            .find(|(entry, _)| entry == name)
+            .map(|&(_, inode)| inode)
+            .ok_or(VfsError::NotFound)
+    }
+
+    fn metadata(&mut self, inode: Inode) -> Result<Metadata, VfsError> {
+        Ok(match self.node(inode)? {
+            Node::File(data) => Metadata {
+                kind: FileKind::File,
+                size: data.len() as u64,
+            },
+            Node::Directory(_) => Metadata {
+                kind: FileKind::Directory,
+                size: 0,
+            },
+        })
+    }
+
+    fn read_dir(&mut self, dir: Inode) -> Result<Vec<DirEntry>, VfsError> {
+        let entries = self.directory(dir)?.clone();
+        entries
+            .into_iter()
+            .map(|(name, inode)| {
+                Ok(DirEntry {
+                    name,
+                    kind: self.metadata(inode)?.kind,
+                    inode,
+                })
+            })
+            .collect()
+    }
+
+    fn read_at(&mut self, inode: Inode, offset: u64, buffer: &mut [u8]) -> Result<usize, VfsError> {
+        let data = self.file(inode)?;
+        let start = (offset as usize).min(data.len());
+        let count = buffer.len().min(data.len() - start);
+        buffer[..count].copy_from_slice(&data[start..start + count]);
+        Ok(count)
+    }
+
+    fn write_at(&mut self, inode: Inode, offset: u64, data: &[u8]) -> Result<usize, VfsError> {
+        let start = usize::try_from(offset).map_err(|_| VfsError::NoSpace)?;
+        let end = start.checked_add(data.len()).ok_or(VfsError::NoSpace)?;
+        if end > MAX_FILE_SIZE {
+            return Err(VfsError::NoSpace);
+        }
+        let contents = self.file(inode)?.to_mut();
+        if contents.len() < end {
+            TmpFs::resize(contents, end)?;
+        }
+        contents[start..end].copy_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn set_len(&mut self, inode: Inode, len: u64) -> Result<(), VfsError> {
+        let contents = self.file(inode)?;
+        if len == 0 {
+            // no need to copy borrowed contents only to throw them away
+            *contents = Cow::Owned(Vec::new());
+        } else {
+            let len = usize::try_from(len).map_err(|_| VfsError::NoSpace)?;
+            TmpFs::resize(contents.to_mut(), len)?;
+        }
+        Ok(())
+    }
+
+    fn create(&mut self, dir: Inode, name: &str, kind: FileKind) -> Result<Inode, VfsError> {
+        if self.lookup(dir, name).is_ok() {
+            return Err(VfsError::AlreadyExists);
+        }
+        let inode = self.nodes.len();
+        self.directory(dir)?.push((name.into(), inode));
+        self.nodes.push(Some(match kind {
+            FileKind::File => Node::File(Cow::Owned(Vec::new())),
+            FileKind::Directory => Node::Directory(Vec::new()),
+        }));
+        Ok(inode)
+    }
+
+    fn remove(&mut self, dir: Inode, name: &str) -> Result<(), VfsError> {
+        let inode = self.lookup(dir, name)?;
+        if let Node::Directory(entries) = self.node(inode)? {
+            if !entries.is_empty() {
+                return Err(VfsError::DirectoryNotEmpty);
+            }
+        }
+        self.directory(dir)?.retain(|&(_, entry)| entry != inode);
+        self.nodes[inode] = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,435 @@
+//! The virtual filesystem: one tree of absolute paths made of filesystems mounted at different
+//! places, and a table of open files, each known by its descriptor ([`Fd`]).
+//!
+//! Filesystems implement [`FileSystem`], which works with inode numbers rather than paths; this
+//! module turns paths into inodes, picking the filesystem mounted deepest along the path.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+pub use crate::fat32::SeekFrom;
+
+/// Most files open at once.
+pub const MAX_OPEN_FILES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VfsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    DirectoryNotEmpty,
+    /// The filesystem is mounted read-only, or can't be written at all.
+    ReadOnly,
+    /// The path isn't absolute, or is `/` where a name to create or remove is needed.
+    InvalidPath,
+    /// The descriptor isn't open, or wasn't opened for this (e.g. writing to a file opened to
+    /// read).
+    BadDescriptor,
+    TooManyOpenFiles,
+    /// The file would grow past what the filesystem allows, or there is no room for it.
+    NoSpace,
+    /// The filesystem's storage failed, or holds something that makes no sense.
+    Io,
+}
+
+/// Number of a file or directory within its filesystem.
+pub type Inode = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Directory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub kind: FileKind,
+    /// Length in bytes; 0 for directories.
+    pub size: u64,
+}
+
+/// One entry of a directory, as listed by [`read_dir`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: FileKind,
+    pub inode: Inode,
+}
+
+/// What a file is opened for, as in `std::fs::OpenOptions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OpenFlags {
+    pub read: bool,
+    pub write: bool,
+    /// Create the file if it doesn't exist.
+    pub create: bool,
+    /// Empty the file when it's opened.
+    pub truncate: bool,
+    /// Every write goes to the end of the file.
+    pub append: bool,
+}
+
+impl OpenFlags {
+    pub const READ: OpenFlags = OpenFlags {
+        read: true,
+        write: false,
+        create: false,
+        truncate: false,
+        append: false,
+    };
+    /// Opens for writing, creating the file or emptying it if it's there.
+    pub const CREATE: OpenFlags = OpenFlags {
+        read: false,
+        write: true,
+        create: true,
+        truncate: true,
+        append: false,
+    };
+    pub const APPEND: OpenFlags = OpenFlags {
+        read: false,
+        write: true,
+        create: true,
+        truncate: false,
+        append: true,
+    };
+}
+
+/// A filesystem that can be mounted into the tree. Names given to it never contain `/` and are
+/// never `.` or `..`; the VFS deals with those.
+///
+/// Only reading is required: filesystems that can't be written leave the other methods as they
+/// are, failing with [`VfsError::ReadOnly`].
+pub trait FileSystem: Send {
+    /// The inode of the filesystem's top directory.
+    fn root(&self) -> Inode;
+
+    /// Finds `name` in the directory `dir`.
+    fn lookup(&mut self, dir: Inode, name: &str) -> Result<Inode, VfsError>;
+
+    fn metadata(&mut self, inode: Inode) -> Result<Metadata, VfsError>;
+
+    fn read_dir(&mut self, dir: Inode) -> Result<Vec<DirEntry>, VfsError>;
+
+    /// Reads from the file `inode` starting at `offset`, returning how many bytes were read: 0
+    /// at or past the end.
+    fn read_at(&mut self, inode: Inode, offset: u64, buffer: &mut [u8]) -> Result<usize, VfsError>;
+
+    /// Writes `data` to the file `inode` at `offset`, growing it if needed. A write past the end
+    /// fills the gap with zeroes.
+    fn write_at(&mut self, _inode: Inode, _offset: u64, _data: &[u8]) -> Result<usize, VfsError> {
+        Err(VfsError::ReadOnly)
+    }
+
+    /// Cuts the file `inode` down to, or grows it with zeroes up to, `len` bytes.
+    fn set_len(&mut self, _inode: Inode, _len: u64) -> Result<(), VfsError> {
+        Err(VfsError::ReadOnly)
+    }
+
+    /// Makes an empty file or directory called `name` in `dir`.
+    fn create(&mut self, _dir: Inode, _name: &str, _kind: FileKind) -> Result<Inode, VfsError> {
+        Err(VfsError::ReadOnly)
+    }
+
+    /// Removes `name` from `dir`. Directories have to be empty.
+    fn remove(&mut self, _dir: Inode, _name: &str) -> Result<(), VfsError> {
+        Err(VfsError::ReadOnly)
+    }
+}
+
+/// An open file's descriptor, handed out by [`open`] and given back with [`close`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Fd(pub usize);
+
+struct Mount {
+    /// Where the filesystem is mounted, as path components; empty for `/`.
+    path: Vec<String>,
+    fs: Box<dyn FileSystem>,
+    read_only: bool,
+}
+
+struct OpenFile {
+    mount: usize,
+    inode: Inode,
+    position: u64,
+    flags: OpenFlags,
+}
+
+struct Vfs {
+    mounts: Vec<Mount>,
+    /// Indexed by descriptor; closed descriptors are `None` and are reused, lowest first.
+    files: Vec<Option<OpenFile>>,
+}
+
+static VFS: Mutex<Vfs> = Mutex::new(Vfs {
+    mounts: Vec::new(),
+    files: Vec::new(),
+});
+
+/// Splits an absolute path into its names, dropping empty ones and `.`, and going up a level
+/// for each `..`.
+fn components(path: &str) -> Result<Vec<&str>, VfsError> {
+    if !path.starts_with('/') {
+        return Err(VfsError::InvalidPath);
+    }
+    let mut names = Vec::new();
+    for name in path.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => {
+                names.pop();
+            }
+            name => names.push(name),
+        }
+    }
+    Ok(names)
+}
+
+impl Vfs {
+    /// Finds the filesystem mounted deepest along `names` and the inode they lead to on it.
+    fn resolve(&mut self, names: &[&str]) -> Result<(usize, Inode), VfsError> {
+        let (index, mount) = self
+            .mounts
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, mount)| {
+                mount.path.len() <= names.len() && mount.path.iter().zip(names).all(|(a, b)| a == b)
+            })
+            .max_by_key(|(_, mount)| mount.path.len())
+            .ok_or(VfsError::NotFound)?;
+        let mut inode = mount.fs.root();
+        for name in &names[mount.path.len()..] {
+            if mount.fs.metadata(inode)?.kind != FileKind::Directory {
+                return Err(VfsError::NotADirectory);
+            }
+            inode = mount.fs.lookup(inode, name)?;
+        }
+        Ok((index, inode))
+    }
+
+    /// Resolves all but the last name of `path`, which has to be a directory, for creating or
+    /// removing the last one in it.
+    fn resolve_parent<'a>(&mut self, path: &'a str) -> Result<(usize, Inode, &'a str), VfsError> {
+        let names = components(path)?;
+        let (&name, parent) = names.split_last().ok_or(VfsError::InvalidPath)?;
+        let (mount, dir) = self.resolve(parent)?;
+        if self.mounts[mount].fs.metadata(dir)?.kind != FileKind::Directory {
+            return Err(VfsError::NotADirectory);
+        }
+        Ok((mount, dir, name))
+    }
+
+    fn writable(&mut self, mount: usize) -> Result<&mut dyn FileSystem, VfsError> {
+        let mount = &mut self.mounts[mount];
+        if mount.read_only {
+            return Err(VfsError::ReadOnly);
+        }
+        Ok(mount.fs.as_mut())
+    }
+
+    fn file(&mut self, fd: Fd) -> Result<&mut OpenFile, VfsError> {
+        self.files
+            .get_mut(fd.0)
+            .and_then(Option::as_mut)
+            .ok_or(VfsError::BadDescriptor)
+    }
+}
+
+fn add_mount(path: &str, fs: Box<dyn FileSystem>, read_only: bool) -> Result<(), VfsError> {
+    let path: Vec<String> = components(path)?.into_iter().map(String::from).collect();
+    let mut vfs = VFS.lock();
+    if vfs.mounts.iter().any(|mount| mount.path == path) {
+        return Err(VfsError::AlreadyExists);
+    }
+    vfs.mounts.push(Mount {
+        path,
+        fs,
+        read_only,
+    });
+    Ok(())
+}
+
+/// Mounts `fs` at `path`, hiding whatever was there before. The first filesystem has to go at
+/// `/`; others can go anywhere below it.
+pub fn mount(path: &str, fs: Box<dyn FileSystem>) -> Result<(), VfsError> {
+    add_mount(path, fs, false)
+}
+
+/// Like [`mount`], but nothing on the filesystem can be changed through the VFS.
+pub fn mount_read_only(path: &str, fs: Box<dyn FileSystem>) -> Result<(), VfsError> {
+    add_mount(path, fs, true)
+}
+
+/// Opens the file at `path`, returning its descriptor, positioned at the start.
+pub fn open(path: &str, flags: OpenFlags) -> Result<Fd, VfsError> {
+    let mut vfs = VFS.lock();
+    let (mount, dir, name) = vfs.resolve_parent(path)?;
+    let inode = match vfs.mounts[mount].fs.lookup(dir, name) {
+        Ok(inode) => {
+            if vfs.mounts[mount].fs.metadata(inode)?.kind == FileKind::Directory {
+                return Err(VfsError::IsADirectory);
+            }
+            if flags.write && flags.truncate {
+                vfs.writable(mount)?.set_len(inode, 0)?;
+            } else if flags.write {
+                vfs.writable(mount)?;
+            }
+            inode
+        }
+        Err(VfsError::NotFound) if flags.create => {
+            vfs.writable(mount)?.create(dir, name, FileKind::File)?
+        }
+        Err(error) => return Err(error),
+    };
+
+    let file = OpenFile {
+        mount,
+        inode,
+        position: 0,
+        flags,
+    };
+    let fd = match vfs.files.iter().position(Option::is_none) {
+        Some(free) => free,
+        None if vfs.files.len() < MAX_OPEN_FILES => {
+            vfs.files.push(None);
+            vfs.files.len() - 1
+        }
+        None => return Err(VfsError::TooManyOpenFiles),
+    };
+    vfs.files[fd] = Some(file);
+    Ok(Fd(fd))
+}
+
+pub fn close(fd: Fd) -> Result<(), VfsError> {
+    let mut vfs = VFS.lock();
+    vfs.file(fd)?;
+    vfs.files[fd.0] = None;
+    Ok(())
+}
+
+/// Reads from the file's position into `buffer`, moving the position past what was read.
+/// Returns how many bytes that was: 0 at the end of the file.
+pub fn read(fd: Fd, buffer: &mut [u8]) -> Result<usize, VfsError> {
+    let mut vfs = VFS.lock();
+    let &mut OpenFile {
+        mount,
+        inode,
+        position,
+        flags,
+    } = vfs.file(fd)?;
+    if !flags.read {
+        return Err(VfsError::BadDescriptor);
+    }
+    let count = vfs.mounts[mount].fs.read_at(inode, position, buffer)?;
+    vfs.file(fd)?.position += count as u64;
+    Ok(count)
+}
+
+/// Writes `data` at the file's position, or at its end if it was opened to append, moving the
+/// position past it.
+pub fn write(fd: Fd, data: &[u8]) -> Result<usize, VfsError> {
+    let mut vfs = VFS.lock();
+    let &mut OpenFile {
+        mount,
+        inode,
+        mut position,
+        flags,
+    } = vfs.file(fd)?;
+    if !flags.write {
+        return Err(VfsError::BadDescriptor);
+    }
+    let fs = vfs.writable(mount)?;
+    if flags.append {
+        position = fs.metadata(inode)?.size;
+    }
+    let count = fs.write_at(inode, position, data)?;
+    vfs.file(fd)?.position = position + count as u64;
+    Ok(count)
+}
+
+/// Moves the file's position, returning the new one; seeking before the start stops there. It
+/// can go past the end: reading there finds nothing, and writing there fills the gap with zeroes.
+pub fn seek(fd: Fd, to: SeekFrom) -> Result<u64, VfsError> {
+    let mut vfs = VFS.lock();
+    let &mut OpenFile {
+        mount,
+        inode,
+        position,
+        ..
+    } = vfs.file(fd)?;
+    let size = vfs.mounts[mount].fs.metadata(inode)?.size;
+    let new_position = match to {
+        SeekFrom::Start(offset) => offset,
+        SeekFrom::End(offset) => size.saturating_add_signed(offset),
+        SeekFrom::Current(offset) => position.saturating_add_signed(offset),
+    };
+    vfs.file(fd)?.position = new_position;
+    Ok(new_position)
+}
+
+pub fn fstat(fd: Fd) -> Result<Metadata, VfsError> {
+    let mut vfs = VFS.lock();
+    let &mut OpenFile { mount, inode, .. } = vfs.file(fd)?;
+    vfs.mounts[mount].fs.metadata(inode)
+}
+
+pub fn stat(path: &str) -> Result<Metadata, VfsError> {
+    let mut vfs = VFS.lock();
+    let (mount, inode) = vfs.resolve(&components(path)?)?;
+    vfs.mounts[mount].fs.metadata(inode)
+}
+
+/// Lists the directory at `path`, without `.` and `..`.
+pub fn read_dir(path: &str) -> Result<Vec<DirEntry>, VfsError> {
+    let mut vfs = VFS.lock();
+    let (mount, inode) = vfs.resolve(&components(path)?)?;
+    vfs.mounts[mount].fs.read_dir(inode)
+}
+
+pub fn create_dir(path: &str) -> Result<(), VfsError> {
+    let mut vfs = VFS.lock();
+    let (mount, dir, name) = vfs.resolve_parent(path)?;
+    match vfs.mounts[mount].fs.lookup(dir, name) {
+        Ok(_) => Err(VfsError::AlreadyExists),
+        Err(VfsError::NotFound) => {
+            vfs.writable(mount)?
+                .create(dir, name, FileKind::Directory)?;
+            Ok(())
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/// Removes the file, or empty directory, at `path`. Descriptors still open on a removed file
+/// fail from then on.
+pub fn remove(path: &str) -> Result<(), VfsError> {
+    let mut vfs = VFS.lock();
+    let (mount, dir, name) = vfs.resolve_parent(path)?;
+    vfs.writable(mount)?.remove(dir, name)
+}
+
+/// Reads the whole file at `path`.
+pub fn read_file(path: &str) -> Result<Vec<u8>, VfsError> {
+    let fd = open(path, OpenFlags::READ)?;
+    let mut data = Vec::new();
+    let mut chunk = [0; 512];
+    let result = loop {
+        match read(fd, &mut chunk) {
+            Ok(0) => break Ok(data),
+            Ok(count) => data.extend_from_slice(&chunk[..count]),
+            Err(error) => break Err(error),
+        }
+    };
+    close(fd)?;
+    result
+}
+
+/// Replaces the file at `path` with `data`, creating it if needed.
+pub fn write_file(path: &str, data: &[u8]) -> Result<(), VfsError> {
+    let fd = open(path, OpenFlags::CREATE)?;
+    let result = write(fd, data);
+    close(fd)?;
+    result.map(|_| ())
+}
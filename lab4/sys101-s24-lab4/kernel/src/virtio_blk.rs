@@ -0,0 +1,382 @@
+//! virtio-blk disks, `-drive if=virtio` in QEMU, through the legacy PCI interface: registers in
+//! an I/O BAR, and one virtqueue in memory shared with the device, holding the requests. The
+//! device interrupts when it has finished one; while interrupts are enabled the driver sleeps
+//! until then, otherwise, or if the device's line has no handler, it polls the queue.
+
+use crate::block::{BlockDevice, BlockError};
+use crate::frames::{self, DmaBuffer};
+use crate::pci::{self, Bar, PciDevice};
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, AtomicU16, AtomicU8, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::structures::port::{PortRead, PortWrite};
+
+pub const VENDOR_ID: u16 = 0x1AF4;
+/// The transitional block device, which still has the legacy interface. Modern-only ones are
+/// 0x1042.
+pub const DEVICE_ID: u16 = 0x1001;
+
+// Legacy registers, as offsets into BAR0
+const DEVICE_FEATURES: u16 = 0x00;
+const DRIVER_FEATURES: u16 = 0x04;
+/// Physical frame number of the virtqueue.
+const QUEUE_ADDRESS: u16 = 0x08;
+const QUEUE_SIZE: u16 = 0x0C;
+const QUEUE_SELECT: u16 = 0x0E;
+const QUEUE_NOTIFY: u16 = 0x10;
+const DEVICE_STATUS: u16 = 0x12;
+/// Reading acknowledges the interrupt.
+const ISR_STATUS: u16 = 0x13;
+/// The block device's configuration, starting with its capacity in sectors, when MSI-X is off.
+const CAPACITY: u16 = 0x14;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FAILED: u8 = 128;
+
+const FEATURE_READ_ONLY: u32 = 1 << 5;
+const FEATURE_FLUSH: u32 = 1 << 9;
+
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+const REQUEST_FLUSH: u32 = 4;
+const REQUEST_OK: u8 = 0;
+const REQUEST_UNSUPPORTED: u8 = 2;
+
+const DESCRIPTOR_NEXT: u16 = 1;
+/// The device writes to this buffer rather than reading it.
+const DESCRIPTOR_WRITE: u16 = 2;
+
+const QUEUE_ALIGN: usize = 4096;
+pub const SECTOR_SIZE: usize = 512;
+/// Sectors moved by one request, through the bounce buffer.
+const SECTORS_PER_REQUEST: usize = 128;
+const BOUNCE_SIZE: usize = SECTORS_PER_REQUEST * SECTOR_SIZE;
+/// Where the request header and status byte go in their frame.
+const HEADER_OFFSET: usize = 0;
+const STATUS_OFFSET: usize = 16;
+
+const POLL_TIMEOUT: usize = 10_000_000;
+/// Wake-ups from `hlt` to wait for a request, at least one per timer tick.
+const IRQ_TIMEOUT: usize = 200;
+
+#[repr(C)]
+struct Descriptor {
+    address: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct RequestHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// The device's IRQ line and ISR port, for the interrupt handler, which can't wait for a lock.
+/// `NO_IRQ` until a disk is open.
+static IRQ_LINE: AtomicU8 = AtomicU8::new(NO_IRQ);
+static ISR_PORT: AtomicU16 = AtomicU16::new(0);
+const NO_IRQ: u8 = 0xFF;
+
+/// A virtio-blk disk found by [`VirtioBlk::open`].
+pub struct VirtioBlk {
+    device: PciDevice,
+    io_base: u16,
+    features: u32,
+    sectors: u64,
+    queue_size: u16,
+    /// Descriptor table, then the ring of requests made available to the device, then (at the
+    /// next page) the ring of requests it has used.
+    queue: DmaBuffer,
+    used_offset: usize,
+    /// Index the next request goes at in the available ring.
+    next_available: u16,
+    /// How far the used ring has been read.
+    last_used: u16,
+    /// The request header and the status byte the device writes back.
+    request: DmaBuffer,
+    /// Data is copied through here, so the caller's buffers can be anywhere.
+    bounce: DmaBuffer,
+    /// Set once a request timed out. The device has been reset then, so it no longer touches
+    /// the queue or buffers, and every later request fails.
+    broken: bool,
+    /// Whether the device's interrupts get through. Only the lines the PCI handlers cover are
+    /// unmasked; on any other the driver always polls.
+    uses_irq: bool,
+}
+
+impl VirtioBlk {
+    /// Sets up the first virtio-blk device [`pci::scan`] found. Returns `None` if there isn't
+    /// one, it has no legacy interface, or there is no memory for its queue.
+    pub fn open() -> Option<Self> {
+        let device = pci::find(VENDOR_ID, DEVICE_ID)?;
+        let Some(Bar::Io { port: io_base, .. }) = device.bars[0] else {
+            return None;
+        };
+        device.enable_bus_master();
+
+        write::<u8>(io_base, DEVICE_STATUS, 0);
+        write(io_base, DEVICE_STATUS, STATUS_ACKNOWLEDGE);
+        write(io_base, DEVICE_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        let offered = read::<u32>(io_base, DEVICE_FEATURES);
+        let features = offered & (FEATURE_READ_ONLY | FEATURE_FLUSH);
+        write(io_base, DRIVER_FEATURES, features);
+        let sectors = read::<u32>(io_base, CAPACITY) as u64
+            | (read::<u32>(io_base, CAPACITY + 4) as u64) << 32;
+
+        write::<u16>(io_base, QUEUE_SELECT, 0);
+        let queue_size = read::<u16>(io_base, QUEUE_SIZE);
+        if queue_size == 0 {
+            write(io_base, DEVICE_STATUS, STATUS_FAILED);
+            return None;
+        }
+        let size = queue_size as usize;
+        let used_offset = (size * 16 + 6 + size * 2).next_multiple_of(QUEUE_ALIGN);
+        let queue_bytes = used_offset + (6 + size * 8).next_multiple_of(QUEUE_ALIGN);
+        let buffers = (
+            frames::allocate(queue_bytes),
+            frames::allocate(STATUS_OFFSET + 1),
+            frames::allocate(BOUNCE_SIZE),
+        );
+        let (Some(queue), Some(request), Some(bounce)) = buffers else {
+            write(io_base, DEVICE_STATUS, STATUS_FAILED);
+            return None;
+        };
+        let frame_number = queue.physical_address() / frames::FRAME_SIZE;
+        write(io_base, QUEUE_ADDRESS, frame_number as u32);
+
+        let uses_irq = crate::interrupts::PCI_IRQS.contains(&device.interrupt_line);
+        if uses_irq {
+            ISR_PORT.store(io_base + ISR_STATUS, Ordering::SeqCst);
+            IRQ_LINE.store(device.interrupt_line, Ordering::SeqCst);
+            crate::interrupts::unmask_irq(device.interrupt_line);
+        }
+        write(
+            io_base,
+            DEVICE_STATUS,
+            STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK,
+        );
+        Some(VirtioBlk {
+            device,
+            io_base,
+            features,
+            sectors,
+            queue_size,
+            queue,
+            used_offset,
+            next_available: 0,
+            last_used: 0,
+            request,
+            bounce,
+            broken: false,
+            uses_irq,
+        })
+    }
+
+    /// The PCI function the disk is on.
+    pub fn device(&self) -> &PciDevice {
+        &self.device
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.features & FEATURE_READ_ONLY != 0
+    }
+
+    fn descriptor(&mut self, index: u16) -> *mut Descriptor {
+        unsafe { (self.queue.as_mut_ptr() as *mut Descriptor).add(index as usize) }
+    }
+
+    /// The `idx` field of the used ring, which the device bumps for each request it finishes.
+    fn used_index(&mut self) -> u16 {
+        let used = unsafe { self.queue.as_mut_ptr().add(self.used_offset) };
+        unsafe { read_volatile(used.add(2) as *const u16) }
+    }
+
+    /// Runs one request of `len` bytes of the bounce buffer, or none for a flush, and waits for
+    /// the device to finish it. Only one request is ever in flight, so it always uses the first
+    /// three descriptors.
+    fn request(&mut self, kind: u32, sector: u64, len: usize) -> Result<(), BlockError> {
+        if self.broken {
+            return Err(BlockError::Timeout);
+        }
+        let request = self.request.physical_address();
+        let bounce = self.bounce.physical_address();
+        unsafe {
+            let base = self.request.as_mut_ptr();
+            write_volatile(
+                base.add(HEADER_OFFSET) as *mut RequestHeader,
+                RequestHeader {
+                    kind,
+                    reserved: 0,
+                    sector,
+                },
+            );
+            write_volatile(base.add(STATUS_OFFSET), 0xFF);
+
+            let status_index = if len > 0 { 2 } else { 1 };
+            write_volatile(
+                self.descriptor(0),
+                Descriptor {
+                    address: request + HEADER_OFFSET as u64,
+                    len: size_of::<RequestHeader>() as u32,
+                    flags: DESCRIPTOR_NEXT,
+                    next: 1,
+                },
+            );
+            if len > 0 {
+                let write = if kind == REQUEST_IN {
+                    DESCRIPTOR_WRITE
+                } else {
+                    0
+                };
+                write_volatile(
+                    self.descriptor(1),
+                    Descriptor {
+                        address: bounce,
+                        len: len as u32,
+                        flags: DESCRIPTOR_NEXT | write,
+                        next: 2,
+                    },
+                );
+            }
+            write_volatile(
+                self.descriptor(status_index),
+                Descriptor {
+                    address: request + STATUS_OFFSET as u64,
+                    len: 1,
+                    flags: DESCRIPTOR_WRITE,
+                    next: 0,
+                },
+            );
+
+            // Put descriptor 0 in the available ring, then tell the device the ring grew
+            let size = self.queue_size as usize;
+            let available = self.queue.as_mut_ptr().add(size * 16) as *mut u16;
+            let slot = (self.next_available % self.queue_size) as usize;
+            write_volatile(available.add(2 + slot), 0);
+            fence(Ordering::SeqCst);
+            self.next_available = self.next_available.wrapping_add(1);
+            write_volatile(available.add(1), self.next_available);
+            fence(Ordering::SeqCst);
+        }
+        write::<u16>(self.io_base, QUEUE_NOTIFY, 0);
+
+        if let Err(error) = self.wait() {
+            self.give_up();
+            return Err(error);
+        }
+        fence(Ordering::SeqCst);
+        match unsafe { read_volatile(self.request.as_mut_ptr().add(STATUS_OFFSET)) } {
+            REQUEST_OK => Ok(()),
+            error => Err(BlockError::Device(error)),
+        }
+    }
+
+    /// Stops using the device after a request timed out. That request may still be in flight, and
+    /// finishing late would let the device write into a buffer that has been reused and bump the
+    /// used ring under the next request, so the device is reset, which makes it let go of the
+    /// queue, and marked failed.
+    fn give_up(&mut self) {
+        write::<u8>(self.io_base, DEVICE_STATUS, 0);
+        write(self.io_base, DEVICE_STATUS, STATUS_FAILED);
+        self.broken = true;
+    }
+
+    /// Waits until the device has used the request just made: by sleeping between interrupts
+    /// while they are on and reach the driver, by polling otherwise.
+    fn wait(&mut self) -> Result<(), BlockError> {
+        let expected = self.last_used.wrapping_add(1);
+        if self.uses_irq && interrupts::are_enabled() {
+            let mut wakeups = 0;
+            loop {
+                // Interrupts stay off between checking the ring and halting, so the completion
+                // can't slip in between and leave us asleep until the next timer tick
+                interrupts::disable();
+                if self.used_index() == expected {
+                    interrupts::enable();
+                    break;
+                }
+                if wakeups == IRQ_TIMEOUT {
+                    interrupts::enable();
+                    return Err(BlockError::Timeout);
+                }
+                interrupts::enable_and_hlt();
+                wakeups += 1;
+            }
+        } else {
+            let mut polls = 0;
+            while self.used_index() != expected {
+                if polls == POLL_TIMEOUT {
+                    return Err(BlockError::Timeout);
+                }
+                core::hint::spin_loop();
+                polls += 1;
+            }
+            // Nothing will read the ISR for this one, so acknowledge it here
+            read::<u8>(self.io_base, ISR_STATUS);
+        }
+        self.last_used = expected;
+        Ok(())
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_sectors(&mut self, lba: u64, buffer: &mut [u8]) -> Result<(), BlockError> {
+        self.sectors_for(lba, buffer.len())?;
+        for (index, chunk) in buffer.chunks_mut(BOUNCE_SIZE).enumerate() {
+            let sector = lba + (index * SECTORS_PER_REQUEST) as u64;
+            self.request(REQUEST_IN, sector, chunk.len())?;
+            chunk.copy_from_slice(&self.bounce.as_slice()[..chunk.len()]);
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&mut self, lba: u64, buffer: &[u8]) -> Result<(), BlockError> {
+        self.sectors_for(lba, buffer.len())?;
+        if self.is_read_only() {
+            return Err(BlockError::Device(REQUEST_UNSUPPORTED));
+        }
+        for (index, chunk) in buffer.chunks(BOUNCE_SIZE).enumerate() {
+            let sector = lba + (index * SECTORS_PER_REQUEST) as u64;
+            self.bounce.as_mut_slice()[..chunk.len()].copy_from_slice(chunk);
+            self.request(REQUEST_OUT, sector, chunk.len())?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), BlockError> {
+        if self.features & FEATURE_FLUSH == 0 {
+            return Ok(());
+        }
+        self.request(REQUEST_FLUSH, 0, 0)
+    }
+}
+
+fn read<T: PortRead>(io_base: u16, register: u16) -> T {
+    unsafe { Port::new(io_base + register).read() }
+}
+
+fn write<T: PortWrite>(io_base: u16, register: u16, value: T) {
+    unsafe { Port::new(io_base + register).write(value) };
+}
+
+/// Called by the interrupt handlers of the IRQ lines PCI devices share. Reading the ISR
+/// acknowledges the interrupt; waking the CPU from `hlt` is all the rest it needs to do.
+pub(crate) fn handle_interrupt(irq: u8) {
+    if IRQ_LINE.load(Ordering::SeqCst) == irq {
+        unsafe { Port::<u8>::new(ISR_PORT.load(Ordering::SeqCst)).read() };
+    }
+}
@@ -0,0 +1,84 @@
+/// Clearing this wave wins the game.
+pub const FINAL_LEVEL: u32 = 10;
+
+/// How hard the game starts, picked on the title screen. Every level is harder than the one
+/// before whichever is picked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        }
+    }
+
+    /// The next harder one, or the easiest after the hardest.
+    pub fn next(self) -> Difficulty {
+        Difficulty::ALL[(self as usize + 1) % Difficulty::ALL.len()]
+    }
+
+    /// The next easier one, or the hardest after the easiest.
+    pub fn previous(self) -> Difficulty {
+        let count = Difficulty::ALL.len();
+        Difficulty::ALL[(self as usize + count - 1) % count]
+    }
+
+    /// The first wave's settings.
+    fn first_wave(self) -> Wave {
+        match self {
+            Difficulty::Easy => Wave {
+                move_interval: 48,
+                fire_interval: 10,
+                step: 12,
+                start_y: 50,
+            },
+            Difficulty::Normal => Wave {
+                move_interval: 40,
+                fire_interval: 6,
+                step: 15,
+                start_y: 50,
+            },
+            Difficulty::Hard => Wave {
+                move_interval: 32,
+                fire_interval: 3,
+                step: 18,
+                start_y: 80,
+            },
+        }
+    }
+}
+
+/// How the enemies of one wave behave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Wave {
+    /// Counted up by the game tick; the enemies step once it goes past this.
+    pub move_interval: u32,
+    /// Ticks between two enemy shots.
+    pub fire_interval: u32,
+    /// Pixels the enemies move sideways with each step.
+    pub step: usize,
+    /// Where the top row of enemies starts, down from the top of the screen.
+    pub start_y: usize,
+}
+
+/// The settings for wave `level`, counting from 1: each one moves and fires faster, steps
+/// further and starts lower than the one before, up to a limit.
+pub fn wave(level: u32, difficulty: Difficulty) -> Wave {
+    let first = difficulty.first_wave();
+    let harder = level.saturating_sub(1);
+    Wave {
+        move_interval: first.move_interval.saturating_sub(4 * harder).max(8),
+        fire_interval: first.fire_interval.saturating_sub(harder).max(1),
+        step: (first.step + 2 * harder as usize).min(30),
+        start_y: first.start_y + (20 * harder as usize).min(120),
+    }
+}
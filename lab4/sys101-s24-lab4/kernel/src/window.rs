@@ -0,0 +1,54 @@
+use crate::console::Console;
+use crate::screen::Rect;
+use core::fmt;
+use kernel::RacyCell;
+
+/// Most windows that can be open at the same time.
+const MAX_WINDOWS: usize = 4;
+
+const NO_WINDOW: Option<Console> = None;
+static WINDOWS: RacyCell<[Option<Console>; MAX_WINDOWS]> = RacyCell::new([NO_WINDOW; MAX_WINDOWS]);
+
+/// Names one of the text windows. Each window is an independent [Console] with its own cursor,
+/// colors and scrolling, drawn into its own rectangle of the screen. Windows should not overlap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowId(usize);
+
+impl WindowId {
+    /// The id of window number `index`. Panics if there can't be that many windows, which for
+    /// an id made in a `const` stops the build instead.
+    pub const fn new(index: usize) -> Self {
+        assert!(index < MAX_WINDOWS, "window index out of range");
+        WindowId(index)
+    }
+}
+
+/// Opens window `id` on the given part of the screen, replacing any window already open under
+/// that id, and returns it cleared. The screen has to be initialized first.
+pub fn open(id: WindowId, area: Rect) -> &'static mut Console {
+    let windows = unsafe { WINDOWS.get_mut() };
+    windows[id.0] = Some(Console::new(area));
+    windows[id.0].as_mut().unwrap()
+}
+
+/// Returns an open window. Panics if `id` has not been opened.
+pub fn window(id: WindowId) -> &'static mut Console {
+    let windows = unsafe { WINDOWS.get_mut() };
+    windows[id.0].as_mut().unwrap()
+}
+
+/// Advances the blinking cursors of all open windows. Call this from the timer handler.
+pub fn tick() {
+    for window in unsafe { WINDOWS.get_mut() }.iter_mut().flatten() {
+        window.tick();
+    }
+}
+
+/// Writes to one window with `write!`.
+pub struct Writer(pub WindowId);
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        window(self.0).write_str(s)
+    }
+}
@@ -15,6 +15,67 @@ fn main() {
         cmd.arg("-drive").arg(format!("format=raw,file={bios_path}"));
         cmd.arg("-serial").arg("stdio");
     }
+
+    // the PC speaker and Sound Blaster are silent by default, so the game boots on any host, even
+    // one without a sound system. Set QEMU_AUDIO to a QEMU audio backend to hear them, e.g.
+    // QEMU_AUDIO=pa (PulseAudio), alsa, coreaudio (macOS) or dsound (Windows), or
+    // QEMU_AUDIO=wav,path=game.wav to record the game's sound to a file
+    let audio = std::env::var("QEMU_AUDIO").unwrap_or_else(|_| "none".into());
+    cmd.arg("-audiodev").arg(format!("{audio},id=audio0"));
+    cmd.arg("-machine").arg("pcspk-audiodev=audio0");
+    cmd.arg("-device").arg("sb16,audiodev=audio0");
+
+    // set DATA_DISK to a raw image to attach it as the primary slave IDE disk, next to the boot
+    // disk; it is created, empty, if it doesn't exist yet
+    if let Ok(data_disk) = std::env::var("DATA_DISK") {
+        create_data_disk(&data_disk);
+        cmd.arg("-drive").arg(format!("format=raw,file={data_disk},if=ide,index=1"));
+    }
+
+    // the game's FAT32 disk, attached with virtio-blk; the first run copies it from the one the
+    // build script made with the sprites on it, and after that it keeps whatever the game saves.
+    // Set VIRTIO_DISK to use another image instead
+    let virtio_disk = std::env::var("VIRTIO_DISK").unwrap_or_else(|_| GAME_DISK.into());
+    if let Err(error) = create_game_disk(&virtio_disk) {
+        eprintln!("Couldn't create the game disk {virtio_disk}: {error}");
+        std::process::exit(1);
+    }
+    cmd.arg("-drive").arg(format!("format=raw,file={virtio_disk},if=virtio"));
+
+    // set GAME_SEED to a number to play the same game every time, e.g. to reproduce a bug; the
+    // kernel logs the seed it picked otherwise. It is handed over through QEMU's fw_cfg device
+    if let Ok(seed) = std::env::var("GAME_SEED") {
+        cmd.arg("-fw_cfg").arg(format!("name=opt/sys101/seed,string={seed}"));
+    }
+
     let mut child = cmd.spawn().unwrap();
     child.wait().unwrap();
-}
\ No newline at end of file
+}
+
+/// Where the game's disk is kept between runs.
+const GAME_DISK: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/target/game.img");
+
+/// Copies the build script's game disk to `path`, making its directory if need be, unless there
+/// is a disk there already.
+fn create_game_disk(path: &str) -> std::io::Result<()> {
+    let path = std::path::Path::new(path);
+    if path.exists() {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::copy(env!("FAT_PATH"), path)?;
+    Ok(())
+}
+
+/// Size of a newly created data disk.
+const DATA_DISK_SIZE: u64 = 16 * 1024 * 1024;
+
+fn create_data_disk(path: &str) {
+    if std::path::Path::new(path).exists() {
+        return;
+    }
+    let file = std::fs::File::create(path).unwrap();
+    file.set_len(DATA_DISK_SIZE).unwrap();
+}